@@ -0,0 +1,41 @@
+//! Decode arbitrary bytes as a PDU holding any value.  Decoding may fail,
+//! but must not panic, and anything that decodes must survive a round trip
+//! and be readable as events.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use serde_bser::de::{BserReader, SliceRead};
+use serde_bser::value::Value;
+
+fn read_events(data: &[u8]) -> serde_bser::Result<()> {
+    let mut reader = BserReader::new(SliceRead::new(data))?;
+    while reader.next_event()?.is_some() {}
+    Ok(())
+}
+
+/// Structural equality in which NaN equals NaN, unlike `==`.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Real(a), Value::Real(b)) => a == b || (a.is_nan() && b.is_nan()),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| same(a, b)))
+        }
+        (a, b) => a == b,
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let events = read_events(data);
+    if let Ok(value) = serde_bser::from_slice::<Value>(data) {
+        events.unwrap();
+        let pdu = serde_bser::ser::to_vec(&value).unwrap();
+        let decoded: Value = serde_bser::from_slice(&pdu).unwrap();
+        assert!(same(&decoded, &value), "{:?} != {:?}", decoded, value);
+    }
+});
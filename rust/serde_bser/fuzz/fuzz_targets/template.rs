@@ -0,0 +1,34 @@
+//! Decode arbitrary bytes as the body of a template, both as `Value`s and as
+//! rows of a struct with optional fields, which exercises `BSER_SKIP`
+//! handling.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use serde::Deserialize;
+use serde_bser::header::{BSER_INT32, BSER_TEMPLATE, BSER_V2_MAGIC};
+use serde_bser::value::Value;
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+struct File {
+    name: Option<String>,
+    size: Option<u64>,
+    exists: Option<bool>,
+    #[serde(default)]
+    symlink_target: Option<Value>,
+}
+
+fuzz_target!(|data: &[u8]| {
+    // Wrap the input in a PDU header and template tag, so the fuzzer spends
+    // its time on the template keys and rows.
+    let mut pdu = BSER_V2_MAGIC.to_vec();
+    pdu.extend_from_slice(&0u32.to_ne_bytes());
+    pdu.push(BSER_INT32);
+    pdu.extend_from_slice(&(data.len() as i32 + 1).to_ne_bytes());
+    pdu.push(BSER_TEMPLATE);
+    pdu.extend_from_slice(data);
+
+    let _ = serde_bser::from_slice::<Value>(&pdu);
+    let _ = serde_bser::from_slice::<Vec<File>>(&pdu);
+});
@@ -0,0 +1,345 @@
+//! Print an annotated tree of the BSER PDUs in a file, or in stdin if no
+//! file is specified.
+//!
+//! Each line starts with the offset of the node it describes, followed by
+//! its type tag and decoded value.  For example:
+//!
+//! ```text
+//! 0000  PDU v2, capabilities 0x0, length 11 (BSER_INT8, 1 byte)
+//! 0008  BSER_ARRAY, 2 items (BSER_INT8, 1 byte)
+//! 0011    [0] BSER_UTF8STRING, 3 bytes (BSER_INT8, 1 byte): "Tom"
+//! 0017    [1] BSER_INT8, 1 byte: 42
+//! ```
+//!
+//! Decoding errors are reported along with the offset at which they
+//! occurred:
+//!
+//! ```text
+//! error at offset 19: while deserializing BSER: invalid start byte for next item: unknown byte '14'
+//! ```
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+
+use serde_bser::bytestring::ByteString;
+use serde_bser::de::{Bunser, DeserializerOptions, PduInfo, SliceRead};
+use serde_bser::header::*;
+use serde_bser::{ErrorKind, Expected, Result};
+
+const USAGE: &str = "usage: bser-dump [FILE]";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let input = match args.as_slice() {
+        [] => read_stdin(),
+        [arg] if arg == "-h" || arg == "--help" => {
+            println!("{}", USAGE);
+            return;
+        }
+        [path] if path == "-" => read_stdin(),
+        [path] => fs::read(path),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let input = match input {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("bser-dump: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut dumper = Dumper {
+        bunser: Bunser::new(SliceRead::new(&input)),
+        out: stdout.lock(),
+        // The same limits as `from_slice`, so that deep nesting is
+        // reported as an error rather than overflowing the stack.
+        options: DeserializerOptions::default(),
+        nesting: 0,
+        end: 0,
+    };
+    // Dump every PDU in the input, so that recorded sessions can be read.
+    while (dumper.offset() as usize) < input.len() {
+        if let Err(err) = dumper.pdu() {
+            let _ = dumper.out.flush();
+            eprintln!("error at offset {}: {}", dumper.offset(), err);
+            process::exit(1);
+        }
+    }
+}
+
+fn read_stdin() -> io::Result<Vec<u8>> {
+    let mut input = vec![];
+    io::stdin().read_to_end(&mut input)?;
+    Ok(input)
+}
+
+struct Dumper<'a, W> {
+    bunser: Bunser<SliceRead<'a>>,
+    out: W,
+    options: DeserializerOptions,
+    /// The number of arrays, objects and templates being dumped.
+    nesting: usize,
+    /// The offset just past the end of the PDU being dumped.
+    end: usize,
+}
+
+impl<'a, W> Dumper<'a, W>
+where
+    W: Write,
+{
+    fn offset(&self) -> i64 {
+        self.bunser.read_count()
+    }
+
+    fn line(&mut self, offset: i64, depth: usize, text: &str) -> Result<()> {
+        writeln!(
+            self.out,
+            "{:04}  {:indent$}{}",
+            offset,
+            "",
+            text,
+            indent = depth * 2
+        )?;
+        Ok(())
+    }
+
+    fn pdu(&mut self) -> Result<()> {
+        let offset = self.offset();
+        let info = self.bunser.read_pdu()?;
+        self.options.check_pdu_len(info.body_len()?)?;
+        self.end = info.end()?;
+        let PduInfo {
+            bser_version,
+            bser_capabilities,
+            len,
+            start,
+        } = info;
+        // The length is the last thing in the header
+        let len_width = start
+            - offset
+            - if bser_version == BserVersion::V1 {
+                2
+            } else {
+                6
+            };
+        self.line(
+            offset,
+            0,
+            &format!(
+                "PDU {}, capabilities {:#x}, length {} ({})",
+                match bser_version {
+                    BserVersion::V1 => "v1",
+                    BserVersion::V2 => "v2",
+                },
+                bser_capabilities,
+                len,
+                int_desc(len_width as usize - 1),
+            ),
+        )?;
+        self.value(0, "")?;
+        self.bunser.end(&info)
+    }
+
+    /// Dump the value at the current offset, prefixing its description with
+    /// `label`.
+    fn value(&mut self, depth: usize, label: &str) -> Result<()> {
+        let tag = self.bunser.peek()?;
+        match tag {
+            BSER_ARRAY | BSER_OBJECT | BSER_TEMPLATE => {
+                self.options
+                    .check_depth(self.nesting + 1, &header_byte_desc(tag))?;
+                self.nesting += 1;
+                let result = self.container(tag, depth, label);
+                self.nesting -= 1;
+                result
+            }
+            _ => self.scalar(tag, depth, label),
+        }
+    }
+
+    fn container(&mut self, tag: u8, depth: usize, label: &str) -> Result<()> {
+        let offset = self.offset();
+        let desc = header_byte_desc(tag);
+        match tag {
+            BSER_ARRAY | BSER_OBJECT => {
+                self.bunser.discard();
+                let (nitems, width) = self.container_len()?;
+                self.line(
+                    offset,
+                    depth,
+                    &format!("{}{}, {} ({})", label, desc, count(nitems, "item"), width),
+                )?;
+                for i in 0..nitems {
+                    if tag == BSER_ARRAY {
+                        self.value(depth + 1, &format!("[{}] ", i))?;
+                    } else {
+                        let key_offset = self.offset();
+                        let key = self.string()?;
+                        self.line(key_offset, depth + 1, &format!("key {}", key))?;
+                        self.value(depth + 2, "")?;
+                    }
+                }
+            }
+            BSER_TEMPLATE => {
+                self.bunser.discard();
+                self.line(offset, depth, &format!("{}{}", label, desc))?;
+
+                let keys_offset = self.offset();
+                match self.bunser.peek()? {
+                    BSER_ARRAY => self.bunser.discard(),
+                    ch => {
+                        return Err(ErrorKind::DeInvalidStartByte(Expected::TemplateKeys, ch).into())
+                    }
+                }
+                let (nkeys, width) = self.container_len()?;
+                self.line(
+                    keys_offset,
+                    depth + 1,
+                    &format!("keys: BSER_ARRAY, {} ({})", count(nkeys, "item"), width),
+                )?;
+                let mut keys = Vec::new();
+                for _ in 0..nkeys {
+                    let key_offset = self.offset();
+                    let key = self.string()?;
+                    self.line(key_offset, depth + 2, &key)?;
+                    keys.push(key);
+                }
+
+                let rows_offset = self.offset();
+                let (nrows, width) = self.container_len()?;
+                self.bunser.check_template_rows(nkeys, nrows, self.end)?;
+                self.line(
+                    rows_offset,
+                    depth + 1,
+                    &format!("rows: {} ({})", nrows, width),
+                )?;
+                for i in 0..nrows {
+                    self.line(self.offset(), depth + 1, &format!("[{}]", i))?;
+                    for key in &keys {
+                        let slot_offset = self.offset();
+                        if self.bunser.peek()? == BSER_SKIP {
+                            self.bunser.discard();
+                            self.line(slot_offset, depth + 2, &format!("{}: BSER_SKIP", key))?;
+                        } else {
+                            self.value(depth + 2, &format!("{}: ", key))?;
+                        }
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn scalar(&mut self, tag: u8, depth: usize, label: &str) -> Result<()> {
+        let offset = self.offset();
+        let desc = header_byte_desc(tag);
+        match tag {
+            BSER_BYTESTRING | BSER_UTF8STRING => {
+                let (s, len, width) = self.string_parts()?;
+                self.line(
+                    offset,
+                    depth,
+                    &format!(
+                        "{}{}, {} ({}): {}",
+                        label,
+                        desc,
+                        count(len, "byte"),
+                        width,
+                        s
+                    ),
+                )?;
+            }
+            BSER_INT8 | BSER_INT16 | BSER_INT32 | BSER_INT64 => {
+                let width = int_width(tag);
+                let n = self.bunser.check_next_int()?;
+                self.line(
+                    offset,
+                    depth,
+                    &format!("{}{}, {}: {}", label, desc, count(width, "byte"), n),
+                )?;
+            }
+            BSER_REAL => {
+                let n = self.bunser.next_f64()?;
+                self.line(offset, depth, &format!("{}{}: {}", label, desc, n))?;
+            }
+            BSER_TRUE | BSER_FALSE | BSER_NULL => {
+                self.bunser.discard();
+                self.line(offset, depth, &format!("{}{}", label, desc))?;
+            }
+            ch => return Err(ErrorKind::DeInvalidStartByte(Expected::Value, ch).into()),
+        }
+        Ok(())
+    }
+
+    /// Read an integer length, returning it along with a description of its
+    /// encoding.
+    fn length(&mut self) -> Result<(usize, String)> {
+        let tag = self.bunser.peek()?;
+        let len = self.bunser.check_next_int()?;
+        if len < 0 {
+            return Err(ErrorKind::DeInvalidLength(len).into());
+        }
+        Ok((len as usize, int_desc(int_width(tag))))
+    }
+
+    fn container_len(&mut self) -> Result<(usize, String)> {
+        let (len, width) = self.length()?;
+        self.options.check_container_len(len)?;
+        Ok((len, width))
+    }
+
+    /// Read a string of either kind, returning it quoted.
+    fn string(&mut self) -> Result<String> {
+        self.string_parts().map(|(s, _, _)| s)
+    }
+
+    fn string_parts(&mut self) -> Result<(String, usize, String)> {
+        let tag = self.bunser.peek()?;
+        match tag {
+            BSER_BYTESTRING | BSER_UTF8STRING => self.bunser.discard(),
+            ch => return Err(ErrorKind::DeInvalidStartByte(Expected::String, ch).into()),
+        }
+        let (len, width) = self.length()?;
+        self.options.check_string_len(len)?;
+        let bytes = self.bunser.read_bytes(len as i64)?.get_ref().to_vec();
+        let s = if tag == BSER_UTF8STRING {
+            String::from_utf8(bytes).map_err(|err| err.utf8_error())?
+        } else {
+            ByteString::from(bytes).as_escaped_string()
+        };
+        Ok((format!("{:?}", s), len, width))
+    }
+}
+
+fn int_width(tag: u8) -> usize {
+    match tag {
+        BSER_INT8 => 1,
+        BSER_INT16 => 2,
+        BSER_INT32 => 4,
+        _ => 8,
+    }
+}
+
+fn int_desc(width: usize) -> String {
+    let tag = match width {
+        1 => BSER_INT8,
+        2 => BSER_INT16,
+        4 => BSER_INT32,
+        _ => BSER_INT64,
+    };
+    format!("{}, {}", header_byte_desc(tag), count(width, "byte"))
+}
+
+fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", n, noun)
+    }
+}
@@ -1,7 +1,11 @@
+use std::cell::RefCell;
 use std::convert::TryInto;
-use std::ffi::OsString;
-use std::fmt::Write;
-use std::path::PathBuf;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 /// The ByteString type represents values encoded using BSER_BYTESTRING.
 /// The purpose of this encoding is to represent bytestrings with an arbitrary
@@ -59,39 +63,45 @@ impl ByteString {
         self.0.as_slice()
     }
 
+    /// Consumes the bytestring and returns the raw bytes
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
     /// Returns a version of the bytestring encoded as a mostly-utf-8
     /// string, with invalid sequences escaped using `\xXX` hex notation.
     /// This is for diagnostic and display purposes.
     pub fn as_escaped_string(&self) -> String {
-        let mut input = self.0.as_slice();
-        let mut output = String::new();
+        escape(&self.0)
+    }
+}
 
-        loop {
-            match ::std::str::from_utf8(input) {
-                Ok(valid) => {
-                    output.push_str(valid);
-                    break;
-                }
-                Err(error) => {
-                    let (valid, after_valid) = input.split_at(error.valid_up_to());
-                    unsafe {
-                        output.push_str(::std::str::from_utf8_unchecked(valid))
-                    }
+fn escape(mut input: &[u8]) -> String {
+    let mut output = String::new();
 
-                    if let Some(invalid_sequence_length) = error.error_len() {
-                        for b in &after_valid[..invalid_sequence_length] {
-                            write!(output, "\\x{:x}", b).unwrap();
-                        }
-                        input = &after_valid[invalid_sequence_length..];
-                    } else {
-                        break;
+    loop {
+        match ::std::str::from_utf8(input) {
+            Ok(valid) => {
+                output.push_str(valid);
+                break;
+            }
+            Err(error) => {
+                let (valid, after_valid) = input.split_at(error.valid_up_to());
+                unsafe { output.push_str(::std::str::from_utf8_unchecked(valid)) }
+
+                if let Some(invalid_sequence_length) = error.error_len() {
+                    for b in &after_valid[..invalid_sequence_length] {
+                        write!(output, "\\x{:x}", b).unwrap();
                     }
+                    input = &after_valid[invalid_sequence_length..];
+                } else {
+                    break;
                 }
             }
         }
-
-        output
     }
+
+    output
 }
 
 /// Guaranteed conversion from an owned byte vector to a ByteString
@@ -165,7 +175,7 @@ impl TryInto<ByteString> for OsString {
     fn try_into(self) -> Result<ByteString, Self::Error> {
         let s = self
             .into_string()
-            .map_err(|_| "OsString is not representible as UTF-8")?;
+            .map_err(|_| "OsString is not representable as UTF-8")?;
         Ok(ByteString(s.into_bytes()))
     }
 }
@@ -176,6 +186,370 @@ impl TryInto<ByteString> for PathBuf {
     type Error = &'static str;
 
     fn try_into(self) -> Result<ByteString, Self::Error> {
-        Ok(self.into_os_string().try_into()?)
+        self.into_os_string().try_into()
+    }
+}
+
+/// Serializes as `BSER_BYTESTRING`.
+impl Serialize for ByteString {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+/// Accepts either kind of BSER string.
+impl<'de> Deserialize<'de> for ByteString {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(ByteStringVisitor)
+    }
+}
+
+struct ByteStringVisitor;
+
+impl<'de> de::Visitor<'de> for ByteStringVisitor {
+    type Value = ByteString;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a bytestring or string")
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<ByteString, E> {
+        Ok(ByteString(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<ByteString, E> {
+        Ok(ByteString(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<ByteString, E> {
+        Ok(value.into())
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<ByteString, E> {
+        Ok(value.into())
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<ByteString, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(ByteString(bytes))
+    }
+}
+
+/// A bytestring that shares the buffer it was decoded from.
+///
+/// When a PDU is decoded from a `Bytes` buffer with `from_bytes` or
+/// `Decoder::decode_bytes`, each `BytesString` is a reference-counted slice
+/// of that buffer, so holding on to many of them, such as the names in a
+/// large query result, costs little more than the buffer itself.  When
+/// decoded in any other way, the bytes are copied.
+///
+/// Otherwise it behaves as `ByteString` does: it is encoded as
+/// `BSER_BYTESTRING`, and either kind of BSER string can be decoded as one.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BytesString(Bytes);
+
+impl std::fmt::Debug for BytesString {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(fmt, "\"{}\"", escape(&self.0).escape_debug())
+    }
+}
+
+impl std::fmt::Display for BytesString {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(fmt, "\"{}\"", escape(&self.0).escape_default())
+    }
+}
+
+impl std::ops::Deref for BytesString {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl BytesString {
+    /// Returns the raw bytes as a slice
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Consumes the bytestring and returns the shared buffer
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    /// Returns the bytestring as a path, without copying it.  This is
+    /// guaranteed to succeed on unix systems, but requires the bytes to be
+    /// utf8 on Windows systems.
+    #[cfg(unix)]
+    pub fn as_path(&self) -> Result<&Path, std::str::Utf8Error> {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(Path::new(OsStr::from_bytes(&self.0)))
+    }
+
+    #[cfg(windows)]
+    pub fn as_path(&self) -> Result<&Path, std::str::Utf8Error> {
+        std::str::from_utf8(&self.0).map(Path::new)
+    }
+
+    /// Returns a version of the bytestring encoded as a mostly-utf-8
+    /// string, with invalid sequences escaped using `\xXX` hex notation.
+    /// This is for diagnostic and display purposes.
+    pub fn as_escaped_string(&self) -> String {
+        escape(&self.0)
+    }
+}
+
+impl From<Bytes> for BytesString {
+    fn from(bytes: Bytes) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Vec<u8>> for BytesString {
+    fn from(vec: Vec<u8>) -> Self {
+        Self(vec.into())
+    }
+}
+
+impl From<String> for BytesString {
+    fn from(s: String) -> Self {
+        Self(s.into())
+    }
+}
+
+impl From<&str> for BytesString {
+    fn from(s: &str) -> Self {
+        Self(Bytes::copy_from_slice(s.as_bytes()))
+    }
+}
+
+impl From<ByteString> for BytesString {
+    fn from(s: ByteString) -> Self {
+        Self(s.0.into())
+    }
+}
+
+impl From<BytesString> for ByteString {
+    fn from(s: BytesString) -> Self {
+        Self(s.0.to_vec())
+    }
+}
+
+/// Serializes as `BSER_BYTESTRING`.
+impl Serialize for BytesString {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+/// Accepts either kind of BSER string.
+impl<'de> Deserialize<'de> for BytesString {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(BytesStringVisitor)
+    }
+}
+
+struct BytesStringVisitor;
+
+impl<'de> de::Visitor<'de> for BytesStringVisitor {
+    type Value = BytesString;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a bytestring or string")
+    }
+
+    fn visit_borrowed_bytes<E>(self, value: &'de [u8]) -> Result<BytesString, E> {
+        Ok(BytesString(take_offered(value)))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<BytesString, E> {
+        Ok(BytesString(Bytes::copy_from_slice(value)))
+    }
+
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<BytesString, E> {
+        Ok(value.into())
+    }
+
+    fn visit_borrowed_str<E>(self, value: &'de str) -> Result<BytesString, E> {
+        Ok(BytesString(take_offered(value.as_bytes())))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<BytesString, E> {
+        Ok(value.into())
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<BytesString, E> {
+        Ok(value.into())
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<BytesString, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        ByteStringVisitor.visit_seq(seq).map(BytesString::from)
+    }
+}
+
+thread_local! {
+    /// The string that a deserializer reading from a `Bytes` buffer is
+    /// passing to a visitor, as a slice of that buffer.  Serde has no way
+    /// to hand a `Bytes` to `BytesStringVisitor` directly, so the
+    /// deserializer offers it here for the duration of the call.
+    static OFFERED: RefCell<Option<Bytes>> = const { RefCell::new(None) };
+}
+
+/// Run `f`, which passes the contents of `shared` to a visitor, offering
+/// `shared` to the visitor in case it is a `BytesStringVisitor`.
+pub(crate) fn offer<T>(shared: Bytes, f: impl FnOnce() -> T) -> T {
+    struct Withdraw;
+
+    impl Drop for Withdraw {
+        fn drop(&mut self) {
+            OFFERED.with(|cell| cell.borrow_mut().take());
+        }
+    }
+
+    OFFERED.with(|cell| *cell.borrow_mut() = Some(shared));
+    let _withdraw = Withdraw;
+    f()
+}
+
+/// Takes the offered `Bytes` if it holds `value` itself, or else copies
+/// `value`.
+fn take_offered(value: &[u8]) -> Bytes {
+    match OFFERED.with(|cell| cell.borrow_mut().take()) {
+        Some(shared) if shared.as_ptr() == value.as_ptr() && shared.len() == value.len() => shared,
+        _ => Bytes::copy_from_slice(value),
+    }
+}
+
+#[cfg(unix)]
+fn serialize_os_str<S>(s: &OsStr, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_bytes(std::os::unix::ffi::OsStrExt::as_bytes(s))
+}
+
+#[cfg(windows)]
+fn serialize_os_str<S>(s: &OsStr, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match s.to_str() {
+        Some(s) => serializer.serialize_bytes(s.as_bytes()),
+        None => Err(serde::ser::Error::custom(
+            "OsString is not representable as UTF-8",
+        )),
+    }
+}
+
+fn deserialize_os_string<'de, D>(deserializer: D) -> Result<OsString, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    ByteString::deserialize(deserializer)?
+        .try_into()
+        .map_err(de::Error::custom)
+}
+
+/// Serialize and deserialize a `PathBuf` as a `BSER_BYTESTRING`, which is
+/// lossless on unix systems.  Use it with
+/// `#[serde(with = "serde_bser::bytestring::path")]`.
+///
+/// serde's own impls for `PathBuf` use `BSER_UTF8STRING`, and so fail for
+/// paths that aren't valid UTF-8.
+pub mod path {
+    use std::path::{Path, PathBuf};
+
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<P, S>(path: P, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        P: AsRef<Path>,
+        S: Serializer,
+    {
+        super::serialize_os_str(path.as_ref().as_os_str(), serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::deserialize_os_string(deserializer).map(PathBuf::from)
+    }
+
+    /// The same as `path`, for `Option<PathBuf>`.
+    pub mod option {
+        use std::path::{Path, PathBuf};
+
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        #[derive(Serialize)]
+        struct Borrowed<'a>(#[serde(with = "super")] &'a Path);
+
+        #[derive(Deserialize)]
+        struct Owned(#[serde(with = "super")] PathBuf);
+
+        pub fn serialize<P, S>(path: &Option<P>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            P: AsRef<Path>,
+            S: Serializer,
+        {
+            path.as_ref()
+                .map(|path| Borrowed(path.as_ref()))
+                .serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Ok(Option::<Owned>::deserialize(deserializer)?.map(|path| path.0))
+        }
+    }
+}
+
+/// Serialize and deserialize an `OsString` as a `BSER_BYTESTRING`, which is
+/// lossless on unix systems.  Use it with
+/// `#[serde(with = "serde_bser::bytestring::os_string")]`.
+pub mod os_string {
+    use std::ffi::{OsStr, OsString};
+
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<O, S>(s: O, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        O: AsRef<OsStr>,
+        S: Serializer,
+    {
+        super::serialize_os_str(s.as_ref(), serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<OsString, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::deserialize_os_string(deserializer)
     }
 }
@@ -0,0 +1,193 @@
+//! A tokio codec for framing BSER PDUs on async streams.
+//!
+//! `BserCodec` splits a byte stream into complete PDUs, buffering partial
+//! PDUs across reads and keeping any bytes that belong to the following
+//! PDU.  Use it with `tokio_util::codec::FramedRead` to turn any
+//! `AsyncRead` into a stream of PDUs, and with `FramedWrite` to send
+//! serializable values.
+
+use std::cmp;
+
+use bytes::{Bytes, BytesMut};
+use serde::ser::Serialize;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::de::{read_pdu_info, DeserializerOptions};
+use crate::errors::*;
+use crate::ser::{self, SerializerOptions};
+
+/// The `max_pdu_len` a `BserCodec` applies unless its `decode_options`
+/// are set: 1GiB, far more than watchman sends in practice.
+pub const DEFAULT_MAX_PDU_LEN: usize = 1 << 30;
+
+/// How much space to reserve for the rest of a PDU, at most, beyond the
+/// bytes of it that have already arrived.
+const RESERVE_CHUNK: usize = 64 * 1024;
+
+/// Frames BSER PDUs.
+///
+/// Decoding yields each complete PDU, including its header, ready to be
+/// passed to `serde_bser::from_slice`.  Encoding serializes a value as a
+/// PDU using the codec's `SerializerOptions`, encoding into a buffer that
+/// is reused for each PDU.
+#[derive(Clone, Debug)]
+pub struct BserCodec {
+    encoder: ser::Encoder,
+    decode_options: DeserializerOptions,
+}
+
+impl Default for BserCodec {
+    fn default() -> Self {
+        Self {
+            encoder: ser::Encoder::default(),
+            decode_options: DeserializerOptions::new().max_pdu_len(DEFAULT_MAX_PDU_LEN),
+        }
+    }
+}
+
+impl BserCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a codec that encodes PDUs using the specified options.
+    pub fn with_options(options: SerializerOptions) -> Self {
+        Self {
+            encoder: ser::Encoder::with_options(options),
+            ..Self::default()
+        }
+    }
+
+    /// Apply the limits in `options` when decoding.  The codec only frames
+    /// PDUs, so of those limits only `max_pdu_len` is checked; it is checked
+    /// before buffering the PDU.  This replaces the default limit of
+    /// `DEFAULT_MAX_PDU_LEN`, so PDUs are unlimited in length if `options`
+    /// doesn't set one.
+    pub fn decode_options(mut self, options: DeserializerOptions) -> Self {
+        self.decode_options = options;
+        self
+    }
+}
+
+impl Decoder for BserCodec {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Bytes>> {
+        let info = match read_pdu_info(src)? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        let total = info.end()?;
+        self.decode_options.check_pdu_len(info.body_len()?)?;
+        if src.len() < total {
+            // Make room for more of the PDU, but don't trust its length
+            // enough to allocate for all of it before it arrives.  Growing
+            // by up to what has already arrived keeps the copying linear.
+            let chunk = cmp::max(src.len(), RESERVE_CHUNK);
+            src.reserve(cmp::min(total - src.len(), chunk));
+            return Ok(None);
+        }
+        Ok(Some(src.split_to(total).freeze()))
+    }
+}
+
+impl<T> Encoder<T> for BserCodec
+where
+    T: Serialize,
+{
+    type Error = Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<()> {
+        dst.extend_from_slice(self.encoder.encode(item)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    #[tokio::test]
+    async fn test_framing() {
+        let mut buf = vec![];
+        {
+            let mut writer = FramedWrite::new(&mut buf, BserCodec::new());
+            writer.send("first").await.unwrap();
+            writer.send(vec![1, 2, 3]).await.unwrap();
+            writer.send(true).await.unwrap();
+        }
+
+        // Deliver the bytes in small pieces that straddle PDU boundaries
+        let chunks = buf
+            .chunks(5)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect::<Vec<std::io::Result<_>>>();
+        let reader = tokio::io::stream_reader(tokio::stream::iter(chunks));
+        let mut reader = FramedRead::new(reader, BserCodec::new());
+
+        let pdu = reader.next().await.unwrap().unwrap();
+        assert_eq!(crate::from_slice::<String>(&pdu).unwrap(), "first");
+        let pdu = reader.next().await.unwrap().unwrap();
+        assert_eq!(crate::from_slice::<Vec<i32>>(&pdu).unwrap(), vec![1, 2, 3]);
+        let pdu = reader.next().await.unwrap().unwrap();
+        assert!(crate::from_slice::<bool>(&pdu).unwrap());
+        assert!(reader.next().await.is_none());
+    }
+
+    #[test]
+    fn test_decode_partial() {
+        let pdu = crate::ser::serialize(Vec::new(), "hello").unwrap();
+        let mut codec = BserCodec::new();
+        let mut src = BytesMut::new();
+        for (i, byte) in pdu.iter().enumerate() {
+            assert!(codec.decode(&mut src).unwrap().is_none(), "at byte {}", i);
+            src.extend_from_slice(&[*byte]);
+        }
+        // A following PDU that arrived in the same read is preserved
+        src.extend_from_slice(&pdu[..3]);
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap(), pdu);
+        assert_eq!(&src[..], &pdu[..3]);
+
+        let mut src = BytesMut::from(&b"\x00\x07\x00"[..]);
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_decode_huge_len() {
+        // A length so large that adding the header overflows
+        let mut codec = BserCodec::new().decode_options(DeserializerOptions::new());
+        let mut src = BytesMut::from(&b"\x00\x01\x06\xff\xff\xff\xff\xff\xff\xff\x7f"[..]);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::DeInvalidLength(_)));
+
+        // Without a limit, only a bounded amount is reserved for the rest
+        // of a PDU whose header claims it is huge.
+        let mut src = BytesMut::from(&b"\x00\x01\x05\x00\x00\x00\x7f"[..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(src.capacity() <= 2 * RESERVE_CHUNK);
+
+        // The default limit rejects it outright.
+        let err = BserCodec::new().decode(&mut src).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::DePduTooLarge { .. }));
+    }
+
+    #[test]
+    fn test_decode_max_pdu_len() {
+        let mut codec = BserCodec::new().decode_options(DeserializerOptions::new().max_pdu_len(5));
+        let mut src = BytesMut::from(&crate::ser::to_vec("hi").unwrap()[..]);
+        assert!(codec.decode(&mut src).unwrap().is_some());
+
+        // Only the header has arrived, but the PDU is rejected without
+        // waiting for (or reserving space for) the rest of it.
+        let pdu = crate::ser::to_vec("hello").unwrap();
+        let mut src = BytesMut::from(&pdu[..8]);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::DePduTooLarge { len: 8, max: 5 }
+        ));
+    }
+}
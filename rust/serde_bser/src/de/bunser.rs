@@ -1,7 +1,9 @@
 //! Internal stateless code for handling BSER deserialization.
 
+use std::convert::TryFrom;
+
 use byteorder::{ByteOrder, NativeEndian};
-use error_chain::bail;
+use bytes::Bytes;
 
 use crate::de::read::{DeRead, Reference};
 use crate::errors::*;
@@ -14,38 +16,123 @@ pub struct Bunser<R> {
 }
 
 pub struct PduInfo {
+    pub bser_version: BserVersion,
     pub bser_capabilities: u32,
     pub len: i64,
     pub start: i64,
 }
 
+impl PduInfo {
+    /// The length of the PDU, excluding its header.
+    pub fn body_len(&self) -> Result<usize> {
+        usize::try_from(self.len).map_err(|_| ErrorKind::DeInvalidLength(self.len).into())
+    }
+
+    /// The offset just past the end of the PDU, in the input it was read
+    /// from.  A length too large for this to be represented is an error.
+    pub fn end(&self) -> Result<usize> {
+        self.start
+            .checked_add(self.len)
+            .filter(|_| self.len >= 0)
+            .and_then(|end| usize::try_from(end).ok())
+            .ok_or_else(|| ErrorKind::DeInvalidLength(self.len).into())
+    }
+}
+
+/// Decode the PDU header at the start of `buf` without consuming it.
+///
+/// Returns `Ok(None)` if `buf` doesn't yet hold the complete header, which
+/// makes this suitable for framing PDUs that arrive in pieces.
+pub fn read_pdu_info(buf: &[u8]) -> Result<Option<PduInfo>> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let (bser_version, mut pos) = match &buf[..2] {
+        m if m == BSER_V1_MAGIC => (BserVersion::V1, 2),
+        m if m == BSER_V2_MAGIC => (BserVersion::V2, 6),
+        m => bail!(ErrorKind::DeInvalidMagic(m.to_vec())),
+    };
+    if buf.len() <= pos {
+        return Ok(None);
+    }
+    let bser_capabilities = match bser_version {
+        BserVersion::V1 => 0,
+        BserVersion::V2 => NativeEndian::read_u32(&buf[2..6]),
+    };
+
+    let width = match buf[pos] {
+        BSER_INT8 => 1,
+        BSER_INT16 => 2,
+        BSER_INT32 => 4,
+        BSER_INT64 => 8,
+        ch => bail!(ErrorKind::DeInvalidStartByte(Expected::Integer, ch)),
+    };
+    pos += 1;
+    if buf.len() < pos + width {
+        return Ok(None);
+    }
+    let bytes = &buf[pos..pos + width];
+    let len = match width {
+        1 => bytes[0] as i8 as i64,
+        2 => NativeEndian::read_i16(bytes) as i64,
+        4 => NativeEndian::read_i32(bytes) as i64,
+        _ => NativeEndian::read_i64(bytes),
+    };
+    let info = PduInfo {
+        bser_version,
+        bser_capabilities,
+        len,
+        start: (pos + width) as i64,
+    };
+    info.end()?;
+    Ok(Some(info))
+}
+
 impl<'de, R> Bunser<R>
 where
     R: DeRead<'de>,
 {
     pub fn new(read: R) -> Self {
-        Bunser {
-            read,
-            scratch: Vec::with_capacity(128),
-        }
+        // The scratch buffer is only needed when reading from an `io::Read`,
+        // and then grows to fit the longest string.
+        Self::with_scratch(read, Vec::new())
+    }
+
+    pub(crate) fn with_scratch(read: R, scratch: Vec<u8>) -> Self {
+        Bunser { read, scratch }
+    }
+
+    pub(crate) fn into_scratch(self) -> Vec<u8> {
+        self.scratch
     }
 
     /// Read the PDU off the stream. This should be called in the beginning.
+    ///
+    /// Both BSER v1 and v2 PDUs are accepted; v1 PDUs have no capabilities.
     pub fn read_pdu(&mut self) -> Result<PduInfo> {
-        {
+        let bser_version = {
             let magic = self.read_bytes(2)?;
-            if magic.get_ref() != &EMPTY_HEADER[..2] {
-                bail!("invalid magic header {:?}", magic);
+            match magic.get_ref() {
+                m if m == BSER_V1_MAGIC => BserVersion::V1,
+                m if m == BSER_V2_MAGIC => BserVersion::V2,
+                m => bail!(ErrorKind::DeInvalidMagic(m.to_vec())),
             }
-        }
-        let bser_capabilities = self.read.next_u32(&mut self.scratch)?;
+        };
+        let bser_capabilities = match bser_version {
+            BserVersion::V1 => 0,
+            BserVersion::V2 => self.read.next_u32(&mut self.scratch)?,
+        };
         let len = self.check_next_int()?;
         let start = self.read_count();
-        Ok(PduInfo {
+        let info = PduInfo {
+            bser_version,
             bser_capabilities,
             len,
             start,
-        })
+        };
+        // Reject negative and overflowing lengths up front.
+        info.end()?;
+        Ok(info)
     }
 
     pub fn read_count(&self) -> i64 {
@@ -53,13 +140,25 @@ where
     }
 
     pub fn end(&self, pdu_info: &PduInfo) -> Result<()> {
-        let expected = (pdu_info.start + pdu_info.len) as usize;
+        let expected = pdu_info.end()?;
         if self.read.read_count() != expected {
-            bail!(
-                "Expected {} bytes read, but only read {} bytes",
+            bail!(ErrorKind::DeLengthMismatch {
                 expected,
-                self.read.read_count()
-            );
+                actual: self.read.read_count(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that `nrows` template rows of `nkeys` slots each can fit
+    /// before `end`, given that every slot takes at least one byte.  A
+    /// template with no keys is allowed at most one row per remaining byte,
+    /// so that a huge row count can't be used to loop without consuming
+    /// any input.
+    pub fn check_template_rows(&self, nkeys: usize, nrows: usize, end: usize) -> Result<()> {
+        let remaining = end.saturating_sub(self.read.read_count());
+        if nrows.saturating_mul(nkeys.max(1)) > remaining {
+            bail!(ErrorKind::DeUnexpectedEof("template"));
         }
         Ok(())
     }
@@ -74,6 +173,19 @@ where
         self.read.discard();
     }
 
+    /// Start recording the bytes that are read, starting with the peeked
+    /// byte if there is one.
+    #[inline]
+    pub fn begin_raw(&mut self) {
+        self.read.begin_raw();
+    }
+
+    /// Return the bytes read since `begin_raw`.
+    #[inline]
+    pub fn end_raw(&mut self) -> Reference<'de, '_, [u8]> {
+        self.read.end_raw(&mut self.scratch)
+    }
+
     /// Return a borrowed or copied version of the next n bytes.
     #[inline]
     pub fn read_bytes<'s>(&'s mut self, len: i64) -> Result<Reference<'de, 's, [u8]>> {
@@ -81,14 +193,18 @@ where
         self.read.next_bytes(len, &mut self.scratch)
     }
 
+    /// The bytes returned by the last call to `read_bytes`, sharing the
+    /// input if it is held in a `Bytes`.
+    #[inline]
+    pub fn share_last(&self) -> Option<Bytes> {
+        self.read.share_last()
+    }
+
     /// Return the next i8 value. This assumes the caller already knows the next
     /// value is an i8.
     pub fn next_i8(&mut self) -> Result<i8> {
         self.read.discard();
-        let bytes = self
-            .read_bytes(1)
-            .chain_err(|| "error while reading i8")?
-            .get_ref();
+        let bytes = self.read_bytes(1)?.get_ref();
         Ok(bytes[0] as i8)
     }
 
@@ -96,10 +212,7 @@ where
     /// next value is an i16.
     pub fn next_i16(&mut self) -> Result<i16> {
         self.read.discard();
-        let bytes = self
-            .read_bytes(2)
-            .chain_err(|| "error while reading i16")?
-            .get_ref();
+        let bytes = self.read_bytes(2)?.get_ref();
         Ok(NativeEndian::read_i16(bytes))
     }
 
@@ -107,10 +220,7 @@ where
     /// next value is an i32.
     pub fn next_i32(&mut self) -> Result<i32> {
         self.read.discard();
-        let bytes = self
-            .read_bytes(4)
-            .chain_err(|| "error while reading i32")?
-            .get_ref();
+        let bytes = self.read_bytes(4)?.get_ref();
         Ok(NativeEndian::read_i32(bytes))
     }
 
@@ -118,10 +228,7 @@ where
     /// next value is an i64.
     pub fn next_i64(&mut self) -> Result<i64> {
         self.read.discard();
-        let bytes = self
-            .read_bytes(8)
-            .chain_err(|| "error while reading i64")?
-            .get_ref();
+        let bytes = self.read_bytes(8)?.get_ref();
         Ok(NativeEndian::read_i64(bytes))
     }
 
@@ -132,8 +239,8 @@ where
             BSER_INT8 => self.next_i8()? as i64,
             BSER_INT16 => self.next_i16()? as i64,
             BSER_INT32 => self.next_i32()? as i64,
-            BSER_INT64 => self.next_i64()? as i64,
-            ch => bail!(ErrorKind::DeInvalidStartByte("integer".into(), ch)),
+            BSER_INT64 => self.next_i64()?,
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::Integer, ch)),
         };
 
         Ok(value)
@@ -141,10 +248,7 @@ where
 
     pub fn next_f64(&mut self) -> Result<f64> {
         self.read.discard();
-        let bytes = self
-            .read_bytes(8)
-            .chain_err(|| "error while reading f64")?
-            .get_ref();
+        let bytes = self.read_bytes(8)?.get_ref();
         Ok(NativeEndian::read_f64(bytes))
     }
 }
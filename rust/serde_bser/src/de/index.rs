@@ -0,0 +1,232 @@
+//! Random access to the PDUs in a recording of concatenated PDUs.
+
+use std::convert::TryFrom;
+use std::io::{self, Seek, SeekFrom};
+use std::ops::Range;
+use std::slice;
+
+use serde::de;
+
+use super::{from_slice_with_options, Bunser, DeRead, DeserializerOptions, IoRead, SliceRead};
+use crate::errors::*;
+use crate::header::*;
+
+/// Where a PDU is in a recording, along with the contents of its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PduEntry {
+    /// The offset of the start of the PDU's header.
+    pub offset: usize,
+    /// The length of the whole PDU, including its header.
+    pub len: usize,
+    pub bser_version: BserVersion,
+    pub bser_capabilities: u32,
+}
+
+impl PduEntry {
+    /// The range of bytes the PDU occupies in the recording.
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.len
+    }
+}
+
+/// An index of the PDUs in a recording of concatenated PDUs, such as a
+/// captured watchman session.
+///
+/// Building the index only reads the header of each PDU, so it's cheap
+/// even for a large recording; any one PDU can then be decoded without
+/// decoding those before it.
+///
+/// ```
+/// use serde_bser::de::PduIndex;
+///
+/// let mut recording = serde_bser::ser::to_vec("first").unwrap();
+/// recording.extend(serde_bser::ser::to_vec(vec![1, 2, 3]).unwrap());
+///
+/// let index = PduIndex::new(&recording).unwrap();
+/// assert_eq!(index.len(), 2);
+/// let numbers: Vec<i32> = index.decode(&recording, 1).unwrap();
+/// assert_eq!(numbers, vec![1, 2, 3]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PduIndex {
+    entries: Vec<PduEntry>,
+}
+
+impl PduIndex {
+    /// Index the PDUs in `slice`.  It is an error for `slice` to end partway
+    /// through a PDU.
+    pub fn new(slice: &[u8]) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < slice.len() {
+            let entry = read_entry(SliceRead::new(&slice[offset..]), offset, slice.len())?;
+            offset += entry.len;
+            entries.push(entry);
+        }
+        Ok(PduIndex { entries })
+    }
+
+    /// Index the PDUs read from `rdr`, seeking past the body of each one.
+    /// Headers are read a byte at a time, so `rdr` should be buffered.
+    pub fn from_reader<R>(mut rdr: R) -> Result<Self>
+    where
+        R: io::Read + Seek,
+    {
+        let end = usize::try_from(rdr.seek(SeekFrom::End(0))?)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "recording is too large"))?;
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < end {
+            rdr.seek(SeekFrom::Start(offset as u64))?;
+            let entry = read_entry(IoRead::new(&mut rdr), offset, end)?;
+            offset += entry.len;
+            entries.push(entry);
+        }
+        Ok(PduIndex { entries })
+    }
+
+    /// The number of PDUs.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entry for the `n`th PDU, counting from zero.
+    pub fn get(&self, n: usize) -> Option<&PduEntry> {
+        self.entries.get(n)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, PduEntry> {
+        self.entries.iter()
+    }
+
+    /// The `n`th PDU in `slice`, which must be the recording that was
+    /// indexed.
+    pub fn pdu<'a>(&self, slice: &'a [u8], n: usize) -> Option<&'a [u8]> {
+        self.get(n).and_then(|entry| slice.get(entry.range()))
+    }
+
+    /// Decode the `n`th PDU in `slice`, which must be the recording that was
+    /// indexed.
+    pub fn decode<'de, T>(&self, slice: &'de [u8], n: usize) -> Result<T>
+    where
+        T: de::Deserialize<'de>,
+    {
+        self.decode_with_options(slice, n, &DeserializerOptions::default())
+    }
+
+    pub fn decode_with_options<'de, T>(
+        &self,
+        slice: &'de [u8],
+        n: usize,
+        options: &DeserializerOptions,
+    ) -> Result<T>
+    where
+        T: de::Deserialize<'de>,
+    {
+        let pdu = match self.pdu(slice, n) {
+            Some(pdu) => pdu,
+            None => bail!(ErrorKind::DeCustom(format!(
+                "no PDU {} in a recording of {}",
+                n,
+                self.len()
+            ))),
+        };
+        let offset = self.entries[n].offset;
+        from_slice_with_options(pdu, options).map_err(|err| err.offset_by(offset))
+    }
+}
+
+/// A recording of concatenated PDUs in a memory-mapped file, along with its
+/// index.  Only the pages holding the headers of the PDUs, and those of
+/// the PDUs that are decoded, are read from the file.
+///
+/// The file must not be modified while it is open: as for any memory map,
+/// changes made to the file by this or another process are visible through
+/// the map, and truncating the file can crash the process.
+#[cfg(feature = "mmap")]
+pub struct PduFile {
+    map: memmap2::Mmap,
+    index: PduIndex,
+}
+
+#[cfg(feature = "mmap")]
+impl PduFile {
+    /// Map the file at `path` and index the PDUs in it.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let file = std::fs::File::open(path)?;
+        // Safety: see the caveats in the documentation above.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        let index = PduIndex::new(&map)?;
+        Ok(PduFile { map, index })
+    }
+
+    pub fn index(&self) -> &PduIndex {
+        &self.index
+    }
+
+    /// The contents of the file.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// The `n`th PDU in the file.
+    pub fn pdu(&self, n: usize) -> Option<&[u8]> {
+        self.index.pdu(&self.map, n)
+    }
+
+    /// Decode the `n`th PDU in the file.  Borrowed strings in the result
+    /// point directly into the map.
+    pub fn decode<'de, T>(&'de self, n: usize) -> Result<T>
+    where
+        T: de::Deserialize<'de>,
+    {
+        self.index.decode(&self.map, n)
+    }
+
+    pub fn decode_with_options<'de, T>(
+        &'de self,
+        n: usize,
+        options: &DeserializerOptions,
+    ) -> Result<T>
+    where
+        T: de::Deserialize<'de>,
+    {
+        self.index.decode_with_options(&self.map, n, options)
+    }
+}
+
+impl<'a> IntoIterator for &'a PduIndex {
+    type Item = &'a PduEntry;
+    type IntoIter = slice::Iter<'a, PduEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+/// Read the header of the PDU at `offset` in a recording that is `end`
+/// bytes long.
+fn read_entry<'de, R>(read: R, offset: usize, end: usize) -> Result<PduEntry>
+where
+    R: DeRead<'de>,
+{
+    let mut bunser = Bunser::new(read);
+    let info = bunser.read_pdu().map_err(|err| err.at_offset(offset))?;
+    let len = info.end().map_err(|err| err.at_offset(offset))?;
+    if len > end - offset {
+        return Err(Error::from(ErrorKind::DeUnexpectedEof("PDU")).at_offset(offset));
+    }
+    Ok(PduEntry {
+        offset,
+        len,
+        bser_version: info.bser_version,
+        bser_capabilities: info.bser_capabilities,
+    })
+}
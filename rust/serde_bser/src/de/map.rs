@@ -1,19 +1,56 @@
-use error_chain::bail;
+use std::str;
+
 use serde::{de, forward_to_deserialize_any};
 
 use crate::errors::*;
 use crate::header::*;
 
-use super::read::DeRead;
+use super::read::{DeRead, Reference};
 use super::reentrant::ReentrantGuard;
 use super::Deserializer;
 
-pub struct MapAccess<'a, R> {
+pub struct MapAccess<'a, 'de, R> {
     de: &'a mut Deserializer<R>,
     remaining: usize,
+    /// The most recently read key, for reporting where errors occur.
+    key: LastKey<'de>,
+}
+
+/// A key is only turned into a `PathSegment` if there's an error, so keys
+/// that borrow from the input aren't copied.  Those that don't are copied
+/// into one buffer that is reused for every key in the map.
+enum LastKey<'de> {
+    Borrowed(&'de str),
+    Copied(String),
+}
+
+impl<'de> LastKey<'de> {
+    fn clear(&mut self) {
+        match self {
+            LastKey::Borrowed(s) => *s = "",
+            LastKey::Copied(s) => s.clear(),
+        }
+    }
+
+    fn set_copied(&mut self, key: &str) {
+        match self {
+            LastKey::Copied(s) => {
+                s.clear();
+                s.push_str(key);
+            }
+            _ => *self = LastKey::Copied(key.to_owned()),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            LastKey::Borrowed(s) => s,
+            LastKey::Copied(s) => s,
+        }
+    }
 }
 
-impl<'a, 'de, R> MapAccess<'a, R>
+impl<'a, 'de, R> MapAccess<'a, 'de, R>
 where
     R: 'a + DeRead<'de>,
 {
@@ -24,11 +61,19 @@ where
         MapAccess {
             de,
             remaining: nitems,
+            key: LastKey::Borrowed(""),
+        }
+    }
+
+    fn in_path(&self, err: Error) -> Error {
+        match self.key.as_str() {
+            "" => err,
+            key => err.in_path(PathSegment::Key(key.to_owned())),
         }
     }
 }
 
-impl<'a, 'de, R> de::MapAccess<'de> for MapAccess<'a, R>
+impl<'a, 'de, R> de::MapAccess<'de> for MapAccess<'a, 'de, R>
 where
     R: 'a + DeRead<'de>,
 {
@@ -42,7 +87,13 @@ where
             Ok(None)
         } else {
             self.remaining -= 1;
-            let key = seed.deserialize(MapKey { de: &mut *self.de })?;
+            self.key.clear();
+            let key = seed
+                .deserialize(MapKey {
+                    de: &mut *self.de,
+                    key: &mut self.key,
+                })
+                .map_err(|err| self.in_path(err))?;
             Ok(Some(key))
         }
     }
@@ -52,16 +103,18 @@ where
         V: de::DeserializeSeed<'de>,
     {
         seed.deserialize(&mut *self.de)
+            .map_err(|err| self.in_path(err))
     }
 }
 
 /// A deserializer that is specialized to deal with map keys. Specifically, map keys are always
 /// strings.
-struct MapKey<'a, R> {
+struct MapKey<'a, 'de, R> {
     de: &'a mut Deserializer<R>,
+    key: &'a mut LastKey<'de>,
 }
 
-impl<'a, 'de, R> de::Deserializer<'de> for MapKey<'a, R>
+impl<'a, 'de, R> de::Deserializer<'de> for MapKey<'a, 'de, R>
 where
     R: DeRead<'de>,
 {
@@ -75,8 +128,26 @@ where
         match self.de.bunser.peek()? {
             // Both bytestrings and UTF-8 strings are treated as Unicode strings, since field
             // identifiers must be Unicode strings.
-            BSER_BYTESTRING | BSER_UTF8STRING => self.de.visit_utf8string(visitor),
-            other => bail!(ErrorKind::DeInvalidStartByte("map key".into(), other)),
+            BSER_BYTESTRING | BSER_UTF8STRING => {
+                self.de.bunser.discard();
+                let len = self.de.string_len()?;
+                let s = self
+                    .de
+                    .bunser
+                    .read_bytes(len as i64)?
+                    .map_result(str::from_utf8)?;
+                match s {
+                    Reference::Borrowed(s) => {
+                        *self.key = LastKey::Borrowed(s);
+                        self.de.share_last(|| visitor.visit_borrowed_str(s))
+                    }
+                    Reference::Copied(s) => {
+                        self.key.set_copied(s);
+                        visitor.visit_str(s)
+                    }
+                }
+            }
+            other => bail!(ErrorKind::DeInvalidStartByte(Expected::MapKey, other)),
         }
     }
 
@@ -1,30 +1,178 @@
 mod bunser;
+mod index;
 mod map;
 mod read;
-mod reentrant;
+mod reader;
+pub(crate) mod reentrant;
 mod seq;
+mod stream;
 mod template;
 #[cfg(test)]
 mod test;
 mod variant;
 
 use std::io;
+use std::mem;
 use std::str;
 
-use error_chain::bail;
+use bytes::Bytes;
 use serde::{de, forward_to_deserialize_any};
 
+use crate::bytestring;
 use crate::errors::*;
 use crate::header::*;
+use crate::raw;
+use crate::value::exact;
 
-pub use self::bunser::{Bunser, PduInfo};
-pub use self::read::{DeRead, Reference, SliceRead};
+pub use self::bunser::{read_pdu_info, Bunser, PduInfo};
+#[cfg(feature = "mmap")]
+pub use self::index::PduFile;
+pub use self::index::{PduEntry, PduIndex};
+pub use self::read::{BytesRead, DeRead, IoRead, Reference, SliceRead};
+pub use self::reader::{BserReader, Event};
 use self::reentrant::ReentrantLimit;
+pub use self::stream::StreamDeserializer;
+pub use self::template::FIELD_INDICES_STRUCT;
 
 pub struct Deserializer<R> {
     bunser: Bunser<R>,
     pdu_info: PduInfo,
     remaining_depth: ReentrantLimit,
+    max_string_len: Option<usize>,
+    max_container_len: Option<usize>,
+    u64_policy: U64Policy,
+    template_field_indices: bool,
+}
+
+/// Options that control how a PDU is decoded.
+///
+/// The limits guard against corrupt or hostile PDUs: lengths are read from
+/// the input, so without them a single bad length prefix could cause a
+/// huge allocation.  Exceeding a limit is an error.
+#[derive(Clone, Debug)]
+pub struct DeserializerOptions {
+    capabilities: Option<u32>,
+    pub(crate) max_depth: usize,
+    pub(crate) max_pdu_len: Option<usize>,
+    pub(crate) max_string_len: Option<usize>,
+    pub(crate) max_container_len: Option<usize>,
+    u64_policy: U64Policy,
+    template_field_indices: bool,
+}
+
+impl Default for DeserializerOptions {
+    fn default() -> Self {
+        Self {
+            capabilities: None,
+            max_depth: 128,
+            max_pdu_len: None,
+            max_string_len: None,
+            max_container_len: None,
+            u64_policy: U64Policy::default(),
+            template_field_indices: false,
+        }
+    }
+}
+
+impl DeserializerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The maximum nesting depth of arrays, objects and templates.  Defaults
+    /// to 128.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The maximum length of a PDU, excluding its header.  Unlimited by
+    /// default.
+    pub fn max_pdu_len(mut self, max_pdu_len: usize) -> Self {
+        self.max_pdu_len = Some(max_pdu_len);
+        self
+    }
+
+    /// The maximum length of a string or bytestring, in bytes.  Unlimited by
+    /// default.
+    pub fn max_string_len(mut self, max_string_len: usize) -> Self {
+        self.max_string_len = Some(max_string_len);
+        self
+    }
+
+    /// The maximum number of items in an array or object, or rows in a
+    /// template.  Unlimited by default.
+    pub fn max_container_len(mut self, max_container_len: usize) -> Self {
+        self.max_container_len = Some(max_container_len);
+        self
+    }
+
+    /// Set how a negative integer is decoded as a `u64`.  Defaults to
+    /// `U64Policy::Reject`.
+    pub fn u64_policy(mut self, u64_policy: U64Policy) -> Self {
+        self.u64_policy = u64_policy;
+        self
+    }
+
+    /// When decoding the rows of a template into a struct, match the keys
+    /// to the struct's fields once per template and identify each field to
+    /// the struct by its index, rather than by name in every row.  This
+    /// makes decoding large templates, such as query results, much faster.
+    ///
+    /// Off by default, because it relies on the field list that serde
+    /// passes to `deserialize_struct` lining up with the struct's field
+    /// indices.  That holds for derived `Deserialize` impls, except that
+    /// `#[serde(alias)]` adds extra names to the list, so structs with
+    /// aliased fields must not be decoded this way.  To opt in for
+    /// particular structs instead, see `FIELD_INDICES_STRUCT`.
+    pub fn template_field_indices(mut self, enabled: bool) -> Self {
+        self.template_field_indices = enabled;
+        self
+    }
+
+    /// Check the length from a PDU header against `max_pdu_len`.  This and
+    /// the other `check_` methods are for code that walks PDUs itself, such
+    /// as `bser-dump`; a `Deserializer` applies the limits on its own.
+    pub fn check_pdu_len(&self, len: usize) -> Result<()> {
+        match self.max_pdu_len {
+            Some(max) if len > max => bail!(ErrorKind::DePduTooLarge { len, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the length of a string or bytestring against `max_string_len`.
+    pub fn check_string_len(&self, len: usize) -> Result<()> {
+        match self.max_string_len {
+            Some(max) if len > max => bail!(ErrorKind::DeStringTooLong { len, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the length of an array, object or template against
+    /// `max_container_len`.
+    pub fn check_container_len(&self, len: usize) -> Result<()> {
+        match self.max_container_len {
+            Some(max) if len > max => bail!(ErrorKind::DeContainerTooLong { len, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that a `kind` nested `depth` containers deep is within
+    /// `max_depth`.
+    pub fn check_depth(&self, depth: usize, kind: &str) -> Result<()> {
+        if depth > self.max_depth {
+            bail!(ErrorKind::DeRecursionLimitExceeded(kind.into()));
+        }
+        Ok(())
+    }
+
+    /// Decode as though the PDU header advertised these capability bits
+    /// (`BSER_CAP_*`).  This is useful for BSER v1 PDUs, which carry no
+    /// capabilities, or when the capabilities were negotiated out of band.
+    pub fn capabilities(mut self, capabilities: u32) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
 }
 
 macro_rules! make_visit_num {
@@ -39,12 +187,12 @@ macro_rules! make_visit_num {
     };
 }
 
-fn from_trait<'de, R, T>(read: R) -> Result<T>
+fn from_trait<'de, R, T>(read: R, options: &DeserializerOptions) -> Result<T>
 where
     R: DeRead<'de>,
     T: de::Deserialize<'de>,
 {
-    let mut d = Deserializer::new(read)?;
+    let mut d = Deserializer::with_options(read, options)?;
     let value = de::Deserialize::deserialize(&mut d)?;
 
     // Make sure we saw the expected length.
@@ -56,7 +204,33 @@ pub fn from_slice<'de, T>(slice: &'de [u8]) -> Result<T>
 where
     T: de::Deserialize<'de>,
 {
-    from_trait(SliceRead::new(slice))
+    from_slice_with_options(slice, &DeserializerOptions::default())
+}
+
+pub fn from_slice_with_options<'de, T>(slice: &'de [u8], options: &DeserializerOptions) -> Result<T>
+where
+    T: de::Deserialize<'de>,
+{
+    from_trait(SliceRead::new(slice), options)
+}
+
+/// Deserialize the PDU held in `bytes`.  Unlike `from_slice`, any
+/// `BytesString` in the result shares `bytes` rather than copying from it.
+pub fn from_bytes<'de, T>(bytes: &'de Bytes) -> Result<T>
+where
+    T: de::Deserialize<'de>,
+{
+    from_bytes_with_options(bytes, &DeserializerOptions::default())
+}
+
+pub fn from_bytes_with_options<'de, T>(
+    bytes: &'de Bytes,
+    options: &DeserializerOptions,
+) -> Result<T>
+where
+    T: de::Deserialize<'de>,
+{
+    from_trait(BytesRead::new(bytes), options)
 }
 
 pub fn from_reader<R, T>(rdr: R) -> Result<T>
@@ -64,7 +238,98 @@ where
     R: io::Read,
     T: de::DeserializeOwned,
 {
-    from_trait(read::IoRead::new(rdr))
+    from_reader_with_options(rdr, &DeserializerOptions::default())
+}
+
+pub fn from_reader_with_options<R, T>(rdr: R, options: &DeserializerOptions) -> Result<T>
+where
+    R: io::Read,
+    T: de::DeserializeOwned,
+{
+    from_trait(IoRead::new(rdr), options)
+}
+
+/// Decodes a series of PDUs, keeping the memory that `from_slice` and
+/// `from_reader` allocate for each PDU so that it can be used for the
+/// next.
+///
+/// ```
+/// use serde_bser::de::Decoder;
+///
+/// let mut decoder = Decoder::new();
+/// for n in 0..3 {
+///     let pdu = serde_bser::ser::to_vec(vec![n]).unwrap();
+///     let decoded: Vec<i32> = decoder.decode(&pdu).unwrap();
+///     assert_eq!(decoded, vec![n]);
+/// }
+/// ```
+pub struct Decoder {
+    options: DeserializerOptions,
+    /// Used by `decode_from`; it grows to fit the longest string read.
+    scratch: Vec<u8>,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::with_options(DeserializerOptions::default())
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: DeserializerOptions) -> Self {
+        Decoder {
+            options,
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &DeserializerOptions {
+        &self.options
+    }
+
+    /// Decode the PDU in `slice`, as `from_slice_with_options` would.
+    pub fn decode<'de, T>(&mut self, slice: &'de [u8]) -> Result<T>
+    where
+        T: de::Deserialize<'de>,
+    {
+        self.decode_read(SliceRead::new(slice))
+    }
+
+    /// Decode the PDU in `bytes`, as `from_bytes_with_options` would.
+    pub fn decode_bytes<'de, T>(&mut self, bytes: &'de Bytes) -> Result<T>
+    where
+        T: de::Deserialize<'de>,
+    {
+        self.decode_read(BytesRead::new(bytes))
+    }
+
+    /// Decode a PDU read from `rdr`, as `from_reader_with_options` would.
+    pub fn decode_from<R, T>(&mut self, rdr: R) -> Result<T>
+    where
+        R: io::Read,
+        T: de::DeserializeOwned,
+    {
+        self.decode_read(IoRead::new(rdr))
+    }
+
+    fn decode_read<'de, R, T>(&mut self, read: R) -> Result<T>
+    where
+        R: DeRead<'de>,
+        T: de::Deserialize<'de>,
+    {
+        let bunser = Bunser::with_scratch(read, mem::take(&mut self.scratch));
+        let mut d = Deserializer::with_bunser(bunser, &self.options)?;
+        let result = de::Deserialize::deserialize(&mut d).and_then(|value| {
+            d.end()?;
+            Ok(value)
+        });
+        self.scratch = d.bunser.into_scratch();
+        result
+    }
 }
 
 impl<'de, R> Deserializer<R>
@@ -72,25 +337,86 @@ where
     R: DeRead<'de>,
 {
     pub fn new(read: R) -> Result<Self> {
-        let mut bunser = Bunser::new(read);
-        let pdu_info = bunser.read_pdu()?;
+        Self::with_options(read, &DeserializerOptions::default())
+    }
+
+    pub fn with_options(read: R, options: &DeserializerOptions) -> Result<Self> {
+        Self::with_bunser(Bunser::new(read), options)
+    }
+
+    /// Read the PDU header, using memory kept over from an earlier PDU.
+    fn with_bunser(mut bunser: Bunser<R>, options: &DeserializerOptions) -> Result<Self> {
+        let mut pdu_info = bunser.read_pdu().map_err(|err| err.at_offset(0))?;
+        options
+            .check_pdu_len(pdu_info.body_len()?)
+            .map_err(|err| err.at_offset(0))?;
+        if let Some(capabilities) = options.capabilities {
+            pdu_info.bser_capabilities = capabilities;
+        }
         Ok(Deserializer {
             bunser,
             pdu_info,
-            remaining_depth: ReentrantLimit::new(128),
+            remaining_depth: ReentrantLimit::new(options.max_depth),
+            max_string_len: options.max_string_len,
+            max_container_len: options.max_container_len,
+            u64_policy: options.u64_policy,
+            template_field_indices: options.template_field_indices,
         })
     }
 
+    /// Create a deserializer for a single value with no PDU header, such as
+    /// one captured by deserializing a `raw::TOKEN` newtype.
+    fn headerless(read: R, len: usize, options: &DeserializerOptions) -> Self {
+        Deserializer {
+            bunser: Bunser::new(read),
+            pdu_info: PduInfo {
+                bser_version: BserVersion::V2,
+                bser_capabilities: options.capabilities.unwrap_or(0),
+                len: len as i64,
+                start: 0,
+            },
+            remaining_depth: ReentrantLimit::new(options.max_depth),
+            max_string_len: options.max_string_len,
+            max_container_len: options.max_container_len,
+            u64_policy: options.u64_policy,
+            template_field_indices: options.template_field_indices,
+        }
+    }
+
     /// This method must be called after a value has been fully deserialized.
     pub fn end(&self) -> Result<()> {
         self.bunser.end(&self.pdu_info)
     }
 
+    /// The capability bits in effect for this PDU: those from the PDU header,
+    /// unless overridden by `DeserializerOptions::capabilities`.
     #[inline]
     pub fn capabilities(&self) -> u32 {
         self.pdu_info.bser_capabilities
     }
 
+    /// The BSER version of the PDU being deserialized.
+    #[inline]
+    pub fn bser_version(&self) -> BserVersion {
+        self.pdu_info.bser_version
+    }
+
+    #[inline]
+    fn unicode_disabled(&self) -> bool {
+        self.capabilities() & BSER_CAP_DISABLE_UNICODE != 0
+    }
+
+    /// Run `f` to decode the value at the current offset, recording that
+    /// offset in any error it returns.
+    #[inline]
+    fn at_current_offset<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let offset = self.bunser.read_count() as usize;
+        f(self).map_err(|err| err.at_offset(offset))
+    }
+
     fn parse_value<V>(&mut self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
@@ -99,21 +425,24 @@ where
             BSER_ARRAY => {
                 let guard = self.remaining_depth.acquire("array")?;
                 self.bunser.discard();
-                let nitems = self.bunser.check_next_int()?;
+                let nitems = self.container_len()?;
 
-                visitor.visit_seq(seq::SeqAccess::new(self, nitems as usize, &guard))
+                visitor.visit_seq(seq::SeqAccess::new(self, nitems, &guard))
             }
             BSER_OBJECT => {
                 let guard = self.remaining_depth.acquire("object")?;
                 self.bunser.discard();
-                let nitems = self.bunser.check_next_int()?;
+                let nitems = self.container_len()?;
 
-                visitor.visit_map(map::MapAccess::new(self, nitems as usize, &guard))
+                visitor.visit_map(map::MapAccess::new(self, nitems, &guard))
             }
             BSER_TRUE => self.visit_bool(visitor, true),
             BSER_FALSE => self.visit_bool(visitor, false),
             BSER_NULL => self.visit_unit(visitor),
             BSER_BYTESTRING => self.visit_bytestring(visitor),
+            // When the peer has disabled unicode, strings are opaque bytes
+            // as far as it is concerned, so surface them as bytestrings.
+            BSER_UTF8STRING if self.unicode_disabled() => self.visit_bytestring(visitor),
             BSER_UTF8STRING => self.visit_utf8string(visitor),
             BSER_TEMPLATE => {
                 let guard = self.remaining_depth.acquire("template")?;
@@ -122,8 +451,10 @@ where
                 // TODO: handle possible IO interruption better here -- will
                 // probably need some intermediate states.
                 let keys = self.template_keys()?;
-                let nitems = self.bunser.check_next_int()?;
-                let template = template::Template::new(self, keys, nitems as usize, &guard);
+                let nitems = self.container_len()?;
+                self.bunser
+                    .check_template_rows(keys.len(), nitems, self.pdu_info.end()?)?;
+                let template = template::Template::new(self, keys, nitems, &guard);
                 visitor.visit_seq(template)
             }
             BSER_REAL => self.visit_f64(visitor),
@@ -131,7 +462,7 @@ where
             BSER_INT16 => self.visit_i16(visitor),
             BSER_INT32 => self.visit_i32(visitor),
             BSER_INT64 => self.visit_i64(visitor),
-            ch => bail!(ErrorKind::DeInvalidStartByte("next item".into(), ch)),
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::Value, ch)),
         }
     }
 
@@ -141,20 +472,150 @@ where
     make_visit_num!(visit_i64, next_i64);
     make_visit_num!(visit_f64, next_f64);
 
+    /// Consume the next value without decoding it, subject to the same
+    /// limits as decoding it.
+    fn skip_value(&mut self) -> Result<()> {
+        match self.bunser.peek()? {
+            BSER_ARRAY => {
+                let _guard = self.remaining_depth.acquire("array")?;
+                self.bunser.discard();
+                for _ in 0..self.container_len()? {
+                    self.skip_value()?;
+                }
+            }
+            BSER_OBJECT => {
+                let _guard = self.remaining_depth.acquire("object")?;
+                self.bunser.discard();
+                for _ in 0..self.container_len()? {
+                    self.skip_string()?;
+                    self.skip_value()?;
+                }
+            }
+            BSER_TEMPLATE => {
+                let _guard = self.remaining_depth.acquire("template")?;
+                self.bunser.discard();
+                match self.bunser.peek()? {
+                    BSER_ARRAY => self.bunser.discard(),
+                    ch => bail!(ErrorKind::DeInvalidStartByte(Expected::TemplateKeys, ch)),
+                }
+                let nkeys = self.container_len()?;
+                for _ in 0..nkeys {
+                    self.skip_string()?;
+                }
+                let nrows = self.container_len()?;
+                self.bunser
+                    .check_template_rows(nkeys, nrows, self.pdu_info.end()?)?;
+                for _ in 0..nrows {
+                    for _ in 0..nkeys {
+                        match self.bunser.peek()? {
+                            BSER_SKIP => self.bunser.discard(),
+                            _ => self.skip_value()?,
+                        }
+                    }
+                }
+            }
+            BSER_BYTESTRING | BSER_UTF8STRING => self.skip_string()?,
+            BSER_INT8 | BSER_INT16 | BSER_INT32 | BSER_INT64 => {
+                self.bunser.check_next_int()?;
+            }
+            BSER_REAL => {
+                self.bunser.next_f64()?;
+            }
+            BSER_TRUE | BSER_FALSE | BSER_NULL => self.bunser.discard(),
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::Value, ch)),
+        }
+        Ok(())
+    }
+
+    fn skip_string(&mut self) -> Result<()> {
+        match self.bunser.peek()? {
+            BSER_BYTESTRING | BSER_UTF8STRING => self.bunser.discard(),
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::String, ch)),
+        }
+        let len = self.string_len()?;
+        self.bunser.read_bytes(len as i64)?;
+        Ok(())
+    }
+
+    /// Pass the encoding of the next value, rather than the value itself,
+    /// to the visitor.
+    fn visit_raw<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        self.bunser.begin_raw();
+        self.skip_value()?;
+        match self.bunser.end_raw() {
+            Reference::Borrowed(bytes) => visitor.visit_borrowed_bytes(bytes),
+            Reference::Copied(bytes) => visitor.visit_bytes(bytes),
+        }
+    }
+
+    /// Pass the encoding of the next value to the visitor, along with the
+    /// limits that apply to it, for it to be decoded as an `ExactValue`.
+    fn visit_exact<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let mut options = DeserializerOptions::new();
+        options.max_depth = self.remaining_depth.remaining();
+        options.max_string_len = self.max_string_len;
+        options.max_container_len = self.max_container_len;
+        self.bunser.begin_raw();
+        self.skip_value()?;
+        visitor.visit_seq(exact::ExactAccess::new(&options, self.bunser.end_raw()))
+    }
+
     fn template_keys(&mut self) -> Result<Vec<template::Key<'de>>> {
         // The list of keys is actually an array, so just use the deserializer
         // to process it.
         de::Deserialize::deserialize(self)
     }
 
+    /// Read a non-negative length.
+    fn length(&mut self) -> Result<usize> {
+        let len = self.bunser.check_next_int()?;
+        if len < 0 {
+            bail!(ErrorKind::DeInvalidLength(len));
+        }
+        Ok(len as usize)
+    }
+
+    /// Read the number of items in an array or object, or rows in a
+    /// template.
+    fn container_len(&mut self) -> Result<usize> {
+        let len = self.length()?;
+        match self.max_container_len {
+            Some(max) if len > max => bail!(ErrorKind::DeContainerTooLong { len, max }),
+            _ => Ok(len),
+        }
+    }
+
+    /// Read the length of a string or bytestring.
+    fn string_len(&mut self) -> Result<usize> {
+        let len = self.length()?;
+        if let Some(max) = self.max_string_len {
+            if len > max {
+                bail!(ErrorKind::DeStringTooLong { len, max });
+            }
+        }
+        // A string can't extend past the end of its PDU.  Checking this
+        // before reading means a bad length can't cause a huge allocation.
+        let end = self.pdu_info.end()?;
+        if len > end.saturating_sub(self.bunser.read_count() as usize) {
+            bail!(ErrorKind::DeUnexpectedEof("bytes/string"));
+        }
+        Ok(len)
+    }
+
     fn visit_bytestring<V>(&mut self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
         self.bunser.discard();
-        let len = self.bunser.check_next_int()?;
-        match self.bunser.read_bytes(len)? {
-            Reference::Borrowed(s) => visitor.visit_borrowed_bytes(s),
+        let len = self.string_len()?;
+        match self.bunser.read_bytes(len as i64)? {
+            Reference::Borrowed(s) => self.share_last(|| visitor.visit_borrowed_bytes(s)),
             Reference::Copied(s) => visitor.visit_bytes(s),
         }
     }
@@ -164,17 +625,27 @@ where
         V: de::Visitor<'de>,
     {
         self.bunser.discard();
-        let len = self.bunser.check_next_int()?;
+        let len = self.string_len()?;
         match self
             .bunser
-            .read_bytes(len)?
-            .map_result(|x| str::from_utf8(x))?
+            .read_bytes(len as i64)?
+            .map_result(str::from_utf8)?
         {
-            Reference::Borrowed(s) => visitor.visit_borrowed_str(s),
+            Reference::Borrowed(s) => self.share_last(|| visitor.visit_borrowed_str(s)),
             Reference::Copied(s) => visitor.visit_str(s),
         }
     }
 
+    /// Run `f`, which passes the bytes just read to a visitor, offering
+    /// them to a `BytesString` visitor as a slice of the input if the input
+    /// is held in a `Bytes`.
+    fn share_last<T>(&self, f: impl FnOnce() -> T) -> T {
+        match self.bunser.share_last() {
+            Some(shared) => bytestring::offer(shared, f),
+            None => f(),
+        }
+    }
+
     #[inline]
     fn visit_bool<V>(&mut self, visitor: V, value: bool) -> Result<V::Value>
     where
@@ -194,7 +665,7 @@ where
     }
 }
 
-impl<'de, 'a, R> de::Deserializer<'de> for &'a mut Deserializer<R>
+impl<'de, R> de::Deserializer<'de> for &mut Deserializer<R>
 where
     R: DeRead<'de>,
 {
@@ -205,7 +676,7 @@ where
     where
         V: de::Visitor<'de>,
     {
-        self.parse_value(visitor)
+        self.at_current_offset(|de| de.parse_value(visitor))
     }
 
     /// Parse a `null` as a None, and anything else as a `Some(...)`.
@@ -214,24 +685,58 @@ where
     where
         V: de::Visitor<'de>,
     {
-        match self.bunser.peek()? {
+        self.at_current_offset(|de| match de.bunser.peek()? {
             BSER_NULL => {
-                self.bunser.discard();
+                de.bunser.discard();
                 visitor.visit_none()
             }
-            _ => visitor.visit_some(self),
-        }
+            _ => visitor.visit_some(de),
+        })
     }
 
     #[inline]
-    fn deserialize_newtype_struct<V>(self, _name: &str, visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, name: &str, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
+        if name == raw::TOKEN {
+            return self.at_current_offset(|de| de.visit_raw(visitor));
+        }
+        if name == exact::TOKEN {
+            return self.at_current_offset(|de| de.visit_exact(visitor));
+        }
         // This is e.g. E(T). Ignore the E.
         visitor.visit_newtype_struct(self)
     }
 
+    /// Integers are signed in BSER, so depending on `U64Policy` negative
+    /// integers may be reinterpreted as large `u64`s.
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        if self.u64_policy == U64Policy::Reject {
+            return self.deserialize_any(visitor);
+        }
+        self.at_current_offset(|de| match de.bunser.peek()? {
+            BSER_INT8 | BSER_INT16 | BSER_INT32 | BSER_INT64 => {
+                visitor.visit_u64(de.bunser.check_next_int()? as u64)
+            }
+            _ => de.parse_value(visitor),
+        })
+    }
+
+    /// Skip the value without decoding it.
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        self.at_current_offset(|de| {
+            de.skip_value()?;
+            visitor.visit_unit()
+        })
+    }
+
     /// Parse an enum as an object like {key: value}, or a unit variant as just
     /// a value.
     #[inline]
@@ -244,36 +749,53 @@ where
     where
         V: de::Visitor<'de>,
     {
-        match self.bunser.peek()? {
+        self.at_current_offset(|de| match de.bunser.peek()? {
             BSER_BYTESTRING | BSER_UTF8STRING => {
-                visitor.visit_enum(variant::UnitVariantAccess::new(self))
+                visitor.visit_enum(variant::UnitVariantAccess::new(de))
             }
             BSER_OBJECT => {
-                let guard = self
+                let guard = de
                     .remaining_depth
                     .acquire(format!("object-like enum '{}'", name))?;
-                self.bunser.discard();
+                de.bunser.discard();
                 // For enum variants the object must have exactly one entry
                 // (named the variant, but serde will perform that check).
-                let nitems = self.bunser.check_next_int()?;
+                let nitems = de.bunser.check_next_int()?;
                 if nitems != 1 {
                     return Err(de::Error::invalid_value(
                         de::Unexpected::Signed(nitems),
                         &"integer `1`",
                     ));
                 }
-                visitor.visit_enum(variant::VariantAccess::new(self, &guard))
+                visitor.visit_enum(variant::VariantAccess::new(de, &guard))
             }
-            ch => bail!(ErrorKind::DeInvalidStartByte(
-                format!("enum '{}'", name),
-                ch
-            )),
-        }
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::Enum(name), ch)),
+        })
     }
 
     forward_to_deserialize_any! {
-        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes
+        bool i8 i16 i32 i64 u8 u16 u32 f32 f64 char str string bytes
         byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
-        ignored_any
     }
 }
+
+/// Deserialize a single value with no PDU header from `bytes`.
+pub(crate) fn from_raw_slice<'de, T>(bytes: &'de [u8]) -> Result<T>
+where
+    T: de::Deserialize<'de>,
+{
+    from_raw_slice_with_options(bytes, &DeserializerOptions::default())
+}
+
+pub(crate) fn from_raw_slice_with_options<'de, T>(
+    bytes: &'de [u8],
+    options: &DeserializerOptions,
+) -> Result<T>
+where
+    T: de::Deserialize<'de>,
+{
+    let mut d = Deserializer::headerless(SliceRead::new(bytes), bytes.len(), options);
+    let value = de::Deserialize::deserialize(&mut d)?;
+    d.end()?;
+    Ok(value)
+}
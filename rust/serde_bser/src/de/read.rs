@@ -1,9 +1,10 @@
 use std::io;
+use std::ops::Range;
 use std::result;
 
 use crate::errors::*;
 use byteorder::{ByteOrder, NativeEndian};
-use error_chain::bail;
+use bytes::Bytes;
 
 #[cfg(feature = "debug_bytes")]
 use std::fmt;
@@ -26,10 +27,10 @@ struct ByteBuf<'a>(&'a [u8]);
 impl<'a> fmt::LowerHex for ByteBuf<'a> {
     fn fmt(&self, fmtr: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
         for byte in self.0 {
-            let val = byte.clone();
-            if (val >= b'-' && val <= b'9')
-                || (val >= b'A' && val <= b'Z')
-                || (val >= b'a' && val <= b'z')
+            let val = *byte;
+            if (b'-'..=b'9').contains(&val)
+                || val.is_ascii_uppercase()
+                || val.is_ascii_lowercase()
                 || val == b'_'
             {
                 fmtr.write_fmt(format_args!("{}", val as char))?;
@@ -51,6 +52,8 @@ pub trait DeRead<'de> {
     fn read_count(&self) -> usize;
     /// discard peeked byte
     fn discard(&mut self);
+    /// whether all of the input has been consumed
+    fn at_eof(&mut self) -> Result<bool>;
     /// read next byte (if peeked byte not discarded include it)
     fn next_bytes<'s>(
         &'s mut self,
@@ -59,22 +62,53 @@ pub trait DeRead<'de> {
     ) -> Result<Reference<'de, 's, [u8]>>;
     /// read u32 as native endian
     fn next_u32(&mut self, scratch: &mut Vec<u8>) -> Result<u32> {
-        let bytes = self
-            .next_bytes(4, scratch)
-            .chain_err(|| "error while parsing u32")?
-            .get_ref();
+        let bytes = self.next_bytes(4, scratch)?.get_ref();
         Ok(NativeEndian::read_u32(bytes))
     }
+    /// start recording the bytes that are read, including the peeked byte
+    fn begin_raw(&mut self);
+    /// stop recording and return the bytes read since `begin_raw`, excluding
+    /// the peeked byte
+    fn end_raw<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Reference<'de, 's, [u8]>;
+    /// the bytes returned by the last call to `next_bytes`, sharing the
+    /// input rather than copying it, if the input is held in a `Bytes`
+    fn share_last(&self) -> Option<Bytes> {
+        None
+    }
 }
 
 pub struct SliceRead<'a> {
     slice: &'a [u8],
     index: usize,
+    raw_start: usize,
 }
 
 impl<'a> SliceRead<'a> {
     pub fn new(slice: &'a [u8]) -> Self {
-        SliceRead { slice, index: 0 }
+        SliceRead {
+            slice,
+            index: 0,
+            raw_start: 0,
+        }
+    }
+}
+
+/// Reads from a `Bytes` buffer.  Bytes read from it are borrowed, as for
+/// `SliceRead`, and can also be shared as slices of the buffer.
+pub struct BytesRead<'a> {
+    bytes: &'a Bytes,
+    read: SliceRead<'a>,
+    /// Where the bytes last returned by `next_bytes` are in the buffer.
+    last: Range<usize>,
+}
+
+impl<'a> BytesRead<'a> {
+    pub fn new(bytes: &'a Bytes) -> Self {
+        BytesRead {
+            bytes,
+            read: SliceRead::new(bytes),
+            last: 0..0,
+        }
     }
 }
 
@@ -86,6 +120,8 @@ where
     read_count: usize,
     /// Temporary storage of peeked byte.
     peeked: Option<u8>,
+    /// The bytes read since `begin_raw`, if recording.
+    raw: Option<Vec<u8>>,
 }
 
 impl<R> IoRead<R>
@@ -98,6 +134,14 @@ where
             reader,
             read_count: 0,
             peeked: None,
+            raw: None,
+        }
+    }
+
+    #[inline]
+    fn record(&mut self, bytes: &[u8]) {
+        if let Some(raw) = self.raw.as_mut() {
+            raw.extend_from_slice(bytes);
         }
     }
 }
@@ -114,7 +158,7 @@ where
 impl<'a> DeRead<'a> for SliceRead<'a> {
     fn next(&mut self) -> Result<u8> {
         if self.index >= self.slice.len() {
-            bail!("eof while reading next byte");
+            bail!(ErrorKind::DeUnexpectedEof("next byte"));
         }
         let ch = self.slice[self.index];
         self.index += 1;
@@ -123,7 +167,7 @@ impl<'a> DeRead<'a> for SliceRead<'a> {
 
     fn peek(&mut self) -> Result<u8> {
         if self.index >= self.slice.len() {
-            bail!("eof while peeking next byte");
+            bail!(ErrorKind::DeUnexpectedEof("next byte"));
         }
         Ok(self.slice[self.index])
     }
@@ -138,6 +182,11 @@ impl<'a> DeRead<'a> for SliceRead<'a> {
         self.index += 1;
     }
 
+    #[inline]
+    fn at_eof(&mut self) -> Result<bool> {
+        Ok(self.index >= self.slice.len())
+    }
+
     fn next_bytes<'s>(
         &'s mut self,
         len: usize,
@@ -146,12 +195,72 @@ impl<'a> DeRead<'a> for SliceRead<'a> {
         // BSER has no escaping or anything similar, so just go ahead and return
         // a reference to the bytes.
         if self.index + len > self.slice.len() {
-            bail!("eof while parsing bytes/string");
+            bail!(ErrorKind::DeUnexpectedEof("bytes/string"));
         }
         let borrowed = &self.slice[self.index..(self.index + len)];
         self.index += len;
         Ok(Reference::Borrowed(borrowed))
     }
+
+    fn begin_raw(&mut self) {
+        self.raw_start = self.index;
+    }
+
+    fn end_raw<'s>(&'s mut self, _scratch: &'s mut Vec<u8>) -> Reference<'a, 's, [u8]> {
+        Reference::Borrowed(&self.slice[self.raw_start..self.index])
+    }
+}
+
+impl<'a> DeRead<'a> for BytesRead<'a> {
+    #[inline]
+    fn next(&mut self) -> Result<u8> {
+        self.read.next()
+    }
+
+    #[inline]
+    fn peek(&mut self) -> Result<u8> {
+        self.read.peek()
+    }
+
+    #[inline]
+    fn read_count(&self) -> usize {
+        self.read.read_count()
+    }
+
+    #[inline]
+    fn discard(&mut self) {
+        self.read.discard()
+    }
+
+    #[inline]
+    fn at_eof(&mut self) -> Result<bool> {
+        self.read.at_eof()
+    }
+
+    fn next_bytes<'s>(
+        &'s mut self,
+        len: usize,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'a, 's, [u8]>> {
+        let start = self.read.read_count();
+        let bytes = self.read.next_bytes(len, scratch)?;
+        self.last = start..start + len;
+        Ok(bytes)
+    }
+
+    #[inline]
+    fn begin_raw(&mut self) {
+        self.read.begin_raw()
+    }
+
+    #[inline]
+    fn end_raw<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Reference<'a, 's, [u8]> {
+        self.read.end_raw(scratch)
+    }
+
+    fn share_last(&self) -> Option<Bytes> {
+        Some(self.bytes.slice(self.last.clone()))
+    }
 }
 
 impl<'de, R> DeRead<'de> for IoRead<R>
@@ -165,6 +274,7 @@ where
                 let mut buffer = [0; 1];
                 self.reader.read_exact(&mut buffer)?;
                 debug_bytes!("{:x}", ByteBuf(&buffer));
+                self.record(&buffer);
                 self.read_count += 1;
                 Ok(buffer[0])
             }
@@ -178,6 +288,7 @@ where
                 let mut buffer = [0; 1];
                 self.reader.read_exact(&mut buffer)?;
                 debug_bytes!("{:x}", ByteBuf(&buffer));
+                self.record(&buffer);
                 self.peeked = Some(buffer[0]);
                 self.read_count += 1;
                 Ok(buffer[0])
@@ -198,6 +309,26 @@ where
         self.peeked = None
     }
 
+    fn at_eof(&mut self) -> Result<bool> {
+        if self.peeked.is_some() {
+            return Ok(false);
+        }
+        let mut buffer = [0; 1];
+        loop {
+            match self.reader.read(&mut buffer) {
+                Ok(0) => return Ok(true),
+                Ok(_) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        debug_bytes!("{:x}", ByteBuf(&buffer));
+        self.record(&buffer);
+        self.peeked = Some(buffer[0]);
+        self.read_count += 1;
+        Ok(false)
+    }
+
     fn next_bytes<'s>(
         &'s mut self,
         len: usize,
@@ -211,6 +342,9 @@ where
         if idx < len {
             self.reader.read_exact(&mut scratch[idx..len])?;
             debug_bytes!("{:x}", ByteBuf(&scratch[idx..len]));
+            if let Some(raw) = self.raw.as_mut() {
+                raw.extend_from_slice(&scratch[idx..len]);
+            }
             self.read_count += len - idx;
         }
         if let Some(peeked) = self.peeked.take() {
@@ -218,6 +352,78 @@ where
         }
         Ok(Reference::Copied(&scratch[0..len]))
     }
+
+    fn begin_raw(&mut self) {
+        self.raw = Some(self.peeked.into_iter().collect());
+    }
+
+    fn end_raw<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Reference<'de, 's, [u8]> {
+        *scratch = self.raw.take().unwrap_or_default();
+        if self.peeked.is_some() {
+            // The peeked byte belongs to whatever follows
+            scratch.pop();
+        }
+        Reference::Copied(scratch)
+    }
+}
+
+impl<'de, R> DeRead<'de> for &mut R
+where
+    R: DeRead<'de> + ?Sized,
+{
+    #[inline]
+    fn next(&mut self) -> Result<u8> {
+        (**self).next()
+    }
+
+    #[inline]
+    fn peek(&mut self) -> Result<u8> {
+        (**self).peek()
+    }
+
+    #[inline]
+    fn read_count(&self) -> usize {
+        (**self).read_count()
+    }
+
+    #[inline]
+    fn discard(&mut self) {
+        (**self).discard()
+    }
+
+    #[inline]
+    fn at_eof(&mut self) -> Result<bool> {
+        (**self).at_eof()
+    }
+
+    #[inline]
+    fn next_bytes<'s>(
+        &'s mut self,
+        len: usize,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'de, 's, [u8]>> {
+        (**self).next_bytes(len, scratch)
+    }
+
+    #[inline]
+    fn next_u32(&mut self, scratch: &mut Vec<u8>) -> Result<u32> {
+        (**self).next_u32(scratch)
+    }
+
+    #[inline]
+    fn begin_raw(&mut self) {
+        (**self).begin_raw()
+    }
+
+    #[inline]
+    fn end_raw<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Reference<'de, 's, [u8]> {
+        (**self).end_raw(scratch)
+    }
+
+    #[inline]
+    fn share_last(&self) -> Option<Bytes> {
+        (**self).share_last()
+    }
 }
 
 #[derive(Debug)]
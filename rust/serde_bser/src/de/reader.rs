@@ -0,0 +1,305 @@
+use std::borrow::Cow;
+use std::str;
+
+use super::reentrant::ReentrantGuard;
+use super::{DeRead, Deserializer, DeserializerOptions, Reference};
+use crate::errors::*;
+use crate::header::*;
+
+/// An item of BSER read by `BserReader::next_event`.
+///
+/// Arrays and objects start with an event giving the number of items,
+/// and finish with `End`.  Within an object, each value is preceded by
+/// its `Key`.  A template starts with its keys and number of rows, which
+/// is followed by one value or `Skip` for each key of each row in turn,
+/// and finally `End`.
+#[derive(Debug)]
+pub enum Event<'de, 's> {
+    StartArray(usize),
+    StartObject(usize),
+    Template {
+        keys: Vec<Cow<'de, str>>,
+        rows: usize,
+    },
+    Key(Reference<'de, 's, [u8]>),
+    Str(Reference<'de, 's, str>),
+    Bytes(Reference<'de, 's, [u8]>),
+    Int(i64),
+    Real(f64),
+    Bool(bool),
+    Null,
+    /// A template row that has no value for the key.
+    Skip,
+    End,
+}
+
+enum Container {
+    Array,
+    Object { key_next: bool },
+    Template { nkeys: usize, column: usize },
+}
+
+struct Frame {
+    container: Container,
+    /// Items left in an array or object, or rows left in a template.
+    remaining: usize,
+    _guard: ReentrantGuard,
+}
+
+/// What comes next in the input.
+enum Next {
+    Value,
+    Key,
+    Skip,
+    End,
+    Eof,
+}
+
+/// A pull parser that reads a PDU as a series of events, without building
+/// any values.  It applies the same limits as `Deserializer`, including
+/// the recursion limit.
+///
+/// ```
+/// use serde_bser::de::{BserReader, Event, SliceRead};
+///
+/// let pdu = serde_bser::ser::to_vec(vec![1, 2]).unwrap();
+/// let mut reader = BserReader::new(SliceRead::new(&pdu)).unwrap();
+/// let mut sum = 0;
+/// while let Some(event) = reader.next_event().unwrap() {
+///     if let Event::Int(n) = event {
+///         sum += n;
+///     }
+/// }
+/// assert_eq!(sum, 3);
+/// ```
+pub struct BserReader<R> {
+    de: Deserializer<R>,
+    stack: Vec<Frame>,
+    started: bool,
+}
+
+impl<'de, R> BserReader<R>
+where
+    R: DeRead<'de>,
+{
+    /// Read the PDU header and prepare to read the value after it.
+    pub fn new(read: R) -> Result<Self> {
+        Self::with_options(read, &DeserializerOptions::default())
+    }
+
+    pub fn with_options(read: R, options: &DeserializerOptions) -> Result<Self> {
+        Ok(BserReader {
+            de: Deserializer::with_options(read, options)?,
+            stack: Vec::new(),
+            started: false,
+        })
+    }
+
+    /// The capability bits in effect for this PDU.
+    #[inline]
+    pub fn capabilities(&self) -> u32 {
+        self.de.capabilities()
+    }
+
+    /// The BSER version of the PDU being read.
+    #[inline]
+    pub fn bser_version(&self) -> BserVersion {
+        self.de.bser_version()
+    }
+
+    /// Read the next event, or return `None` once the whole PDU has been
+    /// read.
+    pub fn next_event(&mut self) -> Result<Option<Event<'de, '_>>> {
+        let offset = self.de.bunser.read_count() as usize;
+        self.read_event().map_err(|err| err.at_offset(offset))
+    }
+
+    /// Skip the value that `next_event` would start reading, including
+    /// everything inside it.  If the next event would be a key, the key and
+    /// its value are skipped.  It is an error to call this at the end of a
+    /// container or of the PDU.
+    pub fn skip_value(&mut self) -> Result<()> {
+        let offset = self.de.bunser.read_count() as usize;
+        self.skip().map_err(|err| err.at_offset(offset))
+    }
+
+    fn read_event(&mut self) -> Result<Option<Event<'de, '_>>> {
+        match self.advance()? {
+            Next::Value => self.read_value().map(Some),
+            Next::Key => {
+                self.start_string()?;
+                let len = self.de.string_len()?;
+                let key = self.de.bunser.read_bytes(len as i64)?;
+                Ok(Some(Event::Key(key)))
+            }
+            Next::Skip => Ok(Some(Event::Skip)),
+            Next::End => {
+                self.stack.pop();
+                Ok(Some(Event::End))
+            }
+            Next::Eof => {
+                self.de.end()?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn skip(&mut self) -> Result<()> {
+        match self.advance()? {
+            Next::Value => self.de.skip_value(),
+            Next::Key => {
+                self.de.skip_string()?;
+                match self.advance()? {
+                    Next::Value => self.de.skip_value(),
+                    _ => unreachable!("a key is always followed by a value"),
+                }
+            }
+            Next::Skip => Ok(()),
+            Next::End | Next::Eof => bail!(ErrorKind::DeCustom("no value to skip".into())),
+        }
+    }
+
+    /// Work out what comes next, and account for it in the enclosing
+    /// container, unless it is the end of that container.
+    fn advance(&mut self) -> Result<Next> {
+        let frame = match self.stack.last_mut() {
+            Some(frame) => frame,
+            None if self.started => return Ok(Next::Eof),
+            None => {
+                self.started = true;
+                return Ok(Next::Value);
+            }
+        };
+        match &mut frame.container {
+            Container::Array => {
+                if frame.remaining == 0 {
+                    return Ok(Next::End);
+                }
+                frame.remaining -= 1;
+                Ok(Next::Value)
+            }
+            Container::Object { key_next } => {
+                if *key_next {
+                    if frame.remaining == 0 {
+                        return Ok(Next::End);
+                    }
+                    *key_next = false;
+                    Ok(Next::Key)
+                } else {
+                    *key_next = true;
+                    frame.remaining -= 1;
+                    Ok(Next::Value)
+                }
+            }
+            Container::Template { nkeys, column } => {
+                if *nkeys == 0 || (*column == 0 && frame.remaining == 0) {
+                    return Ok(Next::End);
+                }
+                if *column == 0 {
+                    frame.remaining -= 1;
+                }
+                *column = (*column + 1) % *nkeys;
+                match self.de.bunser.peek()? {
+                    BSER_SKIP => {
+                        self.de.bunser.discard();
+                        Ok(Next::Skip)
+                    }
+                    _ => Ok(Next::Value),
+                }
+            }
+        }
+    }
+
+    fn read_value(&mut self) -> Result<Event<'de, '_>> {
+        match self.de.bunser.peek()? {
+            BSER_ARRAY => {
+                let guard = self.de.remaining_depth.acquire("array")?;
+                self.de.bunser.discard();
+                let len = self.de.container_len()?;
+                self.push(Container::Array, len, guard);
+                Ok(Event::StartArray(len))
+            }
+            BSER_OBJECT => {
+                let guard = self.de.remaining_depth.acquire("object")?;
+                self.de.bunser.discard();
+                let len = self.de.container_len()?;
+                self.push(Container::Object { key_next: true }, len, guard);
+                Ok(Event::StartObject(len))
+            }
+            BSER_TEMPLATE => {
+                let guard = self.de.remaining_depth.acquire("template")?;
+                self.de.bunser.discard();
+                let keys: Vec<_> = self
+                    .de
+                    .template_keys()?
+                    .into_iter()
+                    .map(|key| key.0)
+                    .collect();
+                let rows = self.de.container_len()?;
+                let container = Container::Template {
+                    nkeys: keys.len(),
+                    column: 0,
+                };
+                self.push(container, rows, guard);
+                Ok(Event::Template { keys, rows })
+            }
+            BSER_BYTESTRING => {
+                self.de.bunser.discard();
+                let len = self.de.string_len()?;
+                Ok(Event::Bytes(self.de.bunser.read_bytes(len as i64)?))
+            }
+            // As for Deserializer, strings are bytestrings when the peer has
+            // disabled unicode.
+            BSER_UTF8STRING if self.de.unicode_disabled() => {
+                self.de.bunser.discard();
+                let len = self.de.string_len()?;
+                Ok(Event::Bytes(self.de.bunser.read_bytes(len as i64)?))
+            }
+            BSER_UTF8STRING => {
+                self.de.bunser.discard();
+                let len = self.de.string_len()?;
+                let s = self
+                    .de
+                    .bunser
+                    .read_bytes(len as i64)?
+                    .map_result(str::from_utf8)?;
+                Ok(Event::Str(s))
+            }
+            BSER_INT8 | BSER_INT16 | BSER_INT32 | BSER_INT64 => {
+                Ok(Event::Int(self.de.bunser.check_next_int()?))
+            }
+            BSER_REAL => Ok(Event::Real(self.de.bunser.next_f64()?)),
+            BSER_TRUE => {
+                self.de.bunser.discard();
+                Ok(Event::Bool(true))
+            }
+            BSER_FALSE => {
+                self.de.bunser.discard();
+                Ok(Event::Bool(false))
+            }
+            BSER_NULL => {
+                self.de.bunser.discard();
+                Ok(Event::Null)
+            }
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::Value, ch)),
+        }
+    }
+
+    fn start_string(&mut self) -> Result<()> {
+        match self.de.bunser.peek()? {
+            BSER_BYTESTRING | BSER_UTF8STRING => {
+                self.de.bunser.discard();
+                Ok(())
+            }
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::String, ch)),
+        }
+    }
+
+    fn push(&mut self, container: Container, remaining: usize, guard: ReentrantGuard) {
+        self.stack.push(Frame {
+            container,
+            remaining,
+            _guard: guard,
+        });
+    }
+}
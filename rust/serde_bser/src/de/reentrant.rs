@@ -1,6 +1,5 @@
 //! Module to handle reentrant/recursion limits while deserializing.
 
-use error_chain::bail;
 use std::cell::Cell;
 use std::rc::Rc;
 
@@ -25,6 +24,11 @@ impl ReentrantLimit {
         self.0.set(self.0.get() - 1);
         Ok(ReentrantGuard(self.0.clone()))
     }
+
+    /// The number of times the limit can still be acquired.
+    pub fn remaining(&self) -> usize {
+        self.0.get()
+    }
 }
 
 /// RAII guard for reentrant limits.
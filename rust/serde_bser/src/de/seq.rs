@@ -8,6 +8,7 @@ use super::Deserializer;
 
 pub struct SeqAccess<'a, R> {
     de: &'a mut Deserializer<R>,
+    index: usize,
     remaining: usize,
 }
 
@@ -15,12 +16,13 @@ impl<'a, 'de, R> SeqAccess<'a, R>
 where
     R: 'a + DeRead<'de>,
 {
-    /// Create a new `MapAccess`.
+    /// Create a new `SeqAccess`.
     ///
     /// `_guard` makes sure the caller is accounting for the recursion limit.
     pub fn new(de: &'a mut Deserializer<R>, nitems: usize, _guard: &ReentrantGuard) -> Self {
         SeqAccess {
             de,
+            index: 0,
             remaining: nitems,
         }
     }
@@ -40,7 +42,11 @@ where
             Ok(None)
         } else {
             self.remaining -= 1;
-            let value = seed.deserialize(&mut *self.de)?;
+            let index = self.index;
+            self.index += 1;
+            let value = seed
+                .deserialize(&mut *self.de)
+                .map_err(|err| err.in_path(PathSegment::Index(index)))?;
             Ok(Some(value))
         }
     }
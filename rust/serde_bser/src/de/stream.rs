@@ -0,0 +1,119 @@
+use std::marker::PhantomData;
+
+use serde::de;
+
+use super::{DeRead, Deserializer, DeserializerOptions};
+use crate::errors::*;
+
+/// An iterator over the values of a sequence of back-to-back PDUs, such as
+/// a recorded watchman session.
+///
+/// Each PDU yields one `Result<T>`.  Iteration ends when the input is
+/// exhausted at a PDU boundary; input that ends partway through a PDU is
+/// reported as an error.  If a PDU has a valid header but its value can't
+/// be deserialized as `T`, the error is yielded and iteration resumes with
+/// the next PDU.  Any other error ends the iteration.
+///
+/// ```
+/// use serde_bser::de::{SliceRead, StreamDeserializer};
+///
+/// let mut buf = serde_bser::ser::to_vec("hello").unwrap();
+/// buf.extend(serde_bser::ser::to_vec("world").unwrap());
+///
+/// let stream = StreamDeserializer::<_, String>::new(SliceRead::new(&buf));
+/// let values: Vec<String> = stream.map(|v| v.unwrap()).collect();
+/// assert_eq!(values, vec!["hello", "world"]);
+/// ```
+pub struct StreamDeserializer<'de, R, T> {
+    read: R,
+    options: DeserializerOptions,
+    pdu_offset: usize,
+    failed: bool,
+    output: PhantomData<T>,
+    lifetime: PhantomData<&'de ()>,
+}
+
+impl<'de, R, T> StreamDeserializer<'de, R, T>
+where
+    R: DeRead<'de>,
+    T: de::Deserialize<'de>,
+{
+    pub fn new(read: R) -> Self {
+        Self::with_options(read, DeserializerOptions::default())
+    }
+
+    pub fn with_options(read: R, options: DeserializerOptions) -> Self {
+        let pdu_offset = read.read_count();
+        StreamDeserializer {
+            read,
+            options,
+            pdu_offset,
+            failed: false,
+            output: PhantomData,
+            lifetime: PhantomData,
+        }
+    }
+
+    /// The offset of the start of the PDU most recently returned by `next`.
+    pub fn pdu_offset(&self) -> usize {
+        self.pdu_offset
+    }
+
+    /// The number of bytes consumed from the input so far.  Once `next` has
+    /// returned a value this is the offset of the start of the following
+    /// PDU.
+    pub fn byte_offset(&self) -> usize {
+        self.read.read_count()
+    }
+
+    /// Decode the next PDU, clearing `failed` if the input is left
+    /// positioned at the start of the following PDU.
+    fn next_pdu(&mut self) -> Result<Option<T>> {
+        if self.read.at_eof()? {
+            return Ok(None);
+        }
+        self.pdu_offset = self.read.read_count();
+
+        let mut d = Deserializer::with_options(&mut self.read, &self.options)?;
+        let end = d.pdu_info.end()?;
+        let result = de::Deserialize::deserialize(&mut d).and_then(|value| {
+            d.end()?;
+            Ok(value)
+        });
+        drop(d);
+
+        if result.is_ok() {
+            self.failed = false;
+        } else {
+            // Skip the rest of the PDU so that the next one can be read.
+            let consumed = self.read.read_count();
+            if consumed > end {
+                return Err(Error::from(ErrorKind::DeLengthMismatch {
+                    expected: end,
+                    actual: consumed,
+                })
+                .at_offset(self.pdu_offset));
+            }
+            let mut scratch = Vec::new();
+            self.read.next_bytes(end - consumed, &mut scratch)?;
+            self.failed = false;
+        }
+        result.map(Some)
+    }
+}
+
+impl<'de, R, T> Iterator for StreamDeserializer<'de, R, T>
+where
+    R: DeRead<'de>,
+    T: de::Deserialize<'de>,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.failed {
+            return None;
+        }
+        self.failed = true;
+        self.next_pdu().transpose()
+    }
+}
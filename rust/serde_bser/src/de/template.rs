@@ -5,6 +5,8 @@ use serde::{de, forward_to_deserialize_any, Deserialize};
 
 use crate::errors::*;
 use crate::header::*;
+use crate::raw;
+use crate::value::exact;
 
 use super::read::DeRead;
 use super::reentrant::ReentrantGuard;
@@ -18,7 +20,18 @@ use super::Deserializer;
 // identifiers, which are Unicode strings so can't be directly matched up with
 // bytestrings.)
 #[derive(Clone, Debug, Deserialize)]
-pub struct Key<'a>(#[serde(borrow)] Cow<'a, str>);
+pub struct Key<'a>(#[serde(borrow)] pub(super) Cow<'a, str>);
+
+/// A struct whose `Deserialize` impl passes this as its name to
+/// `deserialize_struct` has its fields identified by index in the rows of
+/// templates, as if `DeserializerOptions::template_field_indices` were
+/// enabled for that struct alone.  Only do this if the field list has one
+/// name for each field, in order, which isn't so for derived impls of
+/// structs with `#[serde(alias)]` fields.
+pub const FIELD_INDICES_STRUCT: &str = "$serde_bser::FieldIndices";
+
+/// A list of struct fields, and the index into it of each template key.
+type FieldIndices = (&'static [&'static str], Rc<Vec<Option<u64>>>);
 
 /// A BSER template is logically an array of objects, all with the same or
 /// similar keys.
@@ -41,6 +54,10 @@ pub struct Key<'a>(#[serde(borrow)] Cow<'a, str>);
 pub struct Template<'a, 'de, R> {
     de: &'a mut Deserializer<R>,
     keys: Rc<Vec<Key<'de>>>,
+    /// The struct fields that the keys were last resolved against, and the
+    /// index of the field for each key.
+    field_indices: Option<FieldIndices>,
+    index: usize,
     remaining: usize,
 }
 
@@ -57,6 +74,8 @@ impl<'a, 'de, R: 'a> Template<'a, 'de, R> {
         Template {
             de,
             keys: Rc::new(keys),
+            field_indices: None,
+            index: 0,
             remaining: nitems,
         }
     }
@@ -76,11 +95,16 @@ where
             Ok(None)
         } else {
             self.remaining -= 1;
+            let index = self.index;
+            self.index += 1;
             let obj_de = ObjectDeserializer {
                 de: &mut *self.de,
                 keys: self.keys.clone(),
+                field_indices: &mut self.field_indices,
             };
-            let value = seed.deserialize(obj_de)?;
+            let value = seed
+                .deserialize(obj_de)
+                .map_err(|err| err.in_path(PathSegment::Index(index)))?;
             Ok(Some(value))
         }
     }
@@ -89,6 +113,32 @@ where
 struct ObjectDeserializer<'a, 'de, R> {
     de: &'a mut Deserializer<R>,
     keys: Rc<Vec<Key<'de>>>,
+    field_indices: &'a mut Option<FieldIndices>,
+}
+
+impl<'a, 'de, R> ObjectDeserializer<'a, 'de, R> {
+    /// The index in `fields` of each key, which is only worked out again
+    /// if the fields differ from the previous row's.
+    fn field_indices(&mut self, fields: &'static [&'static str]) -> Rc<Vec<Option<u64>>> {
+        match self.field_indices {
+            Some((cached, indices)) if std::ptr::eq(*cached, fields) => indices.clone(),
+            _ => {
+                let indices = Rc::new(
+                    self.keys
+                        .iter()
+                        .map(|key| {
+                            fields
+                                .iter()
+                                .position(|field| *field == key.0)
+                                .map(|i| i as u64)
+                        })
+                        .collect::<Vec<_>>(),
+                );
+                *self.field_indices = Some((fields, indices.clone()));
+                indices
+            }
+        }
+    }
 }
 
 impl<'a, 'de, R> de::Deserializer<'de> for ObjectDeserializer<'a, 'de, R>
@@ -102,7 +152,27 @@ where
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_map(TemplateObject::new(&mut *self.de, self.keys))
+        visitor.visit_map(TemplateObject::new(&mut *self.de, self.keys, None))
+    }
+
+    /// With `DeserializerOptions::template_field_indices`, or for a
+    /// `FIELD_INDICES_STRUCT`, fields are identified by their index rather
+    /// than by name.
+    fn deserialize_struct<V>(
+        mut self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let indices = if self.de.template_field_indices || name == FIELD_INDICES_STRUCT {
+            Some(self.field_indices(fields))
+        } else {
+            None
+        };
+        visitor.visit_map(TemplateObject::new(&mut *self.de, self.keys, indices))
     }
 
     #[inline]
@@ -123,12 +193,12 @@ where
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_map(TemplateObject::new(&mut *self.de, self.keys))
+        visitor.visit_map(TemplateObject::new(&mut *self.de, self.keys, None))
     }
 
     forward_to_deserialize_any! {
         bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes
-        byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        byte_buf unit unit_struct seq tuple tuple_struct map identifier
         ignored_any option
     }
 }
@@ -136,6 +206,7 @@ where
 struct TemplateObject<'a, 'de, R> {
     de: &'a mut Deserializer<R>,
     keys: Rc<Vec<Key<'de>>>,
+    field_indices: Option<Rc<Vec<Option<u64>>>>,
     cur: usize,
 }
 
@@ -143,8 +214,17 @@ impl<'a, 'de, R> TemplateObject<'a, 'de, R>
 where
     R: 'a + DeRead<'de>,
 {
-    fn new(de: &'a mut Deserializer<R>, keys: Rc<Vec<Key<'de>>>) -> Self {
-        TemplateObject { de, keys, cur: 0 }
+    fn new(
+        de: &'a mut Deserializer<R>,
+        keys: Rc<Vec<Key<'de>>>,
+        field_indices: Option<Rc<Vec<Option<u64>>>>,
+    ) -> Self {
+        TemplateObject {
+            de,
+            keys,
+            field_indices,
+            cur: 0,
+        }
     }
 }
 
@@ -163,8 +243,10 @@ where
         } else {
             let cur = self.cur;
             self.cur += 1;
+            let index = self.field_indices.as_ref().and_then(|indices| indices[cur]);
             let obj_de = KeyDeserializer {
                 key: &self.keys[cur],
+                index,
             };
             let value = seed.deserialize(obj_de)?;
             Ok(Some(value))
@@ -175,12 +257,16 @@ where
     where
         V: de::DeserializeSeed<'de>,
     {
+        let key = &self.keys[self.cur - 1].0;
         seed.deserialize(ValueDeserializer { de: &mut *self.de })
+            .map_err(|err| err.in_path(PathSegment::Key(key.to_string())))
     }
 }
 
 struct KeyDeserializer<'a, 'de: 'a> {
     key: &'a Key<'de>,
+    /// The index of the struct field with this name, if known.
+    index: Option<u64>,
 }
 
 impl<'a, 'de: 'a> de::Deserializer<'de> for KeyDeserializer<'a, 'de> {
@@ -197,9 +283,20 @@ impl<'a, 'de: 'a> de::Deserializer<'de> for KeyDeserializer<'a, 'de> {
         }
     }
 
+    #[inline]
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.index {
+            Some(index) => visitor.visit_u64(index),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
     forward_to_deserialize_any! {
         bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes
-        byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        byte_buf unit unit_struct seq tuple tuple_struct map struct
         ignored_any option enum newtype_struct
     }
 }
@@ -247,19 +344,46 @@ where
     }
 
     #[inline]
-    fn deserialize_newtype_struct<V>(self, _name: &str, visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, name: &str, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
+        for token in [raw::TOKEN, exact::TOKEN] {
+            if name == token && self.de.bunser.peek()? != BSER_SKIP {
+                return self.de.deserialize_newtype_struct(token, visitor);
+            }
+        }
         // This is e.g. E(T). Ignore the E.
         visitor.visit_newtype_struct(self)
     }
 
+    #[inline]
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.de.bunser.peek()? {
+            BSER_SKIP => self.deserialize_any(visitor),
+            _ => self.de.deserialize_u64(visitor),
+        }
+    }
+
+    #[inline]
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.de.bunser.peek()? {
+            BSER_SKIP => self.deserialize_any(visitor),
+            _ => self.de.deserialize_ignored_any(visitor),
+        }
+    }
+
     // TODO: do we also need to do enum here?
 
     forward_to_deserialize_any! {
-        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes
+        bool i8 i16 i32 i64 u8 u16 u32 f32 f64 char str string bytes
         byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
-        ignored_any enum
+        enum
     }
 }
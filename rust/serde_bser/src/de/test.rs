@@ -1,12 +1,20 @@
 use bytes::buf::BufExt;
+use bytes::Bytes;
 use maplit::hashmap;
 use serde::Deserialize;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::Cursor;
 
+use crate::de::{
+    Decoder, Deserializer, DeserializerOptions, IoRead, SliceRead, StreamDeserializer,
+};
 use crate::from_reader;
 use crate::from_slice;
+use crate::from_slice_with_options;
+use crate::header::BSER_CAP_DISABLE_UNICODE;
+use crate::value::Value;
+use crate::BserVersion;
 
 // For "from_reader" data in owned and for "from_slice" data is borrowed
 
@@ -37,6 +45,7 @@ struct TwoBytestrings<'a>(
     #[serde(borrow)] Bytestring<'a>,
 );
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Deserialize, Eq, PartialEq)]
 enum BytestringVariant<'a> {
     TestUnit,
@@ -53,6 +62,7 @@ enum BytestringVariant<'a> {
     },
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Deserialize, Eq, PartialEq)]
 enum StringVariant {
     TestUnit,
@@ -74,6 +84,56 @@ fn test_basic_array() {
     assert_eq!(decoded, expected);
 }
 
+#[test]
+fn test_bser_v1_array() {
+    let bser_v1 = b"\x00\x01\x05\x11\x00\x00\x00\x00\x03\x02\x02\x03\x03Tom\x02\x03\x05Jerry";
+    let decoded = from_slice::<BytestringArray<'_>>(bser_v1).unwrap();
+    assert_eq!(decoded.0, vec![&b"Tom"[..], &b"Jerry"[..]]);
+
+    let reader = Cursor::new(bser_v1.to_vec()).reader();
+    let decoded: Vec<String> = from_reader(reader).unwrap();
+    let expected = vec!["Tom", "Jerry"];
+    assert_eq!(decoded, expected);
+}
+
+#[test]
+fn test_pdu_version() {
+    let bser_v1 = b"\x00\x01\x03\x01\x08";
+    let d = Deserializer::new(SliceRead::new(bser_v1)).unwrap();
+    assert_eq!(d.bser_version(), BserVersion::V1);
+    assert_eq!(d.capabilities(), 0);
+
+    let bser_v2 = b"\x00\x02\x01\x00\x00\x00\x03\x01\x08";
+    let d = Deserializer::new(SliceRead::new(bser_v2)).unwrap();
+    assert_eq!(d.bser_version(), BserVersion::V2);
+    assert_eq!(d.capabilities(), 1);
+
+    let bad_magic = b"\x00\x03\x03\x01\x08";
+    assert!(from_slice::<bool>(bad_magic).is_err());
+}
+
+#[test]
+fn test_disable_unicode_capability() {
+    // "Tom" as a BSER_UTF8STRING, in a PDU with BSER_CAP_DISABLE_UNICODE
+    let bser_v2 = b"\x00\x02\x01\x00\x00\x00\x03\x06\x0d\x03\x03Tom";
+    let decoded: Value = from_slice(bser_v2).unwrap();
+    assert_eq!(decoded, Value::ByteString("Tom".into()));
+
+    // Typed strings are still accepted
+    let decoded: String = from_slice(bser_v2).unwrap();
+    assert_eq!(decoded, "Tom");
+
+    // Without the capability, the string is surfaced as a string ...
+    let bser_v2 = b"\x00\x02\x00\x00\x00\x00\x03\x06\x0d\x03\x03Tom";
+    let decoded: Value = from_slice(bser_v2).unwrap();
+    assert_eq!(decoded, Value::Utf8String("Tom".into()));
+
+    // ... unless the options say otherwise
+    let options = DeserializerOptions::new().capabilities(BSER_CAP_DISABLE_UNICODE);
+    let decoded: Value = from_slice_with_options(bser_v2, &options).unwrap();
+    assert_eq!(decoded, Value::ByteString("Tom".into()));
+}
+
 #[test]
 fn test_basic_object() {
     let bser_v2 =
@@ -498,3 +558,689 @@ fn test_arrays_untagged_enum() {
         })
     );
 }
+
+#[test]
+fn test_stream_deserializer() {
+    let mut buf = vec![];
+    let mut offsets = vec![];
+    for value in &[
+        Value::from("hello"),
+        Value::Integer(42),
+        Value::from("world"),
+    ] {
+        offsets.push(buf.len());
+        buf.extend(crate::ser::to_vec(value).unwrap());
+    }
+
+    let mut stream = StreamDeserializer::<_, String>::new(SliceRead::new(&buf));
+    assert_eq!(stream.next().unwrap().unwrap(), "hello");
+    assert_eq!(stream.pdu_offset(), offsets[0]);
+    assert_eq!(stream.byte_offset(), offsets[1]);
+    // The integer can't be decoded as a string, but that doesn't stop us
+    // from reading the PDU that follows it.
+    assert!(stream.next().unwrap().is_err());
+    assert_eq!(stream.pdu_offset(), offsets[1]);
+    assert_eq!(stream.next().unwrap().unwrap(), "world");
+    assert_eq!(stream.pdu_offset(), offsets[2]);
+    assert!(stream.next().is_none());
+    assert_eq!(stream.byte_offset(), buf.len());
+
+    let reader = IoRead::new(Cursor::new(buf.clone()).reader());
+    let values: Vec<Value> = StreamDeserializer::new(reader)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(values.len(), 3);
+
+    // Input that ends partway through a PDU is an error, after which the
+    // stream is exhausted.
+    let truncated = &buf[..buf.len() - 2];
+    let mut stream = StreamDeserializer::<_, Value>::new(SliceRead::new(truncated));
+    assert!(stream.next().unwrap().is_ok());
+    assert!(stream.next().unwrap().is_ok());
+    assert!(stream.next().unwrap().is_err());
+    assert!(stream.next().is_none());
+}
+
+#[test]
+fn test_decoder() {
+    let mut decoder = Decoder::with_options(DeserializerOptions::new().max_depth(2));
+    let nested = crate::ser::to_vec(vec![vec!["hello"]]).unwrap();
+    let deeper = crate::ser::to_vec(vec![vec![vec!["hello"]]]).unwrap();
+
+    for _ in 0..3 {
+        let value: Vec<Vec<&str>> = decoder.decode(&nested).unwrap();
+        assert_eq!(value, vec![vec!["hello"]]);
+        // Failing partway through a PDU doesn't use up the depth limit
+        // for the PDUs that follow.
+        assert!(decoder.decode::<Value>(&deeper).is_err());
+        assert!(decoder.decode::<Vec<Vec<String>>>(&nested[..3]).is_err());
+    }
+
+    // Reading from a stream keeps the scratch space for the next PDU.
+    let long = "x".repeat(1000);
+    let pdu = crate::ser::to_vec(&long).unwrap();
+    let value: String = decoder.decode_from(Cursor::new(&pdu)).unwrap();
+    assert_eq!(value, long);
+    let capacity = decoder.scratch.capacity();
+    assert!(capacity >= long.len());
+    let value: Vec<Vec<String>> = decoder.decode_from(Cursor::new(&nested)).unwrap();
+    assert_eq!(value, vec![vec!["hello"]]);
+    assert_eq!(decoder.scratch.capacity(), capacity);
+}
+
+#[test]
+fn test_from_bytes() {
+    use crate::bytestring::BytesString;
+    use crate::from_bytes;
+    use crate::Templated;
+
+    #[derive(Debug, Deserialize, serde::Serialize)]
+    struct File {
+        name: BytesString,
+        size: i64,
+    }
+
+    let files = vec![
+        File {
+            name: "foo".into(),
+            size: 1,
+        },
+        File {
+            name: b"caf\xe9".to_vec().into(),
+            size: 2,
+        },
+    ];
+    let within = |frame: &[u8], name: &BytesString| frame.as_ptr_range().contains(&name.as_ptr());
+    for pdu in &[
+        crate::ser::to_vec(&files).unwrap(),
+        crate::ser::to_vec(Templated(&files)).unwrap(),
+    ] {
+        let frame = Bytes::copy_from_slice(pdu);
+        let decoded: Vec<File> = from_bytes(&frame).unwrap();
+        assert_eq!(decoded[0].name.as_bytes(), b"foo");
+        assert_eq!(decoded[1].name.as_bytes(), b"caf\xe9");
+        assert_eq!(decoded[1].size, 2);
+        assert!(decoded.iter().all(|file| within(&frame, &file.name)));
+
+        let mut decoder = Decoder::new();
+        let decoded: Vec<File> = decoder.decode_bytes(&frame).unwrap();
+        assert!(decoded.iter().all(|file| within(&frame, &file.name)));
+
+        // Decoding from a slice copies, even when the slice is that of a
+        // `Bytes` buffer.
+        let decoded: Vec<File> = from_slice(&frame).unwrap();
+        assert!(!decoded.iter().any(|file| within(&frame, &file.name)));
+    }
+
+    // Strings are shared too
+    let frame = Bytes::from(crate::ser::to_vec(vec!["foo"]).unwrap());
+    let decoded: Vec<BytesString> = from_bytes(&frame).unwrap();
+    assert!(within(&frame, &decoded[0]));
+    assert_eq!(format!("{:?}", decoded), r#"["foo"]"#);
+
+    // As are object keys
+    let frame = Bytes::from(crate::ser::to_vec(hashmap! {"foo" => 1}).unwrap());
+    let decoded: HashMap<BytesString, i32> = from_bytes(&frame).unwrap();
+    assert!(decoded.keys().all(|key| within(&frame, key)));
+
+    // Only strings read from the buffer are shared: a `Value` holds its
+    // own copy, so converting from it copies.
+    let decoded: Value = from_bytes(&frame).unwrap();
+    let decoded: HashMap<BytesString, i32> = crate::value::from_value(decoded).unwrap();
+    assert!(!decoded.keys().any(|key| within(&frame, key)));
+}
+
+#[test]
+fn test_pdu_index() {
+    use crate::de::PduIndex;
+    use crate::ser::{to_vec_with_options, SerializerOptions};
+    use crate::ErrorKind;
+
+    let pdus = vec![
+        crate::ser::to_vec("first").unwrap(),
+        to_vec_with_options(
+            "x".repeat(300),
+            &SerializerOptions::new().version(BserVersion::V1),
+        )
+        .unwrap(),
+        to_vec_with_options(
+            vec![1, 2, 3],
+            &SerializerOptions::new().capabilities(BSER_CAP_DISABLE_UNICODE),
+        )
+        .unwrap(),
+    ];
+    let recording = pdus.concat();
+
+    let index = PduIndex::new(&recording).unwrap();
+    assert_eq!(index.len(), 3);
+    let mut offset = 0;
+    for (entry, pdu) in index.iter().zip(&pdus) {
+        assert_eq!(entry.range(), offset..offset + pdu.len());
+        offset += pdu.len();
+    }
+    assert_eq!(index.get(1).unwrap().bser_version, BserVersion::V1);
+    assert_eq!(
+        index.get(2).unwrap().bser_capabilities,
+        BSER_CAP_DISABLE_UNICODE
+    );
+    assert_eq!(index.pdu(&recording, 2).unwrap(), pdus[2].as_slice());
+    assert_eq!(index.decode::<String>(&recording, 1).unwrap().len(), 300);
+    assert_eq!(
+        index.decode::<Vec<i32>>(&recording, 2).unwrap(),
+        vec![1, 2, 3]
+    );
+    assert!(index.decode::<Value>(&recording, 3).is_err());
+
+    // Decoding errors are reported at their offset in the recording; here
+    // that of the array, just after the 8 byte header
+    let err = index.decode::<i32>(&recording, 2).unwrap_err();
+    assert_eq!(err.offset(), Some(index.get(2).unwrap().offset + 8));
+
+    let from_reader = PduIndex::from_reader(Cursor::new(&recording)).unwrap();
+    assert!(from_reader.iter().eq(index.iter()));
+
+    // A recording that ends partway through a PDU is rejected
+    let truncated = &recording[..recording.len() - 1];
+    for err in [
+        PduIndex::new(truncated).unwrap_err(),
+        PduIndex::from_reader(Cursor::new(truncated)).unwrap_err(),
+    ] {
+        assert_eq!(err.offset(), Some(index.get(2).unwrap().offset));
+        assert!(matches!(err.kind(), ErrorKind::DeUnexpectedEof(_)));
+    }
+    let err = PduIndex::new(&recording[..pdus[0].len() + 3]).unwrap_err();
+    assert_eq!(err.offset(), Some(pdus[0].len()));
+
+    // As is one with a length so large that it overflows
+    let mut corrupt = pdus[0].clone();
+    corrupt.extend(b"\x00\x01\x06");
+    corrupt.extend(&i64::MAX.to_ne_bytes());
+    corrupt.extend(&pdus[1]);
+    for err in [
+        PduIndex::new(&corrupt).unwrap_err(),
+        PduIndex::from_reader(Cursor::new(&corrupt)).unwrap_err(),
+    ] {
+        assert_eq!(err.offset(), Some(pdus[0].len()));
+        assert!(matches!(err.kind(), ErrorKind::DeInvalidLength(i64::MAX)));
+    }
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_pdu_file() {
+    use crate::de::PduFile;
+
+    let mut recording = crate::ser::to_vec("first").unwrap();
+    recording.extend(crate::ser::to_vec(vec!["second"]).unwrap());
+    let path = std::env::temp_dir().join(format!("serde_bser-pdu-file-{}", std::process::id()));
+    std::fs::write(&path, &recording).unwrap();
+
+    let file = PduFile::open(&path).unwrap();
+    assert_eq!(file.index().len(), 2);
+    assert_eq!(file.as_slice(), recording.as_slice());
+    let second: Vec<&str> = file.decode(1).unwrap();
+    assert_eq!(second, vec!["second"]);
+    assert_eq!(file.decode::<&str>(0).unwrap(), "first");
+    drop(file);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_error_location() {
+    use crate::header::{BSER_INT8, BSER_UTF8STRING};
+    use crate::{ErrorKind, PathSegment, Templated};
+
+    #[derive(Debug, Deserialize, serde::Serialize)]
+    struct File {
+        name: String,
+        mtime: i64,
+    }
+    #[derive(Debug, Deserialize)]
+    struct StrictFile {
+        #[allow(dead_code)]
+        name: String,
+        #[allow(dead_code)]
+        mtime: String,
+    }
+    #[derive(Debug, Deserialize)]
+    struct Files<T> {
+        #[allow(dead_code)]
+        files: Vec<T>,
+    }
+
+    let pdu = crate::ser::to_vec(hashmap! {
+        "files" => vec![
+        File {
+            name: "foo".into(),
+            mtime: 1,
+        },
+        File {
+            name: "bar".into(),
+            mtime: 2,
+        },
+    ]})
+    .unwrap();
+    let err = from_slice::<Files<StrictFile>>(&pdu).unwrap_err();
+    assert_eq!(
+        err.path().cloned().collect::<Vec<_>>(),
+        vec![
+            PathSegment::Key("files".into()),
+            PathSegment::Index(0),
+            PathSegment::Key("mtime".into()),
+        ]
+    );
+    // The offset is that of the integer that couldn't be decoded.
+    let offset = err.offset().unwrap();
+    assert_eq!(pdu[offset], BSER_INT8);
+    assert!(err
+        .to_string()
+        .ends_with(&format!(" at files[0].mtime (offset {})", offset)));
+
+    // Keys read from a stream, which are copied rather than borrowed, are
+    // reported the same way.
+    let err = crate::from_reader::<_, Files<StrictFile>>(&pdu[..]).unwrap_err();
+    assert_eq!(err.path().count(), 3);
+    assert!(err.to_string().contains(" at files[0].mtime "));
+
+    // Template rows report the row index and key name.
+    let pdu = crate::ser::to_vec(hashmap! {
+        "files" => Templated(vec![
+        File {
+            name: "foo".into(),
+            mtime: 1,
+        },
+        File {
+            name: "bar".into(),
+            mtime: 2,
+        },
+    ])})
+    .unwrap();
+    let err = from_slice::<Files<StrictFile>>(&pdu).unwrap_err();
+    assert!(err.to_string().contains(" at files[0].mtime "));
+
+    // Invalid type tags report the tag that was found.
+    let mut pdu = crate::ser::to_vec(vec!["foo", "bar"]).unwrap();
+    let last_string = pdu.iter().rposition(|&b| b == BSER_UTF8STRING).unwrap();
+    pdu[last_string] = 0x42;
+    let err = from_slice::<Vec<String>>(&pdu).unwrap_err();
+    assert_eq!(err.expected(), Some(crate::Expected::Value));
+    assert_eq!(err.found_tag(), Some(0x42));
+    assert_eq!(err.offset(), Some(last_string));
+    assert!(err.path().eq(&[PathSegment::Index(1)]));
+    assert!(matches!(err.kind(), ErrorKind::DeInvalidStartByte(_, 0x42)));
+}
+
+#[test]
+fn test_limits() {
+    use crate::ErrorKind;
+
+    let pdu = crate::ser::to_vec(vec![vec!["hello"]]).unwrap();
+    let decode = |options: DeserializerOptions| {
+        from_slice_with_options::<Vec<Vec<String>>>(&pdu, &options).map_err(|err| err.into_kind())
+    };
+    assert!(decode(DeserializerOptions::new().max_depth(2)).is_ok());
+    assert!(matches!(
+        decode(DeserializerOptions::new().max_depth(1)),
+        Err(ErrorKind::DeRecursionLimitExceeded(_))
+    ));
+    assert!(matches!(
+        decode(DeserializerOptions::new().max_pdu_len(pdu.len() - 9)),
+        Err(ErrorKind::DePduTooLarge { .. })
+    ));
+    assert!(matches!(
+        decode(DeserializerOptions::new().max_string_len(4)),
+        Err(ErrorKind::DeStringTooLong { len: 5, max: 4 })
+    ));
+    assert!(decode(DeserializerOptions::new().max_container_len(1)).is_ok());
+    assert!(matches!(
+        decode(DeserializerOptions::new().max_container_len(0)),
+        Err(ErrorKind::DeContainerTooLong { len: 1, max: 0 })
+    ));
+
+    // A string length that runs past the end of the PDU is rejected before
+    // anything is allocated for it.
+    let mut pdu = crate::ser::to_vec("hello").unwrap();
+    let len_offset = pdu.len() - 6;
+    assert_eq!(pdu[len_offset], 5);
+    pdu[len_offset] = 0x7f;
+    let err = from_reader::<_, String>(Cursor::new(pdu)).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::DeUnexpectedEof(_)));
+
+    // A PDU length so large that the end of the PDU overflows
+    let pdu = b"\x00\x02\x00\x00\x00\x00\x06\xff\xff\xff\xff\xff\xff\xff\x7f\x02\x03\x01a";
+    for err in [
+        from_slice::<Value>(pdu).unwrap_err(),
+        from_reader::<_, Value>(Cursor::new(pdu)).unwrap_err(),
+    ] {
+        assert!(matches!(err.kind(), ErrorKind::DeInvalidLength(i64::MAX)));
+    }
+
+    // A negative PDU length is invalid, whatever the limit on the length.
+    let pdu = b"\x00\x01\x03\xff\x03\x01a";
+    for options in [
+        DeserializerOptions::new(),
+        DeserializerOptions::new().max_pdu_len(16),
+    ] {
+        let err = from_slice_with_options::<Value>(pdu, &options).unwrap_err();
+        assert_eq!(err.offset(), Some(0));
+        assert!(matches!(err.kind(), ErrorKind::DeInvalidLength(-1)));
+    }
+
+    // A template with no keys claiming i64::MAX rows is rejected rather
+    // than looping without consuming any input, whether it is decoded or
+    // skipped.
+    let pdu =
+        b"\x00\x02\x00\x00\x00\x00\x03\x0d\x0b\x00\x03\x00\x06\xff\xff\xff\xff\xff\xff\xff\x7f";
+    for err in [
+        from_slice::<Value>(pdu).unwrap_err(),
+        from_slice::<serde::de::IgnoredAny>(pdu).unwrap_err(),
+        from_reader::<_, Value>(Cursor::new(pdu)).unwrap_err(),
+    ] {
+        assert!(matches!(err.kind(), ErrorKind::DeUnexpectedEof("template")));
+    }
+}
+
+#[test]
+fn test_raw_value() {
+    use serde::de::Deserialize as _;
+
+    use crate::{RawValue, Templated};
+
+    #[derive(Debug, Deserialize, PartialEq, serde::Serialize)]
+    struct File {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<i64>,
+    }
+    #[derive(Debug, Deserialize, serde::Serialize)]
+    struct Response<'a> {
+        clock: String,
+        #[serde(borrow)]
+        files: RawValue<'a>,
+    }
+    #[derive(Debug, Deserialize)]
+    struct Envelope {
+        clock: String,
+    }
+
+    let files = vec![
+        File {
+            name: "foo".into(),
+            size: Some(1),
+        },
+        File {
+            name: "bar".into(),
+            size: None,
+        },
+    ];
+    for pdu in [
+        crate::ser::to_vec(hashmap! {"clock" => Value::from("c:1")}).unwrap(),
+        crate::ser::to_vec(Response {
+            clock: "c:1".into(),
+            files: RawValue::from_value(&files).unwrap(),
+        })
+        .unwrap(),
+        crate::ser::to_vec(Response {
+            clock: "c:1".into(),
+            files: RawValue::from_value(&Templated(&files)).unwrap(),
+        })
+        .unwrap(),
+    ] {
+        // Unknown fields are skipped without being decoded.
+        let envelope: Envelope = from_slice(&pdu).unwrap();
+        assert_eq!(envelope.clock, "c:1");
+    }
+
+    let pdu = crate::ser::to_vec(Response {
+        clock: "c:1".into(),
+        files: RawValue::from_value(&Templated(&files)).unwrap(),
+    })
+    .unwrap();
+
+    // The raw bytes are borrowed from a slice, and decode later.
+    let response: Response = from_slice(&pdu).unwrap();
+    let start = pdu.as_ptr() as usize;
+    let raw = response.files.as_bytes().as_ptr() as usize;
+    assert!(raw > start && raw < start + pdu.len());
+    assert_eq!(response.files.decode::<Vec<File>>().unwrap(), files);
+    assert_eq!(crate::ser::to_vec(&response).unwrap(), pdu);
+
+    // From a reader, they are copied.
+    let reader = Cursor::new(pdu.clone()).reader();
+    let mut de = Deserializer::new(IoRead::new(reader)).unwrap();
+    let copied = Response::deserialize(&mut de).unwrap();
+    de.end().unwrap();
+    assert_eq!(copied.files, response.files);
+
+    // A raw value in a template row.
+    #[derive(Debug, Deserialize, serde::Serialize)]
+    struct Row<'a> {
+        name: String,
+        #[serde(borrow)]
+        extra: Option<RawValue<'a>>,
+    }
+    let rows = vec![
+        Row {
+            name: "foo".into(),
+            extra: Some(RawValue::from_value(&hashmap! {"a" => 1}).unwrap()),
+        },
+        Row {
+            name: "bar".into(),
+            extra: None,
+        },
+    ];
+    let pdu = crate::ser::to_vec(Templated(&rows)).unwrap();
+    let decoded: Vec<Row> = from_slice(&pdu).unwrap();
+    assert_eq!(decoded[0].extra, rows[0].extra);
+    assert_eq!(decoded[1].extra, None);
+    assert_eq!(
+        decoded[0]
+            .extra
+            .as_ref()
+            .unwrap()
+            .decode::<HashMap<String, i32>>()
+            .unwrap(),
+        hashmap! {"a".to_string() => 1}
+    );
+
+    // Decoding through a Value keeps the contents.
+    let value = crate::value::to_value(&response).unwrap();
+    assert_eq!(value["files"][1]["name"], Value::from("bar"));
+    let raw = RawValue::deserialize(value["files"].clone()).unwrap();
+    assert_eq!(raw.decode::<Vec<File>>().unwrap(), files);
+}
+
+#[test]
+fn test_template_field_indices() {
+    use crate::Templated;
+
+    #[derive(Debug, PartialEq, serde::Serialize)]
+    struct Row {
+        extra: i32,
+        size: i64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
+        name: String,
+    }
+    // The fields are in a different order to the template keys, one is
+    // renamed and one of the keys isn't a field.
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct File {
+        name: String,
+        #[serde(rename = "mode")]
+        perms: Option<u32>,
+        size: i64,
+    }
+
+    let rows = Templated(vec![
+        Row {
+            extra: 1,
+            size: 10,
+            mode: Some(0o644),
+            name: "foo".into(),
+        },
+        Row {
+            extra: 2,
+            size: 20,
+            mode: None,
+            name: "bar".into(),
+        },
+    ]);
+    let pdu = crate::ser::to_vec(&rows).unwrap();
+
+    let expected = vec![
+        File {
+            name: "foo".into(),
+            perms: Some(0o644),
+            size: 10,
+        },
+        File {
+            name: "bar".into(),
+            perms: None,
+            size: 20,
+        },
+    ];
+    let options = DeserializerOptions::new().template_field_indices(true);
+    let decoded: Vec<File> = from_slice_with_options(&pdu, &options).unwrap();
+    assert_eq!(decoded, expected);
+    let decoded: Vec<File> = from_slice(&pdu).unwrap();
+    assert_eq!(decoded, expected);
+
+    // Rows decoded as maps still see the key names.
+    let decoded: Vec<HashMap<String, Value>> = from_slice_with_options(&pdu, &options).unwrap();
+    assert_eq!(decoded[1]["name"], Value::from("bar"));
+
+    // A struct can opt in by name, without the option.
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename = "$serde_bser::FieldIndices")]
+    struct IndexedFile {
+        name: String,
+        #[serde(rename = "mode")]
+        perms: Option<u32>,
+        size: i64,
+    }
+    assert_eq!(crate::de::FIELD_INDICES_STRUCT, "$serde_bser::FieldIndices");
+    let decoded: Vec<IndexedFile> = from_slice(&pdu).unwrap();
+    assert_eq!(decoded[1].name, "bar");
+    assert_eq!(decoded[1].perms, None);
+    assert_eq!(decoded[1].size, 20);
+}
+
+#[test]
+fn test_bser_reader() {
+    use crate::de::{BserReader, DeRead, Event};
+    use crate::{Error, ErrorKind, Templated};
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct File {
+        name: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<i64>,
+    }
+    #[derive(Serialize)]
+    struct Response {
+        files: Templated<Vec<File>>,
+        nested: Vec<Vec<f64>>,
+        fresh: bool,
+    }
+
+    fn describe<'de, R: DeRead<'de>>(reader: &mut BserReader<R>) -> Result<Vec<String>, Error> {
+        let mut events = vec![];
+        while let Some(event) = reader.next_event()? {
+            events.push(match event {
+                Event::StartArray(len) => format!("[{}", len),
+                Event::StartObject(len) => format!("{{{}", len),
+                Event::Template { keys, rows } => format!("T{}{:?}", rows, keys),
+                Event::Key(key) => format!("{}:", String::from_utf8_lossy(key.get_ref())),
+                Event::Str(s) => format!("{:?}", s.get_ref()),
+                Event::Bytes(b) => format!("b{:?}", String::from_utf8_lossy(b.get_ref())),
+                Event::Int(n) => n.to_string(),
+                Event::Real(n) => format!("{:?}", n),
+                Event::Bool(b) => b.to_string(),
+                Event::Null => "null".into(),
+                Event::Skip => "skip".into(),
+                Event::End => "end".into(),
+            });
+        }
+        Ok(events)
+    }
+
+    let response = Response {
+        files: Templated(vec![
+            File {
+                name: "foo",
+                size: Some(10),
+            },
+            File {
+                name: "bar",
+                size: None,
+            },
+        ]),
+        nested: vec![vec![1.5], vec![]],
+        fresh: true,
+    };
+    let pdu = crate::ser::to_vec(&response).unwrap();
+    let expected = vec![
+        "{3",
+        "files:",
+        "T2[\"name\", \"size\"]",
+        "\"foo\"",
+        "10",
+        "\"bar\"",
+        "skip",
+        "end",
+        "nested:",
+        "[2",
+        "[1",
+        "1.5",
+        "end",
+        "[0",
+        "end",
+        "end",
+        "fresh:",
+        "true",
+        "end",
+    ];
+    let mut reader = BserReader::new(SliceRead::new(&pdu)).unwrap();
+    assert_eq!(describe(&mut reader).unwrap(), expected);
+    // Reading past the end keeps returning None
+    assert!(reader.next_event().unwrap().is_none());
+    let mut reader = BserReader::new(IoRead::new(Cursor::new(&pdu))).unwrap();
+    assert_eq!(describe(&mut reader).unwrap(), expected);
+
+    // Skipping the files entry, then the inner arrays
+    let mut reader = BserReader::new(SliceRead::new(&pdu)).unwrap();
+    assert!(matches!(
+        reader.next_event().unwrap(),
+        Some(Event::StartObject(3))
+    ));
+    reader.skip_value().unwrap();
+    assert!(matches!(reader.next_event().unwrap(), Some(Event::Key(_))));
+    assert!(matches!(
+        reader.next_event().unwrap(),
+        Some(Event::StartArray(2))
+    ));
+    reader.skip_value().unwrap();
+    reader.skip_value().unwrap();
+    assert!(reader.skip_value().is_err());
+    assert_eq!(
+        describe(&mut reader).unwrap(),
+        vec!["end", "fresh:", "true", "end"]
+    );
+
+    // The recursion limit applies to both reading and skipping
+    let options = DeserializerOptions::new().max_depth(2);
+    let mut reader = BserReader::with_options(SliceRead::new(&pdu), &options).unwrap();
+    let err = describe(&mut reader).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::DeRecursionLimitExceeded(_)));
+    let mut reader = BserReader::with_options(SliceRead::new(&pdu), &options).unwrap();
+    reader.next_event().unwrap();
+    assert!(reader.skip_value().is_ok());
+    reader.next_event().unwrap();
+    assert!(reader.skip_value().is_err());
+
+    // A truncated PDU is an error rather than the end of the events
+    let mut reader = BserReader::new(SliceRead::new(&pdu[..pdu.len() - 1])).unwrap();
+    assert!(describe(&mut reader).is_err());
+}
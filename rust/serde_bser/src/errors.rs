@@ -1,42 +1,312 @@
 use std::fmt;
+use std::io;
+use std::str;
 
-use error_chain::error_chain;
 use serde::{de, ser};
 
 use crate::header::header_byte_desc;
 
-error_chain! {
-    errors {
-        DeInvalidStartByte(kind: String, byte: u8) {
-            description("while deserializing BSER: invalid start byte")
-            display("while deserializing BSER: invalid start byte for {}: {}",
-                    kind, header_byte_desc(*byte))
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Return early with an error built from an `ErrorKind`.
+macro_rules! bail {
+    ($kind:expr) => {
+        return Err($kind.into())
+    };
+}
+pub(crate) use bail;
+
+/// An error encountered while encoding or decoding BSER.
+///
+/// Errors from decoding carry the offset of the innermost value that
+/// couldn't be decoded, along with the path of array indices and object
+/// keys leading to that value, such as `files[1203].mtime`.
+pub struct Error(Box<ErrorImpl>);
+
+struct ErrorImpl {
+    kind: ErrorKind,
+    offset: Option<usize>,
+    /// Innermost segment first, as the path is built while unwinding.
+    path: Vec<PathSegment>,
+}
+
+/// The different kinds of errors.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A value started with a type tag that isn't valid in that position.
+    /// Holds what was expected, and the tag found.
+    DeInvalidStartByte(Expected, u8),
+    /// The PDU didn't start with a BSER v1 or v2 header.
+    DeInvalidMagic(Vec<u8>),
+    /// A PDU, array, object or string had a negative length.
+    DeInvalidLength(i64),
+    /// The input ended partway through a value.
+    DeUnexpectedEof(&'static str),
+    /// Decoding the value consumed a different number of bytes than the
+    /// length in the PDU header.
+    DeLengthMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// A PDU was longer than `DeserializerOptions::max_pdu_len`.
+    DePduTooLarge {
+        len: usize,
+        max: usize,
+    },
+    /// A string was longer than `DeserializerOptions::max_string_len`.
+    DeStringTooLong {
+        len: usize,
+        max: usize,
+    },
+    /// A container was longer than `DeserializerOptions::max_container_len`.
+    DeContainerTooLong {
+        len: usize,
+        max: usize,
+    },
+    DeCustom(String),
+    DeRecursionLimitExceeded(String),
+    SerCustom(String),
+    SerU64TooBig(u64),
+    Io(io::Error),
+    Utf8(str::Utf8Error),
+}
+
+/// What was expected where an invalid type tag was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Expected {
+    /// Any value.
+    Value,
+    Integer,
+    /// A string or bytestring.
+    String,
+    /// The array of keys at the start of a template.
+    TemplateKeys,
+    MapKey,
+    /// A value of the named enum: a string, or an object with one entry.
+    Enum(&'static str),
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expected::Value => f.write_str("next item"),
+            Expected::Integer => f.write_str("integer"),
+            Expected::String => f.write_str("string"),
+            Expected::TemplateKeys => f.write_str("template keys"),
+            Expected::MapKey => f.write_str("map key"),
+            Expected::Enum(name) => write!(f, "enum '{}'", name),
         }
-        DeCustom(msg: String) {
-            description("error while deserializing BSER")
-            display("error while deserializing BSER: {}", msg)
+    }
+}
+
+/// One step in the path to the value that an error occurred in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathSegment {
+    Index(usize),
+    Key(String),
+}
+
+impl Error {
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
+
+    pub fn into_kind(self) -> ErrorKind {
+        self.0.kind
+    }
+
+    /// The offset in the input of the innermost value that couldn't be
+    /// decoded.
+    pub fn offset(&self) -> Option<usize> {
+        self.0.offset
+    }
+
+    /// The path from the top-level value to the value that couldn't be
+    /// decoded, outermost segment first.
+    pub fn path(&self) -> impl Iterator<Item = &PathSegment> {
+        self.0.path.iter().rev()
+    }
+
+    /// For invalid type tags, what was expected instead.
+    pub fn expected(&self) -> Option<Expected> {
+        match self.0.kind {
+            ErrorKind::DeInvalidStartByte(expected, _) => Some(expected),
+            _ => None,
         }
-        DeRecursionLimitExceeded(kind: String) {
-            description("while deserializing BSER: recursion limit exceeded")
-            display("while deserializing BSER: recursion limit exceeded with {}", kind)
+    }
+
+    /// For invalid type tags, the tag that was found.
+    pub fn found_tag(&self) -> Option<u8> {
+        match self.0.kind {
+            ErrorKind::DeInvalidStartByte(_, tag) => Some(tag),
+            _ => None,
         }
-        SerCustom(msg: String) {
-            description("error while serializing BSER")
-            display("error while serializing BSER: {}", msg)
+    }
+
+    /// Record the offset of the value being decoded, unless a more precise
+    /// offset has already been recorded.
+    pub(crate) fn at_offset(mut self, offset: usize) -> Self {
+        if self.0.offset.is_none() {
+            self.0.offset = Some(offset);
         }
-        SerNeedSize(kind: &'static str) {
-            description("while serializing BSER: need size")
-            display("while serializing BSER: need size of {}", kind)
+        self
+    }
+
+    /// Make the recorded offset relative to a larger input, in which the
+    /// input that was being decoded starts at `base`.
+    pub(crate) fn offset_by(mut self, base: usize) -> Self {
+        if let Some(offset) = &mut self.0.offset {
+            *offset += base;
         }
-        SerU64TooBig(v: u64) {
-            description("while serializing BSER: integer too big")
-            display("while serializing BSER: integer too big: {}", v)
+        self
+    }
+
+    /// Record that the error occurred within `segment` of the enclosing
+    /// value.
+    pub(crate) fn in_path(mut self, segment: PathSegment) -> Self {
+        self.0.path.push(segment);
+        self
+    }
+
+    fn path_string(&self) -> String {
+        let mut path = String::new();
+        for segment in self.path() {
+            match segment {
+                PathSegment::Index(i) => path.push_str(&format!("[{}]", i)),
+                PathSegment::Key(key) => {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(key);
+                }
+            }
         }
+        path
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error(Box::new(ErrorImpl {
+            kind,
+            offset: None,
+            path: vec![],
+        }))
     }
+}
 
-    foreign_links {
-        Io(::std::io::Error);
-        Utf8(::std::str::Utf8Error);
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        ErrorKind::Io(err).into()
+    }
+}
+
+impl From<str::Utf8Error> for Error {
+    fn from(err: str::Utf8Error) -> Self {
+        ErrorKind::Utf8(err).into()
+    }
+}
+
+/// Converts to an `io::Error`, unwrapping I/O errors and reporting anything
+/// else as `InvalidData`.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err.0.kind {
+            ErrorKind::Io(err) => err,
+            _ => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::DeInvalidStartByte(kind, byte) => write!(
+                f,
+                "while deserializing BSER: invalid start byte for {}: {}",
+                kind,
+                header_byte_desc(*byte)
+            ),
+            ErrorKind::DeInvalidMagic(magic) => {
+                write!(
+                    f,
+                    "while deserializing BSER: invalid magic header {:?}",
+                    magic
+                )
+            }
+            ErrorKind::DeInvalidLength(len) => {
+                write!(f, "while deserializing BSER: invalid length {}", len)
+            }
+            ErrorKind::DeUnexpectedEof(what) => {
+                write!(f, "while deserializing BSER: eof while reading {}", what)
+            }
+            ErrorKind::DeLengthMismatch { expected, actual } => write!(
+                f,
+                "while deserializing BSER: expected {} bytes read, but read {} bytes",
+                expected, actual
+            ),
+            ErrorKind::DePduTooLarge { len, max } => write!(
+                f,
+                "while deserializing BSER: PDU length {} exceeds the limit of {}",
+                len, max
+            ),
+            ErrorKind::DeStringTooLong { len, max } => write!(
+                f,
+                "while deserializing BSER: string length {} exceeds the limit of {}",
+                len, max
+            ),
+            ErrorKind::DeContainerTooLong { len, max } => write!(
+                f,
+                "while deserializing BSER: container length {} exceeds the limit of {}",
+                len, max
+            ),
+            ErrorKind::DeCustom(msg) => write!(f, "error while deserializing BSER: {}", msg),
+            ErrorKind::DeRecursionLimitExceeded(kind) => write!(
+                f,
+                "while deserializing BSER: recursion limit exceeded with {}",
+                kind
+            ),
+            ErrorKind::SerCustom(msg) => write!(f, "error while serializing BSER: {}", msg),
+            ErrorKind::SerU64TooBig(v) => {
+                write!(f, "while serializing BSER: integer too big: {}", v)
+            }
+            ErrorKind::Io(err) => err.fmt(f),
+            ErrorKind::Utf8(err) => err.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.kind.fmt(f)?;
+        match (self.0.path.is_empty(), self.0.offset) {
+            (true, None) => Ok(()),
+            (true, Some(offset)) => write!(f, " at offset {}", offset),
+            (false, None) => write!(f, " at {}", self.path_string()),
+            (false, Some(offset)) => write!(f, " at {} (offset {})", self.path_string(), offset),
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Error")
+            .field("kind", &self.0.kind)
+            .field("offset", &self.0.offset)
+            .field("path", &self.path_string())
+            .finish()
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.0.kind {
+            ErrorKind::Io(err) => Some(err),
+            ErrorKind::Utf8(err) => Some(err),
+            _ => None,
+        }
     }
 }
 
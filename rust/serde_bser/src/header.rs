@@ -1,6 +1,7 @@
 //! Header constants for BSER.
 
-pub const EMPTY_HEADER: &[u8] = b"\x00\x02\x00\x00\x00\x00\x05\x00\x00\x00\x00";
+pub const BSER_V1_MAGIC: &[u8] = b"\x00\x01";
+pub const BSER_V2_MAGIC: &[u8] = b"\x00\x02";
 
 pub const BSER_ARRAY: u8 = 0x00;
 pub const BSER_OBJECT: u8 = 0x01;
@@ -19,10 +20,64 @@ pub const BSER_UTF8STRING: u8 = 0x0d;
 
 // Capabilities (we would ideally want to use EnumSet here, but
 // https://github.com/contain-rs/enum-set/issues/21 stops us)
-#[allow(unused)]
-pub const BSER_CAP_DISABLE_UNICODE: u8 = 0x01;
-#[allow(unused)]
-pub const BSER_CAP_DISABLE_UNICODE_FOR_ERRORS: u8 = 0x02;
+pub const BSER_CAP_DISABLE_UNICODE: u32 = 0x01;
+pub const BSER_CAP_DISABLE_UNICODE_FOR_ERRORS: u32 = 0x02;
+
+/// The version of the BSER protocol used to encode a PDU.
+///
+/// Version 1 PDUs carry no capabilities and encode all strings as
+/// `BSER_BYTESTRING`. Version 2 adds a capabilities word to the PDU header
+/// and introduces `BSER_UTF8STRING`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BserVersion {
+    V1,
+    #[default]
+    V2,
+}
+
+impl BserVersion {
+    /// Returns the magic bytes that start a PDU of this version.
+    pub fn magic(self) -> &'static [u8] {
+        match self {
+            BserVersion::V1 => BSER_V1_MAGIC,
+            BserVersion::V2 => BSER_V2_MAGIC,
+        }
+    }
+}
+
+/// How unsigned 64-bit integers that don't fit in an `i64` are handled.
+///
+/// BSER integers are signed, so the values above `i64::MAX` have no
+/// encoding of their own.  The watchman server stores inode and device
+/// numbers, which the OS reports as `u64`, by casting them to `i64`: large
+/// values arrive as negative integers, and casting back to `u64` recovers
+/// them exactly.
+///
+/// ```
+/// use serde_bser::de::{from_slice_with_options, DeserializerOptions};
+/// use serde_bser::ser::{to_vec_with_options, SerializerOptions};
+/// use serde_bser::U64Policy;
+///
+/// let ino = u64::MAX - 1;
+/// assert!(serde_bser::ser::to_vec(ino).is_err());
+///
+/// let ser_options = SerializerOptions::new().u64_policy(U64Policy::Reinterpret);
+/// let pdu = to_vec_with_options(ino, &ser_options).unwrap();
+/// assert_eq!(serde_bser::from_slice::<i64>(&pdu).unwrap(), -2);
+///
+/// let de_options = DeserializerOptions::new().u64_policy(U64Policy::Reinterpret);
+/// assert_eq!(from_slice_with_options::<u64>(&pdu, &de_options).unwrap(), ino);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum U64Policy {
+    /// Serializing a `u64` above `i64::MAX` or deserializing a negative
+    /// integer as a `u64` is an error.
+    #[default]
+    Reject,
+    /// Values are cast between `u64` and `i64`, preserving their bits, as
+    /// the watchman server does for inode and device numbers.
+    Reinterpret,
+}
 
 pub fn header_byte_desc(byte: u8) -> String {
     match byte {
@@ -1,9 +1,20 @@
 pub mod bytestring;
+#[cfg(feature = "codec")]
+pub mod codec;
 pub mod de;
 mod errors;
-mod header;
+pub mod header;
+mod raw;
 pub mod ser;
+#[cfg(feature = "json")]
+pub mod transcode;
 pub mod value;
 
 pub use crate::de::from_reader;
 pub use crate::de::from_slice;
+pub use crate::de::{from_bytes, from_bytes_with_options};
+pub use crate::de::{from_reader_with_options, from_slice_with_options};
+pub use crate::errors::{Error, ErrorKind, Expected, PathSegment, Result};
+pub use crate::header::{BserVersion, U64Policy};
+pub use crate::raw::RawValue;
+pub use crate::ser::Templated;
@@ -0,0 +1,336 @@
+//! Support for passing already-encoded BSER through serde.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::de::DeserializerOptions;
+use crate::errors::*;
+use crate::value::Value;
+
+/// The name of the newtype struct that pre-encoded values serialize and
+/// deserialize as.
+///
+/// When serializing, the BSER serializer writes the bytes of the wrapped
+/// `serialize_bytes` call verbatim.  When deserializing, the BSER
+/// deserializer passes the encoded bytes of the next value to
+/// `visit_bytes`/`visit_borrowed_bytes` instead of decoding it.
+pub(crate) const TOKEN: &str = "$serde_bser::private::Raw";
+
+/// Serializes encoded BSER as bytes, for wrapping in a `TOKEN` newtype.
+pub(crate) struct RawBytes<'a>(pub &'a [u8]);
+
+impl<'a> Serialize for RawBytes<'a> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// A BSER value that is kept in its encoded form, to be decoded later.
+///
+/// Deserializing a `RawValue` captures the encoding of the next value
+/// without decoding it, which makes it cheap to decode the parts of a PDU
+/// that are needed straight away and defer the rest.  When deserializing
+/// with `from_slice` the bytes are borrowed from the input; mark fields
+/// with `#[serde(borrow)]` to take advantage of that.  Serializing a
+/// `RawValue` writes the bytes back out as they are.
+///
+/// Any value can be captured, including a `BSER_TEMPLATE`, which decodes
+/// as a sequence.  The encoding doesn't include a PDU header, so it is
+/// decoded with default capabilities unless `decode_with_options` says
+/// otherwise.
+///
+/// ```
+/// use serde::Deserialize;
+/// use serde_bser::RawValue;
+///
+/// #[derive(Deserialize)]
+/// struct Envelope<'a> {
+///     version: String,
+///     #[serde(borrow)]
+///     files: RawValue<'a>,
+/// }
+///
+/// let pdu = serde_bser::ser::to_vec(maplit::hashmap! {
+///     "version" => serde_bser::value::Value::from("2022.01.01"),
+///     "files" => serde_bser::value::Value::from(vec!["foo.rs".into(), "bar.rs".into()]),
+/// })
+/// .unwrap();
+/// let envelope: Envelope = serde_bser::from_slice(&pdu).unwrap();
+/// assert_eq!(envelope.version, "2022.01.01");
+/// let files: Vec<String> = envelope.files.decode().unwrap();
+/// assert_eq!(files, vec!["foo.rs", "bar.rs"]);
+/// ```
+///
+/// Only serde_bser's serializers and deserializers understand the encoded
+/// form.  Other formats see the encoded bytes when serializing, and
+/// deserializing from them goes through a `Value`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawValue<'a>(Cow<'a, [u8]>);
+
+impl<'a> RawValue<'a> {
+    /// Encode `value`.
+    pub fn from_value<T>(value: &T) -> Result<RawValue<'static>>
+    where
+        T: ?Sized + Serialize,
+    {
+        crate::ser::to_raw_vec(value).map(|bytes| RawValue(Cow::Owned(bytes)))
+    }
+
+    /// The encoded value, without a PDU header.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Copy any borrowed data to produce an owned `RawValue`.
+    pub fn into_owned(self) -> RawValue<'static> {
+        RawValue(Cow::Owned(self.0.into_owned()))
+    }
+
+    /// Decode the value as a `T`.
+    pub fn decode<'de, T>(&'de self) -> Result<T>
+    where
+        T: Deserialize<'de>,
+    {
+        self.decode_with_options(&DeserializerOptions::default())
+    }
+
+    /// Decode the value as a `T`.  The PDU length limit in `options` doesn't
+    /// apply, but the capabilities and other limits do.
+    pub fn decode_with_options<'de, T>(&'de self, options: &DeserializerOptions) -> Result<T>
+    where
+        T: Deserialize<'de>,
+    {
+        crate::de::from_raw_slice_with_options(&self.0, options)
+    }
+}
+
+impl<'a> Serialize for RawValue<'a> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_newtype_struct(TOKEN, &RawBytes(&self.0))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for RawValue<'a> {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct RawValueVisitor<'a>(std::marker::PhantomData<&'a ()>);
+
+        impl<'de: 'a, 'a> Visitor<'de> for RawValueVisitor<'a> {
+            type Value = RawValue<'a>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("any valid BSER value")
+            }
+
+            fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> std::result::Result<Self::Value, E> {
+                Ok(RawValue(Cow::Borrowed(v)))
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Self::Value, E> {
+                Ok(RawValue(Cow::Owned(v.to_vec())))
+            }
+
+            fn visit_newtype_struct<D>(
+                self,
+                deserializer: D,
+            ) -> std::result::Result<Self::Value, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                let value = Value::deserialize(deserializer)?;
+                RawValue::from_value(&value).map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(TOKEN, RawValueVisitor(std::marker::PhantomData))
+    }
+}
+
+/// Serialize the contents of a `TOKEN` newtype, appending the encoded bytes
+/// to `out`.
+pub(crate) fn write<T>(out: &mut Vec<u8>, value: &T) -> Result<()>
+where
+    T: ?Sized + Serialize,
+{
+    value.serialize(RawSink(out))
+}
+
+fn not_raw() -> Error {
+    ErrorKind::SerCustom("expected pre-encoded BSER bytes".into()).into()
+}
+
+/// A serializer that only accepts bytes, which it appends to the buffer.
+struct RawSink<'a>(&'a mut Vec<u8>);
+
+impl<'a> ser::Serializer for RawSink<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Impossible<(), Error>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.0.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_char(self, _v: char) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(not_raw())
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        Err(not_raw())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, _value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(not_raw())
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(not_raw())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(not_raw())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(not_raw())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(not_raw())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(not_raw())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(not_raw())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(not_raw())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(not_raw())
+    }
+}
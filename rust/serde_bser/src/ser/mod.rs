@@ -1,47 +1,286 @@
-mod count_write;
+mod template;
 #[cfg(test)]
 mod test;
 
 use std::io;
+use std::mem;
 
-use bytes::BufMut;
-use serde::ser::{self, Serialize};
+use byteorder::{NativeEndian, WriteBytesExt};
+use bytes::{Buf, BufMut, Bytes};
+use serde::ser;
 
 use crate::errors::*;
 use crate::header::*;
+use crate::raw;
 
-use self::count_write::CountWrite;
+pub use self::template::Templated;
 
-// How full must the buffer get before we start flushing it?
-const HIGHWATER: usize = 4096;
+/// Options that control how a PDU is encoded.
+///
+/// ```
+/// use serde_bser::header::BSER_CAP_DISABLE_UNICODE;
+/// use serde_bser::ser::{serialize_with_options, SerializerOptions};
+///
+/// let options = SerializerOptions::new().capabilities(BSER_CAP_DISABLE_UNICODE);
+/// let pdu = serialize_with_options(Vec::new(), "hello", &options).unwrap();
+/// assert_eq!(&pdu[2..6], &[1, 0, 0, 0]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SerializerOptions {
+    version: BserVersion,
+    capabilities: u32,
+    u64_policy: U64Policy,
+}
+
+impl SerializerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the BSER version of the PDU. Defaults to `BserVersion::V2`.
+    pub fn version(mut self, version: BserVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Set the capability bits (`BSER_CAP_*`) written into the PDU header.
+    /// Capabilities are only representable in BSER v2 PDUs.
+    ///
+    /// With `BSER_CAP_DISABLE_UNICODE`, strings are encoded as
+    /// `BSER_BYTESTRING` rather than `BSER_UTF8STRING`, matching the
+    /// behavior of the watchman server.
+    pub fn capabilities(mut self, capabilities: u32) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
 
-pub fn serialize<W, T>(mut writer: W, value: T) -> Result<W>
+    /// Set how a `u64` above `i64::MAX` is encoded.  Defaults to
+    /// `U64Policy::Reject`.
+    pub fn u64_policy(mut self, u64_policy: U64Policy) -> Self {
+        self.u64_policy = u64_policy;
+        self
+    }
+
+    #[inline]
+    fn unicode_disabled(&self) -> bool {
+        self.version == BserVersion::V1 || self.capabilities & BSER_CAP_DISABLE_UNICODE != 0
+    }
+}
+
+/// Serialize `value` as a PDU and write it to `writer`.
+///
+/// The PDU is encoded into memory first so that its length is known before
+/// anything is written; use an `Encoder` to reuse that memory across many
+/// PDUs.
+pub fn serialize<W, T>(writer: W, value: T) -> Result<W>
 where
     W: io::Write,
     T: ser::Serialize,
 {
-    // For the PDU info we need to first count how many bytes it is going to be.
-    let mut count_serializer = Serializer::new(CountWrite::new());
-    value.serialize(&mut count_serializer)?;
-    let count_write = count_serializer.finish()?;
-    let count = count_write.count();
-
-    // Now write out the first bits of PDU info.
-    // TODO: make this tokio AsyncWrite compatible
-    // TODO: support capabilities
-    writer.write_all(b"\x00\x02\x00\x00\x00\x00")?;
-    let mut serializer = Serializer::new(writer);
-    count.serialize(&mut serializer)?;
-
-    // Finally, serialize the value
+    serialize_with_options(writer, value, &SerializerOptions::default())
+}
+
+/// Serialize `value` as a PDU of the specified BSER version.
+///
+/// Version 1 PDUs have no capabilities field and encode strings as
+/// `BSER_BYTESTRING`, which is what older clients such as pywatchman's
+/// v1 mode expect.
+pub fn serialize_with_version<W, T>(writer: W, value: T, version: BserVersion) -> Result<W>
+where
+    W: io::Write,
+    T: ser::Serialize,
+{
+    serialize_with_options(writer, value, &SerializerOptions::new().version(version))
+}
+
+pub fn serialize_with_options<W, T>(
+    mut writer: W,
+    value: T,
+    options: &SerializerOptions,
+) -> Result<W>
+where
+    W: io::Write,
+    T: ser::Serialize,
+{
+    let mut buf = Vec::new();
+    let start = encode_pdu(&mut buf, value, options)?;
+    writer.write_all(&buf[start..])?;
+    Ok(writer)
+}
+
+/// Serialize `value` as a PDU into a newly allocated buffer.
+///
+/// The header's length isn't known until the body has been encoded, so
+/// the body is shifted down to close the gap left for it. `Encoder::encode`
+/// doesn't need to, as it returns the PDU where it lies in its buffer.
+pub fn to_vec<T>(value: T) -> Result<Vec<u8>>
+where
+    T: ser::Serialize,
+{
+    to_vec_with_options(value, &SerializerOptions::default())
+}
+
+pub fn to_vec_with_options<T>(value: T, options: &SerializerOptions) -> Result<Vec<u8>>
+where
+    T: ser::Serialize,
+{
+    let mut buf = Vec::new();
+    let start = encode_pdu(&mut buf, value, options)?;
+    if start > 0 {
+        buf.copy_within(start.., 0);
+        buf.truncate(buf.len() - start);
+    }
+    Ok(buf)
+}
+
+/// Encodes a series of PDUs into one buffer that is reused for each of
+/// them.
+///
+/// `to_vec` and `serialize` start from an empty buffer every time and grow
+/// it as the value is written.  An `Encoder` keeps its buffer between
+/// PDUs, so once the buffer has grown to fit the largest of them, encoding
+/// doesn't allocate.
+///
+/// ```
+/// use serde_bser::ser::Encoder;
+///
+/// let mut encoder = Encoder::new();
+/// for n in 0..3 {
+///     let pdu = encoder.encode(vec![n]).unwrap();
+///     assert_eq!(serde_bser::from_slice::<Vec<i32>>(pdu).unwrap(), vec![n]);
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Encoder {
+    options: SerializerOptions,
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: SerializerOptions) -> Self {
+        Self {
+            options,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn options(&self) -> &SerializerOptions {
+        &self.options
+    }
+
+    /// Encode `value` as a PDU.  The returned slice is only valid until the
+    /// next PDU is encoded.
+    pub fn encode<T>(&mut self, value: T) -> Result<&[u8]>
+    where
+        T: ser::Serialize,
+    {
+        self.buf.clear();
+        let start = encode_pdu(&mut self.buf, value, &self.options)?;
+        Ok(&self.buf[start..])
+    }
+
+    /// Encode `value` as a PDU that can be kept past the next one, such as
+    /// to be queued for sending.  The PDU takes over the encoder's buffer
+    /// rather than being copied out of it, and the encoder starts the next
+    /// PDU in a new buffer of the same capacity.
+    pub fn encode_bytes<T>(&mut self, value: T) -> Result<Bytes>
+    where
+        T: ser::Serialize,
+    {
+        self.buf.clear();
+        let start = encode_pdu(&mut self.buf, value, &self.options)?;
+        let capacity = self.buf.capacity();
+        let mut pdu = Bytes::from(mem::replace(&mut self.buf, Vec::with_capacity(capacity)));
+        pdu.advance(start);
+        Ok(pdu)
+    }
+
+    /// Encode `value` as a PDU and write it to `writer`.
+    pub fn encode_to<W, T>(&mut self, mut writer: W, value: T) -> Result<W>
+    where
+        W: io::Write,
+        T: ser::Serialize,
+    {
+        writer.write_all(self.encode(value)?)?;
+        Ok(writer)
+    }
+}
+
+/// Encode `value` on its own, without a PDU header.
+pub(crate) fn to_raw_vec<T>(value: T) -> Result<Vec<u8>>
+where
+    T: ser::Serialize,
+{
+    let mut serializer = Serializer::new(vec![], &SerializerOptions::default());
     value.serialize(&mut serializer)?;
-    Ok(serializer.finish()?)
+    Ok(serializer.into_inner())
 }
 
-pub struct Serializer<W> {
-    writer: W,
+/// The largest PDU header: the magic, capabilities and an int64 length.
+const MAX_HEADER_LEN: usize = 2 + 4 + 9;
+
+/// Append `value` to `buf` as a PDU, serializing it only once.
+///
+/// Room for the largest possible header is reserved ahead of the value and
+/// the header is back-filled once the length is known, right-aligned
+/// against the value.  Returns the offset at which the PDU starts; any
+/// bytes between the original end of `buf` and that offset are padding.
+/// On error, `buf` is left holding whatever had been written.
+pub(crate) fn encode_pdu<T>(
+    buf: &mut Vec<u8>,
+    value: T,
+    options: &SerializerOptions,
+) -> Result<usize>
+where
+    T: ser::Serialize,
+{
+    if options.version == BserVersion::V1 && options.capabilities != 0 {
+        bail!(ErrorKind::SerCustom(
+            "capabilities cannot be encoded in a BSER v1 PDU".into()
+        ));
+    }
+
+    let base = buf.len();
+    let body = base + MAX_HEADER_LEN;
+    buf.resize(body, 0);
+    let mut serializer = Serializer::new(mem::take(buf), options);
+    let result = value.serialize(&mut serializer);
+    let end = serializer.scratch.len();
+    if result.is_ok() {
+        // Write the header after the value, then move it into place.
+        serializer
+            .scratch
+            .extend_from_slice(options.version.magic());
+        if options.version == BserVersion::V2 {
+            serializer
+                .scratch
+                .write_u32::<NativeEndian>(options.capabilities)?;
+        }
+        serializer.put_i64((end - body) as i64);
+    }
+    *buf = serializer.into_inner();
+    result?;
+
+    let start = body - (buf.len() - end);
+    buf.copy_within(end.., start);
+    buf.truncate(end);
+    Ok(start)
+}
+
+/// The serializer behind `to_vec`, `serialize` and `Encoder`, which
+/// appends the body of a PDU to an in-memory buffer.
+///
+/// Before 0.3 this was `Serializer<W>`, which wrote to an `io::Write`
+/// and had `flush`, `finish` and `try_finish` methods.  Those are gone, as
+/// nothing is written until the whole PDU has been encoded; to write PDUs
+/// to a stream, use `serialize`, `to_vec` or `Encoder::encode_to`.
+pub struct Serializer {
     scratch: Vec<u8>,
-    offset: usize,
+    options: SerializerOptions,
 }
 
 /// If the value fits in the size specified by `$to`, call the `$put` function.
@@ -49,8 +288,8 @@ pub struct Serializer<W> {
 /// This works for all $val types except for `u64`.
 macro_rules! maybe_put_int {
     ($self:ident, $val:expr, $to:ident, $put:ident) => {
-        let min = $to::min_value() as i64;
-        let max = $to::max_value() as i64;
+        let min = $to::MIN as i64;
+        let max = $to::MAX as i64;
         let val = $val as i64;
         if val >= min && val <= max {
             return $self.$put($val as $to);
@@ -58,59 +297,19 @@ macro_rules! maybe_put_int {
     };
 }
 
-impl<W> Serializer<W>
-where
-    W: io::Write,
-{
-    // Create a new BSER serializer without leading PDU info.
-    fn new(writer: W) -> Self {
+impl Serializer {
+    // Create a new BSER serializer without leading PDU info that appends
+    // to `scratch`.
+    fn new(scratch: Vec<u8>, options: &SerializerOptions) -> Self {
         Serializer {
-            writer,
-            scratch: Vec::with_capacity(HIGHWATER * 2),
-            offset: 0,
-        }
-    }
-
-    /// Write out any internally cached data through to the writer.
-    #[inline]
-    pub fn flush(&mut self) -> io::Result<()> {
-        while self.offset < self.scratch.len() {
-            match self.writer.write(&self.scratch[self.offset..]) {
-                Ok(n) => self.offset += n,
-                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
-                Err(e) => return Err(e),
-            };
-        }
-        self.offset = 0;
-        unsafe {
-            self.scratch.set_len(0);
+            scratch,
+            options: options.clone(),
         }
-        Ok(())
-    }
-
-    /// Finish writing any buffered data and unwrap the inner `Write`.
-    pub fn finish(mut self) -> io::Result<W> {
-        self.flush()?;
-        Ok(self.writer)
     }
 
-    /// Try flushing any buffered data. If successful, unwrap the inner
-    /// `Write`. On failure, return `Self` and the error.
-    #[inline]
-    pub fn try_finish(mut self) -> ::std::result::Result<W, (Self, io::Error)> {
-        match self.flush() {
-            Ok(()) => Ok(self.writer),
-            Err(e) => Err((self, e)),
-        }
-    }
-
-    #[inline]
-    fn maybe_flush(&mut self) -> Result<()> {
-        if self.scratch.len() > HIGHWATER {
-            Ok(self.flush()?)
-        } else {
-            Ok(())
-        }
+    /// Unwrap the buffer holding the encoded data.
+    fn into_inner(self) -> Vec<u8> {
+        self.scratch
     }
 
     #[inline]
@@ -152,31 +351,26 @@ where
 
 macro_rules! write_val {
     ($self:ident, $val:expr) => {{
-        $self.maybe_flush()?;
         $self.scratch.push($val);
         Ok(())
     }};
     ($self:ident, $val:expr, $put:ident) => {{
-        $self.maybe_flush()?;
         $self.$put($val);
         Ok(())
     }};
 }
 
-impl<'a, W> ser::Serializer for &'a mut Serializer<W>
-where
-    W: io::Write,
-{
+impl<'a> ser::Serializer for &'a mut Serializer {
     type Ok = ();
     type Error = Error;
 
-    type SerializeSeq = Compound<'a, W>;
-    type SerializeTuple = Compound<'a, W>;
-    type SerializeTupleStruct = Compound<'a, W>;
-    type SerializeTupleVariant = Compound<'a, W>;
-    type SerializeMap = Compound<'a, W>;
-    type SerializeStruct = Compound<'a, W>;
-    type SerializeStructVariant = Compound<'a, W>;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
 
     #[inline]
     fn serialize_bool(self, value: bool) -> Result<()> {
@@ -229,7 +423,7 @@ where
     fn serialize_u64(self, v: u64) -> Result<()> {
         // maybe_put_int! doesn't work for u64 because it converts to i64
         // internally.
-        if v > (i64::max_value() as u64) {
+        if v > (i64::MAX as u64) && self.options.u64_policy == U64Policy::Reject {
             Err(ErrorKind::SerU64TooBig(v).into())
         } else {
             self.serialize_i64(v as i64)
@@ -243,7 +437,6 @@ where
 
     #[inline]
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.maybe_flush()?;
         self.scratch.push(BSER_REAL);
         #[cfg(target_endian = "little")]
         self.scratch.put_f64_le(v);
@@ -259,7 +452,11 @@ where
 
     #[inline]
     fn serialize_str(self, v: &str) -> Result<()> {
-        self.maybe_flush()?;
+        // BSER_UTF8STRING doesn't exist in BSER v1, and peers that disable
+        // unicode expect every string to be a bytestring.
+        if self.options.unicode_disabled() {
+            return self.serialize_bytes(v.as_bytes());
+        }
         self.scratch.push(BSER_UTF8STRING);
         self.put_i64(v.len() as i64);
         self.scratch.extend_from_slice(v.as_bytes());
@@ -268,7 +465,6 @@ where
 
     #[inline]
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
-        self.maybe_flush()?;
         self.scratch.push(BSER_BYTESTRING);
         self.put_i64(v.len() as i64);
         self.scratch.extend_from_slice(v);
@@ -296,10 +492,16 @@ where
     }
 
     #[inline]
-    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
+        if name == template::TOKEN {
+            return template::serialize(self, value);
+        }
+        if name == raw::TOKEN {
+            return raw::write(&mut self.scratch, value);
+        }
         // This is e.g. E(T). Ignore the E.
         value.serialize(self)
     }
@@ -339,7 +541,10 @@ where
     #[inline]
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
         match len {
-            None => Err(ErrorKind::SerNeedSize("sequence").into()),
+            None => {
+                self.scratch.push(BSER_ARRAY);
+                Ok(Compound::unknown_len(self))
+            }
             Some(len) => self.serialize_tuple(len),
         }
     }
@@ -347,10 +552,9 @@ where
     #[inline]
     fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
         // (A, B, C) etc. Serialize this as an array.
-        self.maybe_flush()?;
         self.scratch.push(BSER_ARRAY);
         self.put_i64(len as i64);
-        Ok(Compound { ser: self })
+        Ok(Compound::new(self))
     }
 
     #[inline]
@@ -372,7 +576,6 @@ where
     ) -> Result<Self::SerializeTupleVariant> {
         // This is e.g. E { N(A, B, C) }, where N is the variant. Serialize this
         // as { variant: [values] }.
-        self.maybe_flush()?;
         self.scratch.push(BSER_OBJECT);
         self.put_i8(1);
         self.serialize_str(variant)?;
@@ -382,18 +585,20 @@ where
     #[inline]
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
         match len {
-            None => Err(ErrorKind::SerNeedSize("map").into()),
+            None => {
+                self.scratch.push(BSER_OBJECT);
+                Ok(Compound::unknown_len(self))
+            }
             Some(len) => self.serialize_struct("", len),
         }
     }
 
     #[inline]
     fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
-        self.maybe_flush()?;
         // BSER objects are serialized as <BSER_OBJECT><len>(<key><value>...).
         self.scratch.push(BSER_OBJECT);
         self.put_i64(len as i64);
-        Ok(Compound { ser: self })
+        Ok(Compound::new(self))
     }
 
     #[inline]
@@ -412,18 +617,65 @@ where
 }
 
 #[doc(hidden)]
-pub struct Compound<'a, W> {
-    ser: &'a mut Serializer<W>,
+pub struct Compound<'a> {
+    ser: &'a mut Serializer,
+    /// For a container whose length wasn't given up front, the offset that
+    /// the length goes at and the number of items so far.
+    unknown_len: Option<(usize, usize)>,
+}
+
+impl<'a> Compound<'a> {
+    #[inline]
+    fn new(ser: &'a mut Serializer) -> Self {
+        Compound {
+            ser,
+            unknown_len: None,
+        }
+    }
+
+    /// Start a container whose length is counted as its items are
+    /// serialized, and inserted ahead of them at the end.
+    #[inline]
+    fn unknown_len(ser: &'a mut Serializer) -> Self {
+        let offset = ser.scratch.len();
+        Compound {
+            ser,
+            unknown_len: Some((offset, 0)),
+        }
+    }
+
+    #[inline]
+    fn count_item(&mut self) {
+        if let Some((_, count)) = self.unknown_len.as_mut() {
+            *count += 1;
+        }
+    }
+
+    #[inline]
+    fn finish(self) -> Result<()> {
+        if let Some((offset, count)) = self.unknown_len {
+            let mut len = Serializer::new(Vec::with_capacity(9), &self.ser.options);
+            len.put_i64(count as i64);
+            self.ser.scratch.splice(offset..offset, len.into_inner());
+        }
+        Ok(())
+    }
 }
 
 macro_rules! impl_compound {
-    ($trait:ty, [$($fns:ident),*]) => {
-        impl<'a, W> $trait for Compound<'a, W>
-            where W: io::Write
-        {
+    ($trait:ty, $item:ident $(, $fns:ident)*) => {
+        impl<'a> $trait for Compound<'a> {
             type Ok = ();
             type Error = Error;
 
+            #[inline]
+            fn $item<T>(&mut self, value: &T) -> Result<()>
+                where T: ?Sized + ser::Serialize
+            {
+                self.count_item();
+                value.serialize(&mut *self.ser)
+            }
+
             $(
                 #[inline]
                 fn $fns<T>(&mut self, value: &T) -> Result<()>
@@ -435,24 +687,21 @@ macro_rules! impl_compound {
 
             #[inline]
             fn end(self) -> Result<()> {
-                Ok(())
+                self.finish()
             }
         }
     }
 }
 
-impl_compound!(ser::SerializeSeq, [serialize_element]);
-impl_compound!(ser::SerializeTuple, [serialize_element]);
-impl_compound!(ser::SerializeTupleStruct, [serialize_field]);
-impl_compound!(ser::SerializeTupleVariant, [serialize_field]);
-impl_compound!(ser::SerializeMap, [serialize_key, serialize_value]);
+impl_compound!(ser::SerializeSeq, serialize_element);
+impl_compound!(ser::SerializeTuple, serialize_element);
+impl_compound!(ser::SerializeTupleStruct, serialize_field);
+impl_compound!(ser::SerializeTupleVariant, serialize_field);
+impl_compound!(ser::SerializeMap, serialize_key, serialize_value);
 
 macro_rules! impl_compound_struct {
     ($trait:ty) => {
-        impl<'a, W> $trait for Compound<'a, W>
-        where
-            W: io::Write,
-        {
+        impl<'a> $trait for Compound<'a> {
             type Ok = ();
             type Error = Error;
 
@@ -0,0 +1,550 @@
+//! Support for emitting the compact `BSER_TEMPLATE` encoding.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::errors::*;
+use crate::header::*;
+
+use super::{Serializer, SerializerOptions};
+
+/// The name of the newtype struct that `Templated` serializes as, which is
+/// how the BSER serializer recognizes it.
+pub(crate) const TOKEN: &str = "$serde_bser::private::Templated";
+
+/// Wraps a sequence of structs or maps so that it is serialized using the
+/// compact `BSER_TEMPLATE` encoding, as the watchman server does for the
+/// list of files in query results.
+///
+/// The template keys are the union of the keys of all of the elements, in
+/// the order in which they are first seen.  Keys that are missing from an
+/// element (for instance, fields skipped by `skip_serializing_if`) are
+/// encoded as `BSER_SKIP`.
+///
+/// Serializers for other formats see the wrapped value unchanged, and
+/// deserializing a `Templated` value accepts both templates and plain
+/// arrays.
+///
+/// ```
+/// use serde::Serialize;
+/// use serde_bser::Templated;
+///
+/// #[derive(Serialize)]
+/// struct File {
+///     name: String,
+///     #[serde(skip_serializing_if = "Option::is_none")]
+///     size: Option<u64>,
+/// }
+///
+/// let files = Templated(vec![
+///     File { name: "foo".into(), size: Some(12) },
+///     File { name: "bar".into(), size: None },
+/// ]);
+/// let pdu = serde_bser::ser::serialize(Vec::new(), &files).unwrap();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Templated<T>(pub T);
+
+impl<T> Templated<T> {
+    /// Consumes the wrapper and returns the sequence.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Serialize for Templated<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_newtype_struct(TOKEN, &self.0)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Templated<T>
+where
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Templated)
+    }
+}
+
+/// Serialize `value`, which must be a sequence of structs or maps, as a
+/// `BSER_TEMPLATE`.
+pub(crate) fn serialize<T>(ser: &mut Serializer, value: &T) -> Result<()>
+where
+    T: ?Sized + Serialize,
+{
+    // The set of keys is only known once every element has been seen, so
+    // the values are encoded into a side buffer first.
+    let mut state = TemplateState::new(&ser.options);
+    value.serialize(&mut state)?;
+    let values = state.values.into_inner();
+
+    ser.scratch.push(BSER_TEMPLATE);
+    ser.scratch.push(BSER_ARRAY);
+    ser.put_i64(state.keys.len() as i64);
+    for key in &state.keys {
+        ser::Serializer::serialize_str(&mut *ser, key)?;
+    }
+
+    ser.put_i64(state.rows.len() as i64);
+    let mut slots: Vec<Option<Range<usize>>> = vec![None; state.keys.len()];
+    for row in &state.rows {
+        for slot in slots.iter_mut() {
+            *slot = None;
+        }
+        for (key, range) in row {
+            slots[*key] = Some(range.clone());
+        }
+        for slot in &slots {
+            match slot {
+                Some(range) => ser.scratch.extend_from_slice(&values[range.clone()]),
+                None => ser.scratch.push(BSER_SKIP),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn unsupported() -> Error {
+    ErrorKind::SerCustom("Templated values must be sequences of structs or maps".into()).into()
+}
+
+struct TemplateState {
+    keys: Vec<String>,
+    key_index: HashMap<String, usize>,
+    /// The encoded values of all of the rows
+    values: Serializer,
+    /// For each row, the key index and location in `values` of each value
+    rows: Vec<Vec<(usize, Range<usize>)>>,
+}
+
+impl TemplateState {
+    fn new(options: &SerializerOptions) -> Self {
+        Self {
+            keys: vec![],
+            key_index: HashMap::new(),
+            values: Serializer::new(vec![], options),
+            rows: vec![],
+        }
+    }
+
+    fn key(&mut self, key: &str) -> usize {
+        match self.key_index.get(key) {
+            Some(idx) => *idx,
+            None => {
+                let idx = self.keys.len();
+                self.keys.push(key.to_string());
+                self.key_index.insert(key.to_string(), idx);
+                idx
+            }
+        }
+    }
+
+    fn add_value<T>(&mut self, key: usize, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let start = self.values.scratch.len();
+        value.serialize(&mut self.values)?;
+        let end = self.values.scratch.len();
+        self.rows
+            .last_mut()
+            .expect("rows are started before values are added")
+            .push((key, start..end));
+        Ok(())
+    }
+}
+
+/// Defines the serializer methods that reject non-container values.
+macro_rules! reject {
+    ($err:ident; $($fn:ident($($ty:ty),*);)*) => {
+        $(
+            fn $fn(self, $(_: $ty),*) -> Result<Self::Ok> {
+                Err($err())
+            }
+        )*
+    };
+}
+
+/// Defines the serializer methods that reject everything apart from
+/// sequences (for the template itself) or maps (for its rows).
+macro_rules! reject_all_but_containers {
+    () => {
+        reject! {
+            unsupported;
+            serialize_bool(bool);
+            serialize_i8(i8);
+            serialize_i16(i16);
+            serialize_i32(i32);
+            serialize_i64(i64);
+            serialize_u8(u8);
+            serialize_u16(u16);
+            serialize_u32(u32);
+            serialize_u64(u64);
+            serialize_f32(f32);
+            serialize_f64(f64);
+            serialize_char(char);
+            serialize_str(&str);
+            serialize_bytes(&[u8]);
+            serialize_none();
+            serialize_unit();
+            serialize_unit_struct(&'static str);
+            serialize_unit_variant(&'static str, u32, &'static str);
+        }
+
+        fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+        where
+            T: ?Sized + Serialize,
+        {
+            value.serialize(self)
+        }
+
+        fn serialize_some<T>(self, value: &T) -> Result<()>
+        where
+            T: ?Sized + Serialize,
+        {
+            value.serialize(self)
+        }
+
+        fn serialize_newtype_variant<T>(
+            self,
+            _name: &'static str,
+            _variant_index: u32,
+            _variant: &'static str,
+            _value: &T,
+        ) -> Result<()>
+        where
+            T: ?Sized + Serialize,
+        {
+            Err(unsupported())
+        }
+
+        fn serialize_tuple_struct(
+            self,
+            _name: &'static str,
+            _len: usize,
+        ) -> Result<Self::SerializeTupleStruct> {
+            Err(unsupported())
+        }
+
+        fn serialize_tuple_variant(
+            self,
+            _name: &'static str,
+            _variant_index: u32,
+            _variant: &'static str,
+            _len: usize,
+        ) -> Result<Self::SerializeTupleVariant> {
+            Err(unsupported())
+        }
+
+        fn serialize_struct_variant(
+            self,
+            _name: &'static str,
+            _variant_index: u32,
+            _variant: &'static str,
+            _len: usize,
+        ) -> Result<Self::SerializeStructVariant> {
+            Err(unsupported())
+        }
+    };
+}
+
+/// Serializes the sequence of rows.
+impl ser::Serializer for &mut TemplateState {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Impossible<(), Error>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    reject_all_but_containers!();
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        if let Some(len) = len {
+            self.rows.reserve(len);
+        }
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(unsupported())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(unsupported())
+    }
+}
+
+impl ser::SerializeSeq for &mut TemplateState {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(RowSerializer { state: self })
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut TemplateState {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Serializes a single row, which must be a struct or a map.
+struct RowSerializer<'a> {
+    state: &'a mut TemplateState,
+}
+
+impl<'a> ser::Serializer for RowSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = RowCompound<'a>;
+    type SerializeStruct = RowCompound<'a>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    reject_all_but_containers!();
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(unsupported())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(unsupported())
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.state
+            .rows
+            .push(Vec::with_capacity(len.unwrap_or_default()));
+        Ok(RowCompound {
+            state: self.state,
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.serialize_map(Some(len))
+    }
+}
+
+struct RowCompound<'a> {
+    state: &'a mut TemplateState,
+    /// The key passed to the most recent `serialize_key` call
+    key: Option<usize>,
+}
+
+impl<'a> ser::SerializeMap for RowCompound<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key = key.serialize(KeySerializer)?;
+        self.key = Some(self.state.key(&key));
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key = self
+            .key
+            .take()
+            .expect("serialize_key is called before serialize_value");
+        self.state.add_value(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeStruct for RowCompound<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key = self.state.key(key);
+        self.state.add_value(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Extracts the string form of a map key.
+struct KeySerializer;
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = Error;
+
+    type SerializeSeq = Impossible<String, Error>;
+    type SerializeTuple = Impossible<String, Error>;
+    type SerializeTupleStruct = Impossible<String, Error>;
+    type SerializeTupleVariant = Impossible<String, Error>;
+    type SerializeMap = Impossible<String, Error>;
+    type SerializeStruct = Impossible<String, Error>;
+    type SerializeStructVariant = Impossible<String, Error>;
+
+    fn serialize_str(self, v: &str) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<String>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    reject! {
+        key_must_be_a_string;
+        serialize_bool(bool);
+        serialize_i8(i8);
+        serialize_i16(i16);
+        serialize_i32(i32);
+        serialize_i64(i64);
+        serialize_u8(u8);
+        serialize_u16(u16);
+        serialize_u32(u32);
+        serialize_u64(u64);
+        serialize_f32(f32);
+        serialize_f64(f64);
+        serialize_bytes(&[u8]);
+        serialize_none();
+        serialize_unit();
+        serialize_unit_struct(&'static str);
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<String>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(key_must_be_a_string())
+    }
+}
+
+fn key_must_be_a_string() -> Error {
+    ErrorKind::SerCustom("template keys must be strings".into()).into()
+}
@@ -1,8 +1,18 @@
-use serde::Serialize;
+use maplit::hashmap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::f64::consts;
 
-use super::serialize;
+use super::{
+    encode_pdu, serialize, serialize_with_options, serialize_with_version, to_vec,
+    to_vec_with_options, Encoder, SerializerOptions,
+};
+use crate::de::{Deserializer, SliceRead};
+use crate::header::{BSER_CAP_DISABLE_UNICODE, BSER_CAP_DISABLE_UNICODE_FOR_ERRORS, BSER_TEMPLATE};
+use crate::BserVersion;
+use crate::Templated;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Serialize)]
 enum TestEnum {
     TestUnit,
@@ -37,7 +47,7 @@ fn test_basic_serialize() {
         test_enum: TestEnum::TestUnit,
         test_enum_list: vec![
             TestEnum::TestNewtype(&b"BSER test"[..]),
-            TestEnum::TestTuple(42, i64::max_value() as u64),
+            TestEnum::TestTuple(42, i64::MAX as u64),
             TestEnum::TestStruct {
                 abc: (),
                 def: '\u{1f4a9}',
@@ -49,3 +59,312 @@ fn test_basic_serialize() {
     let out = serialize(out, to_serialize).unwrap();
     assert_eq!(out, BASIC_SERIALIZED);
 }
+
+#[test]
+fn test_to_vec() {
+    // Lengths that need each width of length header
+    for len in &[0, 100, 1000, 100_000] {
+        let value = "x".repeat(*len);
+        let pdu = to_vec(&value).unwrap();
+        assert_eq!(pdu, serialize(Vec::new(), &value).unwrap());
+        assert_eq!(crate::from_slice::<String>(&pdu).unwrap(), value);
+    }
+
+    let options = SerializerOptions::new().version(BserVersion::V1);
+    assert_eq!(
+        to_vec_with_options(vec!["Tom", "*"], &options).unwrap(),
+        b"\x00\x01\x03\x0d\x00\x03\x02\x02\x03\x03Tom\x02\x03\x01*"
+    );
+
+    // Encoding appends to the existing contents of the buffer
+    let mut buf = b"prefix".to_vec();
+    let start = encode_pdu(&mut buf, 42, &SerializerOptions::new()).unwrap();
+    assert_eq!(&buf[..6], b"prefix");
+    assert_eq!(&buf[start..], to_vec(42).unwrap().as_slice());
+}
+
+#[test]
+fn test_encoder() {
+    let mut encoder = Encoder::new();
+    let long = "x".repeat(1000);
+    assert_eq!(
+        encoder.encode(&long).unwrap(),
+        to_vec(&long).unwrap().as_slice()
+    );
+    let capacity = encoder.buf.capacity();
+    for value in &["a", "bb", "ccc"] {
+        assert_eq!(
+            encoder.encode(value).unwrap(),
+            to_vec(value).unwrap().as_slice()
+        );
+    }
+    // A failed encode leaves the encoder usable, and keeps its buffer
+    assert!(encoder.encode(u64::MAX).is_err());
+    assert_eq!(encoder.encode(42).unwrap(), to_vec(42).unwrap().as_slice());
+    assert_eq!(encoder.buf.capacity(), capacity);
+
+    let pdu = encoder.encode_bytes(&long).unwrap();
+    assert_eq!(pdu, to_vec(&long).unwrap());
+    assert_eq!(encoder.buf.capacity(), capacity);
+    assert_eq!(encoder.encode(42).unwrap(), to_vec(42).unwrap().as_slice());
+    assert_eq!(pdu, to_vec(&long).unwrap());
+
+    let out = encoder.encode_to(b"prefix".to_vec(), 42).unwrap();
+    assert_eq!(&out[6..], to_vec(42).unwrap().as_slice());
+
+    let options = SerializerOptions::new().version(BserVersion::V1);
+    let mut encoder = Encoder::with_options(options.clone());
+    assert_eq!(
+        encoder.encode(("Tom", 42)).unwrap(),
+        to_vec_with_options(("Tom", 42), &options)
+            .unwrap()
+            .as_slice()
+    );
+}
+
+#[test]
+fn test_serialize_v1() {
+    let out = serialize_with_version(Vec::new(), ("Tom", 42), BserVersion::V1).unwrap();
+    assert_eq!(out, b"\x00\x01\x03\x0b\x00\x03\x02\x02\x03\x03Tom\x03*");
+
+    let d = Deserializer::new(SliceRead::new(&out)).unwrap();
+    assert_eq!(d.bser_version(), BserVersion::V1);
+    let decoded: (String, i32) = crate::from_slice(&out).unwrap();
+    assert_eq!(decoded, ("Tom".to_string(), 42));
+}
+
+#[test]
+fn test_serialize_capabilities() {
+    let options = SerializerOptions::new().capabilities(BSER_CAP_DISABLE_UNICODE_FOR_ERRORS);
+    let out = serialize_with_options(Vec::new(), "Tom", &options).unwrap();
+    assert_eq!(out, b"\x00\x02\x02\x00\x00\x00\x03\x06\x0d\x03\x03Tom");
+
+    let options = SerializerOptions::new().capabilities(BSER_CAP_DISABLE_UNICODE);
+    let out = serialize_with_options(Vec::new(), "Tom", &options).unwrap();
+    assert_eq!(out, b"\x00\x02\x01\x00\x00\x00\x03\x06\x02\x03\x03Tom");
+
+    let d = Deserializer::new(SliceRead::new(&out)).unwrap();
+    assert_eq!(d.capabilities(), BSER_CAP_DISABLE_UNICODE);
+
+    let options = SerializerOptions::new()
+        .version(BserVersion::V1)
+        .capabilities(BSER_CAP_DISABLE_UNICODE);
+    assert!(serialize_with_options(Vec::new(), "Tom", &options).is_err());
+}
+
+/// Serializes its items without giving their number up front.
+struct Lazy<I>(I);
+
+impl<I> Serialize for Lazy<I>
+where
+    I: Iterator + Clone,
+    I::Item: Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.clone().filter(|_| true))
+    }
+}
+
+#[test]
+fn test_serialize_unknown_len() {
+    let evens = || (0..1000).filter(|n| n % 2 == 0);
+    let expected: Vec<i32> = evens().collect();
+    // The output is the same as when the lengths are known, including the
+    // int16 needed for the inner lengths.
+    let nested = Lazy((0..3).map(|_| Lazy(evens())));
+    let out = to_vec(&nested).unwrap();
+    assert_eq!(out, to_vec(vec![&expected; 3]).unwrap());
+
+    let map: HashMap<String, Vec<i32>> = hashmap! {
+        "evens".into() => expected.clone(),
+        "empty".into() => vec![],
+    };
+    struct LazyMap<'a>(&'a HashMap<String, Vec<i32>>);
+    impl<'a> Serialize for LazyMap<'a> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(self.0.iter().filter(|_| true))
+        }
+    }
+    let out = to_vec(LazyMap(&map)).unwrap();
+    assert_eq!(
+        crate::from_slice::<HashMap<String, Vec<i32>>>(&out).unwrap(),
+        map
+    );
+
+    // Flattened structs are serialized as maps of unknown length.
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Inner {
+        b: i32,
+    }
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Outer {
+        a: String,
+        #[serde(flatten)]
+        inner: Inner,
+    }
+    let outer = Outer {
+        a: "foo".into(),
+        inner: Inner { b: 42 },
+    };
+    let out = to_vec(&outer).unwrap();
+    assert_eq!(crate::from_slice::<Outer>(&out).unwrap(), outer);
+    let rows = to_vec(Templated(vec![&outer])).unwrap();
+    assert_eq!(crate::from_slice::<Vec<Outer>>(&rows).unwrap(), vec![outer]);
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct TemplateRow {
+    abc: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    def: Option<String>,
+    ghi: Option<i64>,
+}
+
+#[test]
+fn test_serialize_template() {
+    let rows = vec![
+        TemplateRow {
+            abc: 123,
+            def: Some("bar".into()),
+            ghi: None,
+        },
+        TemplateRow {
+            abc: 456,
+            def: None,
+            ghi: Some(789),
+        },
+    ];
+
+    let out = serialize(Vec::new(), Templated(&rows)).unwrap();
+    // The second "def" is skipped.
+    assert_eq!(
+        out,
+        &b"\x00\x02\x00\x00\x00\x00\x03\x28\x0b\x00\x03\x03\x0d\x03\x03abc\x0d\x03\x03def\
+           \x0d\x03\x03ghi\x03\x02\x03{\x0d\x03\x03bar\n\x04\xc8\x01\x0c\x04\x15\x03"[..]
+    );
+
+    let decoded: Vec<TemplateRow> = crate::from_slice(&out).unwrap();
+    assert_eq!(decoded, rows);
+}
+
+#[test]
+fn test_serialize_template_maps() {
+    let rows = vec![hashmap! {"abc" => 1}, hashmap! {"def" => 2}, HashMap::new()];
+    let out = serialize(Vec::new(), Templated(&rows)).unwrap();
+    assert_eq!(out[8], BSER_TEMPLATE);
+
+    let decoded: Vec<HashMap<String, Option<i32>>> = crate::from_slice(&out).unwrap();
+    assert_eq!(
+        decoded,
+        vec![
+            hashmap! {"abc".to_string() => Some(1), "def".to_string() => None},
+            hashmap! {"abc".to_string() => None, "def".to_string() => Some(2)},
+            hashmap! {"abc".to_string() => None, "def".to_string() => None},
+        ]
+    );
+
+    assert!(serialize(Vec::new(), Templated(vec![1, 2, 3])).is_err());
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Stat {
+    ino: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dev: Option<u64>,
+}
+
+#[test]
+fn test_u64_policy() {
+    use crate::de::DeserializerOptions;
+    use crate::U64Policy;
+
+    let rows = vec![
+        Stat {
+            ino: u64::MAX,
+            dev: Some(1 << 63),
+        },
+        Stat { ino: 7, dev: None },
+    ];
+    assert!(to_vec(&rows).is_err());
+
+    let ser_options = SerializerOptions::new().u64_policy(U64Policy::Reinterpret);
+    let de_options = DeserializerOptions::new().u64_policy(U64Policy::Reinterpret);
+    for pdu in [
+        to_vec_with_options(&rows, &ser_options).unwrap(),
+        to_vec_with_options(Templated(&rows), &ser_options).unwrap(),
+    ] {
+        // The values are wrapped the same way as the watchman server does.
+        let wrapped: Vec<HashMap<String, Option<i64>>> = crate::from_slice(&pdu).unwrap();
+        assert_eq!(wrapped[0]["ino"], Some(-1));
+        assert_eq!(wrapped[0]["dev"], Some(i64::MIN));
+
+        assert!(crate::from_slice::<Vec<Stat>>(&pdu).is_err());
+        let decoded: Vec<Stat> = crate::from_slice_with_options(&pdu, &de_options).unwrap();
+        assert_eq!(decoded, rows);
+    }
+}
+
+#[test]
+fn test_bytestring() {
+    use crate::bytestring::ByteString;
+    use crate::header::{BSER_BYTESTRING, BSER_UTF8STRING};
+
+    let bytes = ByteString::from(b"caf\xe9".to_vec());
+    let out = to_vec(&bytes).unwrap();
+    assert_eq!(out[8], BSER_BYTESTRING);
+    assert_eq!(crate::from_slice::<ByteString>(&out).unwrap(), bytes);
+    // UTF-8 strings are accepted too
+    let out = to_vec("abc").unwrap();
+    assert_eq!(out[8], BSER_UTF8STRING);
+    assert_eq!(
+        crate::from_slice::<ByteString>(&out).unwrap(),
+        ByteString::from("abc")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_bytestring_paths() {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::path::PathBuf;
+
+    use crate::bytestring::ByteString;
+    use crate::header::BSER_BYTESTRING;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Paths {
+        #[serde(with = "crate::bytestring::path")]
+        path: PathBuf,
+        #[serde(with = "crate::bytestring::path::option", default)]
+        maybe: Option<PathBuf>,
+        #[serde(with = "crate::bytestring::os_string")]
+        os: OsString,
+    }
+
+    let name = OsString::from_vec(b"caf\xe9/\xff".to_vec());
+    let paths = Paths {
+        path: PathBuf::from(&name),
+        maybe: Some(PathBuf::from(&name)),
+        os: name.clone(),
+    };
+    // serde's own impl can't encode this path
+    assert!(to_vec(&paths.path).is_err());
+
+    let out = to_vec(&paths).unwrap();
+    assert_eq!(crate::from_slice::<Paths>(&out).unwrap(), paths);
+    let decoded: HashMap<String, ByteString> = crate::from_slice(&out).unwrap();
+    assert_eq!(decoded["os"].as_bytes(), name.as_bytes());
+
+    let paths = Paths {
+        maybe: None,
+        ..paths
+    };
+    let out = to_vec(&paths).unwrap();
+    assert_eq!(crate::from_slice::<Paths>(&out).unwrap(), paths);
+    let pos = out
+        .windows(name.len())
+        .position(|w| w == name.as_bytes())
+        .unwrap();
+    assert_eq!(out[pos - 3], BSER_BYTESTRING);
+}
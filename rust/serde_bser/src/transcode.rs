@@ -0,0 +1,529 @@
+//! Conversion between BSER PDUs and JSON text.
+//!
+//! BSER to JSON conversion streams: values are written out as they are
+//! read, without building an intermediate `Value`.  Templates are expanded
+//! into arrays of objects, omitting the keys that a row skips.
+//!
+//! JSON has no byte strings, so strings of both kinds become JSON strings
+//! in which bytes that are not valid UTF-8 are written as `\xNN` escapes
+//! and backslashes are doubled.  Converting JSON to BSER undoes this
+//! escaping, so the bytes of a string survive a round trip; a `\` that
+//! doesn't start one of these escapes is kept as it is.  What is lost is:
+//!
+//! * Whether a string was a `BSER_BYTESTRING` or a `BSER_UTF8STRING`.
+//!   Strings that are valid UTF-8 become `BSER_UTF8STRING`s (or
+//!   bytestrings, depending on the `SerializerOptions`), and the rest
+//!   become bytestrings.
+//! * Reals that are NaN or infinite, which become `null`.
+
+use std::fmt::Write;
+use std::io;
+use std::str;
+
+use serde_json::ser::{CompactFormatter, Formatter, PrettyFormatter};
+
+use crate::de::reentrant::ReentrantLimit;
+use crate::de::{Bunser, DeRead, DeserializerOptions, PduInfo, Reference};
+use crate::errors::*;
+use crate::header::*;
+use crate::ser::{serialize_with_options, SerializerOptions};
+use crate::value::Value;
+
+/// Convert the BSER PDU read from `read` into compact JSON text.
+///
+/// ```
+/// use serde_bser::de::SliceRead;
+///
+/// let pdu = serde_bser::ser::to_vec(vec!["hello"]).unwrap();
+/// let json = serde_bser::transcode::to_json(SliceRead::new(&pdu), Vec::new()).unwrap();
+/// assert_eq!(json, br#"["hello"]"#);
+/// ```
+pub fn to_json<'de, R, W>(read: R, writer: W) -> Result<W>
+where
+    R: DeRead<'de>,
+    W: io::Write,
+{
+    to_json_with_options(read, writer, &DeserializerOptions::default())
+}
+
+/// Convert the BSER PDU read from `read` into compact JSON text, within the
+/// depth and length limits set by `options`.
+pub fn to_json_with_options<'de, R, W>(
+    read: R,
+    writer: W,
+    options: &DeserializerOptions,
+) -> Result<W>
+where
+    R: DeRead<'de>,
+    W: io::Write,
+{
+    transcode(read, writer, CompactFormatter, options)
+}
+
+/// Convert the BSER PDU read from `read` into indented JSON text.
+pub fn to_json_pretty<'de, R, W>(read: R, writer: W) -> Result<W>
+where
+    R: DeRead<'de>,
+    W: io::Write,
+{
+    to_json_pretty_with_options(read, writer, &DeserializerOptions::default())
+}
+
+pub fn to_json_pretty_with_options<'de, R, W>(
+    read: R,
+    writer: W,
+    options: &DeserializerOptions,
+) -> Result<W>
+where
+    R: DeRead<'de>,
+    W: io::Write,
+{
+    transcode(read, writer, PrettyFormatter::new(), options)
+}
+
+/// Convert the JSON text read from `reader` into a BSER PDU.
+pub fn from_json<R, W>(reader: R, writer: W, options: &SerializerOptions) -> Result<W>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    let value: Value = serde_json::from_reader(reader)
+        .map_err(|err| Error::from(ErrorKind::DeCustom(format!("while parsing JSON: {}", err))))?;
+    serialize_with_options(writer, unescape_value(value)?, options)
+}
+
+fn transcode<'de, R, W, F>(
+    read: R,
+    writer: W,
+    formatter: F,
+    options: &DeserializerOptions,
+) -> Result<W>
+where
+    R: DeRead<'de>,
+    W: io::Write,
+    F: Formatter,
+{
+    let mut bunser = Bunser::new(read);
+    let pdu_info = bunser.read_pdu().map_err(|err| err.at_offset(0))?;
+    options
+        .check_pdu_len(pdu_info.body_len()?)
+        .map_err(|err| err.at_offset(0))?;
+    let mut transcoder = Transcoder {
+        bunser,
+        pdu_info,
+        writer,
+        formatter,
+        remaining_depth: ReentrantLimit::new(options.max_depth),
+        max_string_len: options.max_string_len,
+        max_container_len: options.max_container_len,
+    };
+    transcoder
+        .value()
+        .map_err(|err| err.at_offset(transcoder.bunser.read_count() as usize))?;
+    transcoder.bunser.end(&transcoder.pdu_info)?;
+    Ok(transcoder.writer)
+}
+
+struct Transcoder<R, W, F> {
+    bunser: Bunser<R>,
+    pdu_info: PduInfo,
+    writer: W,
+    formatter: F,
+    remaining_depth: ReentrantLimit,
+    max_string_len: Option<usize>,
+    max_container_len: Option<usize>,
+}
+
+impl<'de, R, W, F> Transcoder<R, W, F>
+where
+    R: DeRead<'de>,
+    W: io::Write,
+    F: Formatter,
+{
+    fn value(&mut self) -> Result<()> {
+        match self.bunser.peek()? {
+            BSER_ARRAY => {
+                let _guard = self.remaining_depth.acquire("array")?;
+                self.bunser.discard();
+                let nitems = self.container_len()?;
+                self.formatter.begin_array(&mut self.writer)?;
+                for i in 0..nitems {
+                    self.formatter.begin_array_value(&mut self.writer, i == 0)?;
+                    self.value()?;
+                    self.formatter.end_array_value(&mut self.writer)?;
+                }
+                self.formatter.end_array(&mut self.writer)?;
+            }
+            BSER_OBJECT => {
+                let _guard = self.remaining_depth.acquire("object")?;
+                self.bunser.discard();
+                let nitems = self.container_len()?;
+                self.formatter.begin_object(&mut self.writer)?;
+                for i in 0..nitems {
+                    self.formatter.begin_object_key(&mut self.writer, i == 0)?;
+                    let key = self.string()?;
+                    self.write_str(&key)?;
+                    self.formatter.end_object_key(&mut self.writer)?;
+                    self.formatter.begin_object_value(&mut self.writer)?;
+                    self.value()?;
+                    self.formatter.end_object_value(&mut self.writer)?;
+                }
+                self.formatter.end_object(&mut self.writer)?;
+            }
+            BSER_TEMPLATE => {
+                let _guard = self.remaining_depth.acquire("template")?;
+                self.bunser.discard();
+                self.template()?;
+            }
+            BSER_BYTESTRING | BSER_UTF8STRING => {
+                let s = self.string()?;
+                self.write_str(&s)?;
+            }
+            BSER_INT8 | BSER_INT16 | BSER_INT32 | BSER_INT64 => {
+                let n = self.bunser.check_next_int()?;
+                self.formatter.write_i64(&mut self.writer, n)?;
+            }
+            BSER_REAL => {
+                let n = self.bunser.next_f64()?;
+                if n.is_finite() {
+                    self.formatter.write_f64(&mut self.writer, n)?;
+                } else {
+                    self.formatter.write_null(&mut self.writer)?;
+                }
+            }
+            BSER_TRUE | BSER_FALSE => {
+                let b = self.bunser.peek()? == BSER_TRUE;
+                self.bunser.discard();
+                self.formatter.write_bool(&mut self.writer, b)?;
+            }
+            BSER_NULL => {
+                self.bunser.discard();
+                self.formatter.write_null(&mut self.writer)?;
+            }
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::Value, ch)),
+        }
+        Ok(())
+    }
+
+    /// Write out a template as an array of objects.
+    fn template(&mut self) -> Result<()> {
+        let keys = {
+            match self.bunser.peek()? {
+                BSER_ARRAY => self.bunser.discard(),
+                ch => bail!(ErrorKind::DeInvalidStartByte(Expected::TemplateKeys, ch)),
+            }
+            // Don't trust the count enough to allocate for it up front.
+            let nkeys = self.container_len()?;
+            let mut keys = Vec::new();
+            for _ in 0..nkeys {
+                keys.push(self.string()?);
+            }
+            keys
+        };
+        let nitems = self.container_len()?;
+        self.bunser
+            .check_template_rows(keys.len(), nitems, self.pdu_info.end()?)?;
+
+        self.formatter.begin_array(&mut self.writer)?;
+        for i in 0..nitems {
+            self.formatter.begin_array_value(&mut self.writer, i == 0)?;
+            self.formatter.begin_object(&mut self.writer)?;
+            let mut first = true;
+            for key in &keys {
+                if self.bunser.peek()? == BSER_SKIP {
+                    self.bunser.discard();
+                    continue;
+                }
+                self.formatter.begin_object_key(&mut self.writer, first)?;
+                self.write_str(key)?;
+                self.formatter.end_object_key(&mut self.writer)?;
+                self.formatter.begin_object_value(&mut self.writer)?;
+                self.value()?;
+                self.formatter.end_object_value(&mut self.writer)?;
+                first = false;
+            }
+            self.formatter.end_object(&mut self.writer)?;
+            self.formatter.end_array_value(&mut self.writer)?;
+        }
+        self.formatter.end_array(&mut self.writer)?;
+        Ok(())
+    }
+
+    /// Read a non-negative length.
+    fn length(&mut self) -> Result<usize> {
+        let len = self.bunser.check_next_int()?;
+        if len < 0 {
+            bail!(ErrorKind::DeInvalidLength(len));
+        }
+        Ok(len as usize)
+    }
+
+    /// Read the length of an array, object or template, or the number of
+    /// keys in a template.
+    fn container_len(&mut self) -> Result<usize> {
+        let len = self.length()?;
+        match self.max_container_len {
+            Some(max) if len > max => bail!(ErrorKind::DeContainerTooLong { len, max }),
+            _ => Ok(len),
+        }
+    }
+
+    /// Read a string of either kind, escaping it as described in the module
+    /// documentation.
+    fn string(&mut self) -> Result<String> {
+        let kind = self.bunser.peek()?;
+        match kind {
+            BSER_BYTESTRING | BSER_UTF8STRING => self.bunser.discard(),
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::String, ch)),
+        }
+        let len = self.length()?;
+        if let Some(max) = self.max_string_len {
+            if len > max {
+                bail!(ErrorKind::DeStringTooLong { len, max });
+            }
+        }
+        let end = self.pdu_info.end()?;
+        if len > end.saturating_sub(self.bunser.read_count() as usize) {
+            bail!(ErrorKind::DeUnexpectedEof("bytes/string"));
+        }
+        let bytes = self.bunser.read_bytes(len as i64)?;
+        let bytes = match bytes {
+            Reference::Borrowed(b) => b,
+            Reference::Copied(b) => b,
+        };
+        if kind == BSER_UTF8STRING {
+            str::from_utf8(bytes)?;
+        }
+        Ok(escape(bytes))
+    }
+
+    fn write_str(&mut self, s: &str) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, s).map_err(io::Error::from)?;
+        Ok(())
+    }
+}
+
+/// Double the backslashes in `bytes`, and write the bytes that aren't
+/// valid UTF-8 as `\xNN` escapes.
+fn escape(mut bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len());
+    loop {
+        let (valid, invalid) = match str::from_utf8(bytes) {
+            Ok(valid) => (valid, &b""[..]),
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                let invalid_len = err.error_len().unwrap_or(rest.len());
+                bytes = &rest[invalid_len..];
+                // Safety: `from_utf8` checked this much.
+                let valid = unsafe { str::from_utf8_unchecked(valid) };
+                (valid, &rest[..invalid_len])
+            }
+        };
+        for (i, part) in valid.split('\\').enumerate() {
+            if i > 0 {
+                output.push_str("\\\\");
+            }
+            output.push_str(part);
+        }
+        if invalid.is_empty() {
+            return output;
+        }
+        for b in invalid {
+            write!(output, "\\x{:02x}", b).unwrap();
+        }
+    }
+}
+
+/// Undo `escape` for the strings in `value`.
+fn unescape_value(value: Value) -> Result<Value> {
+    Ok(match value {
+        Value::Utf8String(s) => match unescape(s) {
+            Ok(s) => Value::Utf8String(s),
+            Err(bytes) => Value::ByteString(bytes.into()),
+        },
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(unescape_value)
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = unescape(key).map_err(|_| {
+                        Error::from(ErrorKind::DeCustom(
+                            "object key escapes bytes that are not valid UTF-8".into(),
+                        ))
+                    })?;
+                    Ok((key, unescape_value(value)?))
+                })
+                .collect::<Result<_>>()?,
+        ),
+        value => value,
+    })
+}
+
+/// Undo `escape`, returning the bytes if they aren't valid UTF-8.
+fn unescape(s: String) -> std::result::Result<String, Vec<u8>> {
+    if !s.contains('\\') {
+        return Ok(s);
+    }
+    let input = s.as_bytes();
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match &input[i..] {
+            [b'\\', b'\\', ..] => {
+                bytes.push(b'\\');
+                i += 2;
+            }
+            [b'\\', b'x', hi, lo, ..] if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() => {
+                let hex = str::from_utf8(&input[i + 2..i + 4]).unwrap();
+                bytes.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 4;
+            }
+            _ => {
+                bytes.push(input[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(bytes).map_err(|err| err.into_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+
+    use super::*;
+    use crate::de::SliceRead;
+    use crate::Templated;
+
+    fn json(pdu: &[u8]) -> String {
+        String::from_utf8(to_json(SliceRead::new(pdu), Vec::new()).unwrap()).unwrap()
+    }
+
+    #[derive(Serialize)]
+    struct File {
+        name: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+    }
+
+    #[test]
+    fn test_to_json() {
+        let files = Templated(vec![
+            File {
+                name: "foo",
+                size: Some(12),
+            },
+            File {
+                name: "bar",
+                size: None,
+            },
+        ]);
+        let pdu = crate::ser::to_vec(&files).unwrap();
+        assert_eq!(pdu[8], BSER_TEMPLATE);
+        assert_eq!(json(&pdu), r#"[{"name":"foo","size":12},{"name":"bar"}]"#);
+
+        let value = vec![
+            Value::ByteString(b"a\xff\"".to_vec().into()),
+            Value::Real(1.5),
+            Value::Real(f64::NAN),
+            Value::Bool(true),
+            Value::Null,
+        ];
+        let pdu = crate::ser::to_vec(&value).unwrap();
+        assert_eq!(json(&pdu), r#"["a\\xff\"",1.5,null,true,null]"#);
+
+        let pretty = to_json_pretty(SliceRead::new(&pdu), Vec::new()).unwrap();
+        assert!(pretty.starts_with(b"[\n  \"a\\\\xff"));
+
+        // Truncated PDUs are rejected
+        assert!(to_json(SliceRead::new(&pdu[..pdu.len() - 1]), Vec::new()).is_err());
+
+        // Backslashes are doubled so that they can't be mistaken for escapes
+        let pdu = crate::ser::to_vec(r"C:\x41").unwrap();
+        assert_eq!(json(&pdu), r#""C:\\\\x41""#);
+    }
+
+    #[test]
+    fn test_to_json_limits() {
+        // A template claiming i64::MAX keys
+        let pdu = b"\x00\x02\x00\x00\x00\x00\x03\x0d\x0b\x00\x06\xff\xff\xff\xff\xff\xff\xff\x7f";
+        assert!(to_json(SliceRead::new(pdu), Vec::new()).is_err());
+
+        // A template with no keys claiming i64::MAX rows
+        let pdu =
+            b"\x00\x02\x00\x00\x00\x00\x03\x0d\x0b\x00\x03\x00\x06\xff\xff\xff\xff\xff\xff\xff\x7f";
+        assert!(to_json(SliceRead::new(pdu), Vec::new()).is_err());
+
+        let pdu = crate::ser::to_vec(vec![vec!["hello"]]).unwrap();
+        let to_json = |options: DeserializerOptions| {
+            to_json_with_options(SliceRead::new(&pdu), Vec::new(), &options)
+                .map_err(|err| err.into_kind())
+        };
+        assert!(to_json(DeserializerOptions::new().max_depth(2)).is_ok());
+        assert!(matches!(
+            to_json(DeserializerOptions::new().max_depth(1)),
+            Err(ErrorKind::DeRecursionLimitExceeded(_))
+        ));
+        assert!(matches!(
+            to_json(DeserializerOptions::new().max_pdu_len(pdu.len() - 9)),
+            Err(ErrorKind::DePduTooLarge { .. })
+        ));
+        assert!(matches!(
+            to_json(DeserializerOptions::new().max_string_len(4)),
+            Err(ErrorKind::DeStringTooLong { len: 5, max: 4 })
+        ));
+        assert!(matches!(
+            to_json(DeserializerOptions::new().max_container_len(0)),
+            Err(ErrorKind::DeContainerTooLong { len: 1, max: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_from_json() {
+        let input = r#"{"command":["query","/repo",{"fields":["name"],"since":-1,"x":0.5}]}"#;
+        let pdu = from_json(input.as_bytes(), Vec::new(), &SerializerOptions::new()).unwrap();
+        let value: Value = crate::from_slice(&pdu).unwrap();
+        assert_eq!(
+            value.pointer("/command/1").and_then(Value::as_str),
+            Some("/repo")
+        );
+        assert_eq!(value.pointer("/command/2/since"), Some(&Value::Integer(-1)));
+        assert_eq!(value.pointer("/command/2/x"), Some(&Value::Real(0.5)));
+
+        let pdu = from_json(&b"[1, \"two\"]"[..], Vec::new(), &SerializerOptions::new()).unwrap();
+        assert_eq!(json(&pdu), r#"[1,"two"]"#);
+
+        assert!(from_json(
+            &b"18446744073709551615"[..],
+            Vec::new(),
+            &SerializerOptions::new()
+        )
+        .is_err());
+        assert!(from_json(&b"[1,"[..], Vec::new(), &SerializerOptions::new()).is_err());
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        let value = vec![
+            Value::ByteString(b"a\xff\\xff\xe2\x82".to_vec().into()),
+            Value::Utf8String(r"C:\Users\x\\".into()),
+            Value::Utf8String("caf\u{e9}".into()),
+        ];
+        let pdu = crate::ser::to_vec(&value).unwrap();
+        let text = json(&pdu);
+        let pdu = from_json(text.as_bytes(), Vec::new(), &SerializerOptions::new()).unwrap();
+        assert_eq!(crate::from_slice::<Vec<Value>>(&pdu).unwrap(), value);
+
+        // Backslashes that don't start an escape are kept
+        let pdu = from_json(&br#""a\\b\\x4""#[..], Vec::new(), &SerializerOptions::new()).unwrap();
+        assert_eq!(
+            crate::from_slice::<Value>(&pdu).unwrap(),
+            Value::Utf8String(r"a\b\x4".into())
+        );
+    }
+}
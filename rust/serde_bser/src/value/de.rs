@@ -0,0 +1,198 @@
+//! A deserializer that reads from a `Value` rather than from bytes.
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, IntoDeserializer};
+use serde::forward_to_deserialize_any;
+
+use super::Value;
+use crate::errors::*;
+
+/// Interpret a `Value` as an instance of type `T`.
+///
+/// This accepts the same values as decoding `T` from BSER does, so for
+/// instance a `String` can be read from either kind of string, and enums
+/// are read from a variant name or a single-entry object.
+///
+/// ```
+/// use maplit::hashmap;
+/// use serde::Deserialize;
+/// use serde_bser::value::{from_value, Value};
+///
+/// #[derive(Deserialize)]
+/// struct SavedState {
+///     manifold_path: String,
+/// }
+///
+/// let value = Value::from(hashmap! {
+///     "manifold_path".to_string() => Value::ByteString("path/to/state".into()),
+/// });
+/// let state: SavedState = from_value(value).unwrap();
+/// assert_eq!(state.manifold_path, "path/to/state");
+/// ```
+pub fn from_value<T>(value: Value) -> Result<T>
+where
+    T: de::DeserializeOwned,
+{
+    T::deserialize(value)
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self {
+            Value::Array(list) => {
+                let mut seq = SeqDeserializer::new(list.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Object(map) => {
+                let mut map = MapDeserializer::new(map.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            Value::ByteString(b) => visitor.visit_byte_buf(b.into_bytes()),
+            Value::Integer(n) => visitor.visit_i64(n),
+            Value::Real(n) => visitor.visit_f64(n),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Null => visitor.visit_unit(),
+            Value::Utf8String(s) => visitor.visit_string(s),
+        }
+    }
+
+    /// Parse a `Null` as a None, and anything else as a `Some(...)`.
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self {
+            Value::Null => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &str, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Parse an enum as an object like {key: value}, or a unit variant as just
+    /// a string.
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self {
+            Value::Utf8String(_) | Value::ByteString(_) => visitor.visit_enum(EnumAccess {
+                variant: self,
+                value: None,
+            }),
+            Value::Object(map) => {
+                if map.len() != 1 {
+                    return Err(de::Error::invalid_value(
+                        de::Unexpected::Signed(map.len() as i64),
+                        &"integer `1`",
+                    ));
+                }
+                let (variant, value) = map.into_iter().next().expect("map has one entry");
+                visitor.visit_enum(EnumAccess {
+                    variant: Value::Utf8String(variant),
+                    value: Some(value),
+                })
+            }
+            _ => Err(de::Error::custom(format!(
+                "expected a string or an object for enum '{}'",
+                name
+            ))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes
+        byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+struct EnumAccess {
+    variant: Value,
+    /// The contents of the variant, or `None` for a plain unit variant.
+    value: Option<Value>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, VariantAccess)>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, VariantAccess { value: self.value }))
+    }
+}
+
+struct VariantAccess {
+    value: Option<Value>,
+}
+
+impl VariantAccess {
+    fn value(self) -> Result<Value> {
+        self.value.ok_or_else(|| {
+            de::Error::invalid_type(de::Unexpected::UnitVariant, &"a variant with contents")
+        })
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.value {
+            Some(value) => de::Deserialize::deserialize(value),
+            None => Ok(()),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.value()?)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_any(self.value()?, visitor)
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_any(self.value()?, visitor)
+    }
+}
@@ -0,0 +1,615 @@
+//! A BSER value that remembers exactly how it was encoded.
+
+use byteorder::{NativeEndian, WriteBytesExt};
+use serde::de::{
+    self, Deserialize, DeserializeSeed, Deserializer, IntoDeserializer, SeqAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use serde::ser::{self, Serialize, Serializer};
+
+use super::Value;
+use crate::bytestring::ByteString;
+use crate::de::{Bunser, DeserializerOptions, Reference, SliceRead};
+use crate::errors::*;
+use crate::header::*;
+use crate::raw::{self, RawBytes};
+
+/// The name of the newtype struct that `ExactValue` deserializes as.
+///
+/// The BSER deserializer visits it as a sequence of the limits that apply
+/// to the next value (`max_depth`, `max_string_len` and
+/// `max_container_len`, with `usize::MAX` for no limit), followed by the
+/// encoded value.  See `ExactAccess`.
+pub(crate) const TOKEN: &str = "$serde_bser::private::Exact";
+
+/// The width of an encoded integer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IntWidth {
+    I8,
+    I16,
+    I32,
+    I64,
+}
+
+impl IntWidth {
+    /// The narrowest width that can hold `n`, which is the width that the
+    /// serializer uses.
+    pub fn minimal(n: i64) -> Self {
+        if n >= i8::MIN as i64 && n <= i8::MAX as i64 {
+            IntWidth::I8
+        } else if n >= i16::MIN as i64 && n <= i16::MAX as i64 {
+            IntWidth::I16
+        } else if n >= i32::MIN as i64 && n <= i32::MAX as i64 {
+            IntWidth::I32
+        } else {
+            IntWidth::I64
+        }
+    }
+
+    /// Whether `n` can be encoded with this width.
+    pub fn fits(self, n: i64) -> bool {
+        IntWidth::minimal(n) <= self
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            IntWidth::I8 => BSER_INT8,
+            IntWidth::I16 => BSER_INT16,
+            IntWidth::I32 => BSER_INT32,
+            IntWidth::I64 => BSER_INT64,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            BSER_INT8 => Some(IntWidth::I8),
+            BSER_INT16 => Some(IntWidth::I16),
+            BSER_INT32 => Some(IntWidth::I32),
+            BSER_INT64 => Some(IntWidth::I64),
+            _ => None,
+        }
+    }
+}
+
+/// A BSER value that records the width of every integer and length, the
+/// kind of every string, the order of object keys and the use of
+/// templates, so that serializing a decoded value reproduces its encoding
+/// byte for byte.
+///
+/// This is useful for tools that pass BSER through, or rewrite part of a
+/// PDU without disturbing the rest.  Use `Value` when the encoding details
+/// don't matter.
+///
+/// ```
+/// use serde_bser::value::ExactValue;
+///
+/// // An int32-encoded 1, which the serializer would normally shrink to an
+/// // int8.
+/// let pdu = b"\x00\x02\x00\x00\x00\x00\x03\x05\x05\x01\x00\x00\x00";
+/// let value: ExactValue = serde_bser::from_slice(pdu).unwrap();
+/// assert_eq!(serde_bser::ser::to_vec(&value).unwrap(), pdu);
+/// ```
+///
+/// The PDU header isn't part of the value: it is written as usual, so a
+/// BSER v1 PDU is re-encoded as BSER v2.  Only serde_bser's serializers and
+/// deserializers understand the exact encoding.  Other formats see the
+/// encoded bytes when serializing, and fall back to converting from a
+/// `Value` when deserializing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExactValue {
+    Array {
+        items: Vec<ExactValue>,
+        len_width: IntWidth,
+    },
+    /// Keys are `ByteString`s or `Utf8String`s, in their encoded order.
+    Object {
+        entries: Vec<(ExactValue, ExactValue)>,
+        len_width: IntWidth,
+    },
+    ByteString {
+        bytes: ByteString,
+        len_width: IntWidth,
+    },
+    Utf8String {
+        string: String,
+        len_width: IntWidth,
+    },
+    Integer {
+        value: i64,
+        width: IntWidth,
+    },
+    Real(f64),
+    Bool(bool),
+    Null,
+    /// A `BSER_TEMPLATE`.  Keys are `ByteString`s or `Utf8String`s, and
+    /// each row holds one slot per key, with `None` for `BSER_SKIP`.
+    Template {
+        keys: Vec<ExactValue>,
+        keys_len_width: IntWidth,
+        rows: Vec<Vec<Option<ExactValue>>>,
+        rows_len_width: IntWidth,
+    },
+}
+
+impl ExactValue {
+    /// Decode a single value, with no PDU header, from `bytes`, subject to
+    /// the limits in `options`.
+    fn parse(bytes: &[u8], options: &DeserializerOptions) -> Result<Self> {
+        let mut parser = Parser {
+            bunser: Bunser::new(SliceRead::new(bytes)),
+            end: bytes.len(),
+            options,
+        };
+        let value = parser.value(0)?;
+        let read = parser.bunser.read_count() as usize;
+        if read != bytes.len() {
+            bail!(ErrorKind::DeLengthMismatch {
+                expected: bytes.len(),
+                actual: read,
+            });
+        }
+        Ok(value)
+    }
+
+    /// Encode this value, with no PDU header.
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            ExactValue::Array { items, len_width } => {
+                out.push(BSER_ARRAY);
+                put_int(out, items.len() as i64, *len_width)?;
+                for item in items {
+                    item.encode(out)?;
+                }
+            }
+            ExactValue::Object { entries, len_width } => {
+                out.push(BSER_OBJECT);
+                put_int(out, entries.len() as i64, *len_width)?;
+                for (key, value) in entries {
+                    key.encode_key(out)?;
+                    value.encode(out)?;
+                }
+            }
+            ExactValue::ByteString { bytes, len_width } => {
+                out.push(BSER_BYTESTRING);
+                put_int(out, bytes.len() as i64, *len_width)?;
+                out.extend_from_slice(bytes.as_bytes());
+            }
+            ExactValue::Utf8String { string, len_width } => {
+                out.push(BSER_UTF8STRING);
+                put_int(out, string.len() as i64, *len_width)?;
+                out.extend_from_slice(string.as_bytes());
+            }
+            ExactValue::Integer { value, width } => put_int(out, *value, *width)?,
+            ExactValue::Real(n) => {
+                out.push(BSER_REAL);
+                out.write_f64::<NativeEndian>(*n)?;
+            }
+            ExactValue::Bool(true) => out.push(BSER_TRUE),
+            ExactValue::Bool(false) => out.push(BSER_FALSE),
+            ExactValue::Null => out.push(BSER_NULL),
+            ExactValue::Template {
+                keys,
+                keys_len_width,
+                rows,
+                rows_len_width,
+            } => {
+                out.push(BSER_TEMPLATE);
+                out.push(BSER_ARRAY);
+                put_int(out, keys.len() as i64, *keys_len_width)?;
+                for key in keys {
+                    key.encode_key(out)?;
+                }
+                put_int(out, rows.len() as i64, *rows_len_width)?;
+                for row in rows {
+                    if row.len() != keys.len() {
+                        bail!(ErrorKind::SerCustom(format!(
+                            "template row has {} slots, but there are {} keys",
+                            row.len(),
+                            keys.len()
+                        )));
+                    }
+                    for slot in row {
+                        match slot {
+                            Some(value) => value.encode(out)?,
+                            None => out.push(BSER_SKIP),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_key(&self, out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            ExactValue::ByteString { .. } | ExactValue::Utf8String { .. } => self.encode(out),
+            _ => bail!(ErrorKind::SerCustom(format!(
+                "value {:?} is illegal as a key in a BSER map",
+                self
+            ))),
+        }
+    }
+
+    /// Convert to a `Value`, which is what decoding the same encoding as a
+    /// `Value` would produce.
+    pub fn to_value(&self) -> Result<Value> {
+        let mut bytes = vec![];
+        self.encode(&mut bytes)?;
+        crate::de::from_raw_slice(&bytes)
+    }
+}
+
+fn put_int(out: &mut Vec<u8>, n: i64, width: IntWidth) -> Result<()> {
+    if !width.fits(n) {
+        bail!(ErrorKind::SerCustom(format!(
+            "integer {} doesn't fit in {}",
+            n,
+            header_byte_desc(width.tag())
+        )));
+    }
+    out.push(width.tag());
+    match width {
+        IntWidth::I8 => out.write_i8(n as i8)?,
+        IntWidth::I16 => out.write_i16::<NativeEndian>(n as i16)?,
+        IntWidth::I32 => out.write_i32::<NativeEndian>(n as i32)?,
+        IntWidth::I64 => out.write_i64::<NativeEndian>(n)?,
+    }
+    Ok(())
+}
+
+struct Parser<'a> {
+    bunser: Bunser<SliceRead<'a>>,
+    /// The length of the encoding being parsed.
+    end: usize,
+    options: &'a DeserializerOptions,
+}
+
+impl<'a> Parser<'a> {
+    fn value(&mut self, depth: usize) -> Result<ExactValue> {
+        let offset = self.bunser.read_count() as usize;
+        self.parse_value(depth).map_err(|err| err.at_offset(offset))
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<ExactValue> {
+        let tag = self.bunser.peek()?;
+        if matches!(tag, BSER_ARRAY | BSER_OBJECT | BSER_TEMPLATE) {
+            self.options
+                .check_depth(depth + 1, &header_byte_desc(tag))?;
+        }
+        let value = match tag {
+            BSER_ARRAY => {
+                self.bunser.discard();
+                let (len, len_width) = self.container_len()?;
+                let mut items = vec![];
+                for i in 0..len {
+                    items.push(
+                        self.value(depth + 1)
+                            .map_err(|err| err.in_path(PathSegment::Index(i)))?,
+                    );
+                }
+                ExactValue::Array { items, len_width }
+            }
+            BSER_OBJECT => {
+                self.bunser.discard();
+                let (len, len_width) = self.container_len()?;
+                let mut entries = vec![];
+                for _ in 0..len {
+                    let key = self.key()?;
+                    let value = self
+                        .value(depth + 1)
+                        .map_err(|err| err.in_path(PathSegment::Key(key_name(&key))))?;
+                    entries.push((key, value));
+                }
+                ExactValue::Object { entries, len_width }
+            }
+            BSER_TEMPLATE => {
+                self.bunser.discard();
+                match self.bunser.peek()? {
+                    BSER_ARRAY => self.bunser.discard(),
+                    ch => bail!(ErrorKind::DeInvalidStartByte(Expected::TemplateKeys, ch)),
+                }
+                let (nkeys, keys_len_width) = self.container_len()?;
+                let mut keys = vec![];
+                for _ in 0..nkeys {
+                    keys.push(self.key()?);
+                }
+                let (nrows, rows_len_width) = self.container_len()?;
+                self.bunser.check_template_rows(nkeys, nrows, self.end)?;
+                let mut rows = vec![];
+                for i in 0..nrows {
+                    let mut row = Vec::with_capacity(keys.len());
+                    for key in &keys {
+                        let slot = match self.bunser.peek()? {
+                            BSER_SKIP => {
+                                self.bunser.discard();
+                                None
+                            }
+                            _ => Some(self.value(depth + 1).map_err(|err| {
+                                err.in_path(PathSegment::Key(key_name(key)))
+                                    .in_path(PathSegment::Index(i))
+                            })?),
+                        };
+                        row.push(slot);
+                    }
+                    rows.push(row);
+                }
+                ExactValue::Template {
+                    keys,
+                    keys_len_width,
+                    rows,
+                    rows_len_width,
+                }
+            }
+            BSER_BYTESTRING | BSER_UTF8STRING => self.string()?,
+            BSER_INT8 | BSER_INT16 | BSER_INT32 | BSER_INT64 => {
+                let (value, width) = self.int()?;
+                ExactValue::Integer { value, width }
+            }
+            BSER_REAL => ExactValue::Real(self.bunser.next_f64()?),
+            BSER_TRUE | BSER_FALSE => {
+                self.bunser.discard();
+                ExactValue::Bool(tag == BSER_TRUE)
+            }
+            BSER_NULL => {
+                self.bunser.discard();
+                ExactValue::Null
+            }
+            ch => bail!(ErrorKind::DeInvalidStartByte(Expected::Value, ch)),
+        };
+        Ok(value)
+    }
+
+    fn int(&mut self) -> Result<(i64, IntWidth)> {
+        let tag = self.bunser.peek()?;
+        let width = match IntWidth::from_tag(tag) {
+            Some(width) => width,
+            None => bail!(ErrorKind::DeInvalidStartByte(Expected::Integer, tag)),
+        };
+        Ok((self.bunser.check_next_int()?, width))
+    }
+
+    fn length(&mut self) -> Result<(usize, IntWidth)> {
+        let (len, width) = self.int()?;
+        if len < 0 {
+            bail!(ErrorKind::DeInvalidLength(len));
+        }
+        Ok((len as usize, width))
+    }
+
+    /// Read the number of items in an array or object, keys or rows in a
+    /// template.
+    fn container_len(&mut self) -> Result<(usize, IntWidth)> {
+        let (len, width) = self.length()?;
+        self.options.check_container_len(len)?;
+        Ok((len, width))
+    }
+
+    /// Parse a string of either kind.
+    fn string(&mut self) -> Result<ExactValue> {
+        let tag = self.bunser.peek()?;
+        if tag != BSER_BYTESTRING && tag != BSER_UTF8STRING {
+            bail!(ErrorKind::DeInvalidStartByte(Expected::String, tag));
+        }
+        self.bunser.discard();
+        let (len, len_width) = self.length()?;
+        self.options.check_string_len(len)?;
+        let bytes = self.bunser.read_bytes(len as i64)?.get_ref().to_vec();
+        if tag == BSER_BYTESTRING {
+            Ok(ExactValue::ByteString {
+                bytes: bytes.into(),
+                len_width,
+            })
+        } else {
+            let string = String::from_utf8(bytes).map_err(|err| err.utf8_error())?;
+            Ok(ExactValue::Utf8String { string, len_width })
+        }
+    }
+
+    fn key(&mut self) -> Result<ExactValue> {
+        let offset = self.bunser.read_count() as usize;
+        self.string().map_err(|err| err.at_offset(offset))
+    }
+}
+
+/// The name of a key, for error paths.
+fn key_name(key: &ExactValue) -> String {
+    match key {
+        ExactValue::ByteString { bytes, .. } => bytes.as_escaped_string(),
+        ExactValue::Utf8String { string, .. } => string.clone(),
+        _ => String::new(),
+    }
+}
+
+impl From<Value> for ExactValue {
+    /// Convert a `Value`, using the encoding that the serializer would.
+    /// Object entries are in the order of the `Map`.
+    fn from(value: Value) -> Self {
+        fn len_width(len: usize) -> IntWidth {
+            IntWidth::minimal(len as i64)
+        }
+
+        match value {
+            Value::Array(items) => ExactValue::Array {
+                len_width: len_width(items.len()),
+                items: items.into_iter().map(ExactValue::from).collect(),
+            },
+            Value::Object(map) => ExactValue::Object {
+                len_width: len_width(map.len()),
+                entries: map
+                    .into_iter()
+                    .map(|(key, value)| (ExactValue::from(Value::Utf8String(key)), value.into()))
+                    .collect(),
+            },
+            Value::ByteString(bytes) => ExactValue::ByteString {
+                len_width: len_width(bytes.len()),
+                bytes,
+            },
+            Value::Utf8String(string) => ExactValue::Utf8String {
+                len_width: len_width(string.len()),
+                string,
+            },
+            Value::Integer(value) => ExactValue::Integer {
+                value,
+                width: IntWidth::minimal(value),
+            },
+            Value::Real(n) => ExactValue::Real(n),
+            Value::Bool(b) => ExactValue::Bool(b),
+            Value::Null => ExactValue::Null,
+        }
+    }
+}
+
+impl Serialize for ExactValue {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut bytes = vec![];
+        self.encode(&mut bytes).map_err(ser::Error::custom)?;
+        serializer.serialize_newtype_struct(raw::TOKEN, &RawBytes(&bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for ExactValue {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ExactValueVisitor;
+
+        impl<'de> Visitor<'de> for ExactValueVisitor {
+            type Value = ExactValue;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("any valid BSER value")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<ExactValue, E>
+            where
+                E: de::Error,
+            {
+                ExactValue::parse(v, &DeserializerOptions::default()).map_err(E::custom)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> std::result::Result<ExactValue, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut limit = || {
+                    seq.next_element::<usize>()?
+                        .ok_or_else(|| de::Error::custom("missing ExactValue limit"))
+                };
+                let mut options = DeserializerOptions::new();
+                options.max_depth = limit()?;
+                options.max_string_len = Some(limit()?);
+                options.max_container_len = Some(limit()?);
+                seq.next_element_seed(ParseSeed(&options))?
+                    .ok_or_else(|| de::Error::custom("missing ExactValue encoding"))
+            }
+
+            fn visit_newtype_struct<D>(
+                self,
+                deserializer: D,
+            ) -> std::result::Result<ExactValue, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                Value::deserialize(deserializer).map(ExactValue::from)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(TOKEN, ExactValueVisitor)
+    }
+}
+
+/// Parses the encoded value handed to it, subject to the limits in the
+/// options.
+struct ParseSeed<'a>(&'a DeserializerOptions);
+
+impl<'a, 'de> DeserializeSeed<'de> for ParseSeed<'a> {
+    type Value = ExactValue;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<ExactValue, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(self)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for ParseSeed<'a> {
+    type Value = ExactValue;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an encoded BSER value")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<ExactValue, E>
+    where
+        E: de::Error,
+    {
+        ExactValue::parse(v, self.0).map_err(E::custom)
+    }
+}
+
+/// The sequence that the BSER deserializer passes to the visitor of an
+/// `ExactValue`: the limits in effect at the value, then its encoding.
+pub(crate) struct ExactAccess<'de, 's> {
+    limits: std::array::IntoIter<usize, 3>,
+    bytes: Option<Reference<'de, 's, [u8]>>,
+}
+
+impl<'de, 's> ExactAccess<'de, 's> {
+    pub(crate) fn new(options: &DeserializerOptions, bytes: Reference<'de, 's, [u8]>) -> Self {
+        let limits = [
+            options.max_depth,
+            options.max_string_len.unwrap_or(usize::MAX),
+            options.max_container_len.unwrap_or(usize::MAX),
+        ];
+        ExactAccess {
+            limits: IntoIterator::into_iter(limits),
+            bytes: Some(bytes),
+        }
+    }
+}
+
+impl<'de, 's> SeqAccess<'de> for ExactAccess<'de, 's> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if let Some(limit) = self.limits.next() {
+            return seed.deserialize(limit.into_deserializer()).map(Some);
+        }
+        match self.bytes.take() {
+            Some(bytes) => seed.deserialize(EncodedDeserializer(bytes)).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Passes encoded bytes to a visitor, borrowed from the input if they can
+/// be.
+struct EncodedDeserializer<'de, 's>(Reference<'de, 's, [u8]>);
+
+impl<'de, 's> Deserializer<'de> for EncodedDeserializer<'de, 's> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Reference::Borrowed(bytes) => visitor.visit_borrowed_bytes(bytes),
+            Reference::Copied(bytes) => visitor.visit_bytes(bytes),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
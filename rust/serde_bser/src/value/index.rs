@@ -0,0 +1,161 @@
+//! Support for indexing into a `Value` by array position or object key.
+
+use std::ops;
+
+use super::Value;
+
+/// A type that can be used to index into a `Value`: `usize` for arrays,
+/// and `str` or `String` for objects.  This trait is sealed.
+///
+/// Indexing with `[]` returns `Value::Null` for missing elements and values
+/// of the wrong type, so that nested lookups such as
+/// `value["clock"]["scm"]` don't need to check each step.  Mutable indexing
+/// panics if the array index is out of bounds or the value is neither an
+/// object nor `Null`; indexing a `Null` by key turns it into an empty
+/// object, and a missing key is inserted as `Null`.
+pub trait Index: private::Sealed {
+    #[doc(hidden)]
+    fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value>;
+
+    #[doc(hidden)]
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value>;
+
+    #[doc(hidden)]
+    fn index_or_insert<'v>(&self, v: &'v mut Value) -> &'v mut Value;
+}
+
+impl Index for usize {
+    fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value> {
+        match v {
+            Value::Array(list) => list.get(*self),
+            _ => None,
+        }
+    }
+
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value> {
+        match v {
+            Value::Array(list) => list.get_mut(*self),
+            _ => None,
+        }
+    }
+
+    fn index_or_insert<'v>(&self, v: &'v mut Value) -> &'v mut Value {
+        match v {
+            Value::Array(list) => {
+                let len = list.len();
+                list.get_mut(*self).unwrap_or_else(|| {
+                    panic!(
+                        "cannot access index {} of BSER array of length {}",
+                        self, len
+                    )
+                })
+            }
+            _ => panic!("cannot access index {} of BSER {}", self, type_name(v)),
+        }
+    }
+}
+
+impl Index for str {
+    fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value> {
+        match v {
+            Value::Object(map) => map.get(self),
+            _ => None,
+        }
+    }
+
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value> {
+        match v {
+            Value::Object(map) => map.get_mut(self),
+            _ => None,
+        }
+    }
+
+    fn index_or_insert<'v>(&self, v: &'v mut Value) -> &'v mut Value {
+        if let Value::Null = v {
+            *v = Value::Object(Default::default());
+        }
+        match v {
+            Value::Object(map) => {
+                if !map.contains_key(self) {
+                    map.insert(self.to_owned(), Value::Null);
+                }
+                map.get_mut(self).expect("the key was just inserted")
+            }
+            _ => panic!("cannot access key {:?} of BSER {}", self, type_name(v)),
+        }
+    }
+}
+
+impl Index for String {
+    fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value> {
+        self[..].index_into(v)
+    }
+
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value> {
+        self[..].index_into_mut(v)
+    }
+
+    fn index_or_insert<'v>(&self, v: &'v mut Value) -> &'v mut Value {
+        self[..].index_or_insert(v)
+    }
+}
+
+impl<T> Index for &T
+where
+    T: ?Sized + Index,
+{
+    fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value> {
+        (**self).index_into(v)
+    }
+
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value> {
+        (**self).index_into_mut(v)
+    }
+
+    fn index_or_insert<'v>(&self, v: &'v mut Value) -> &'v mut Value {
+        (**self).index_or_insert(v)
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for usize {}
+    impl Sealed for str {}
+    impl Sealed for String {}
+    impl<T> Sealed for &T where T: ?Sized + Sealed {}
+}
+
+fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+        Value::ByteString(_) => "bytestring",
+        Value::Integer(_) => "integer",
+        Value::Real(_) => "real",
+        Value::Bool(_) => "bool",
+        Value::Null => "null",
+        Value::Utf8String(_) => "string",
+    }
+}
+
+static NULL: Value = Value::Null;
+
+impl<I> ops::Index<I> for Value
+where
+    I: Index,
+{
+    type Output = Value;
+
+    fn index(&self, index: I) -> &Value {
+        index.index_into(self).unwrap_or(&NULL)
+    }
+}
+
+impl<I> ops::IndexMut<I> for Value
+where
+    I: Index,
+{
+    fn index_mut(&mut self, index: I) -> &mut Value {
+        index.index_or_insert(self)
+    }
+}
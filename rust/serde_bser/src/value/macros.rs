@@ -0,0 +1,81 @@
+/// Construct a `Value` from a JSON-like literal.
+///
+/// Arrays and objects are written as in JSON, along with `null`, `true`
+/// and `false`.  Anything else is an expression that is converted with
+/// `Value::from`, so string literals become `Utf8String`s and byte string
+/// literals such as `b"foo"` become `ByteString`s.  Object keys are string
+/// literals, or expressions in parentheses.
+///
+/// ```
+/// use serde_bser::bser;
+/// use serde_bser::value::Value;
+///
+/// let name = "foo.txt";
+/// let value = bser!({
+///     "files": [name, b"caf\xe9"],
+///     "fresh_instance": false,
+///     "clock": null,
+///     (name): [1, 2.5, {}],
+/// });
+/// assert_eq!(value["files"][0], Value::from("foo.txt"));
+/// assert_eq!(value["files"][1], Value::ByteString(b"caf\xe9".to_vec().into()));
+/// assert_eq!(value["foo.txt"][1], Value::Real(2.5));
+/// ```
+#[macro_export]
+macro_rules! bser {
+    // Arrays are built up by splitting the tokens at each top-level comma.
+    // The first group holds the finished elements and the second the
+    // tokens of the current element.
+    (@array [$($elems:expr,)*] () ()) => {
+        ::std::vec![$($elems,)*]
+    };
+    (@array [$($elems:expr,)*] ($($value:tt)+) ()) => {
+        $crate::bser!(@array [$($elems,)* $crate::bser!($($value)+),] () ())
+    };
+    (@array [$($elems:expr,)*] ($($value:tt)+) (, $($rest:tt)*)) => {
+        $crate::bser!(@array [$($elems,)* $crate::bser!($($value)+),] () ($($rest)*))
+    };
+    (@array [$($elems:expr,)*] ($($value:tt)*) ($next:tt $($rest:tt)*)) => {
+        $crate::bser!(@array [$($elems,)*] ($($value)* $next) ($($rest)*))
+    };
+
+    // Objects are built up in the same way, one entry at a time.
+    (@object $object:ident ()) => {};
+    (@object $object:ident [$key:tt] ($($value:tt)+) ()) => {
+        let _ = $object.insert(::std::string::String::from($key), $crate::bser!($($value)+));
+    };
+    (@object $object:ident [$key:tt] ($($value:tt)+) (, $($rest:tt)*)) => {
+        let _ = $object.insert(::std::string::String::from($key), $crate::bser!($($value)+));
+        $crate::bser!(@object $object ($($rest)*));
+    };
+    (@object $object:ident [$key:tt] ($($value:tt)*) ($next:tt $($rest:tt)*)) => {
+        $crate::bser!(@object $object [$key] ($($value)* $next) ($($rest)*));
+    };
+    (@object $object:ident ($key:tt : $($rest:tt)*)) => {
+        $crate::bser!(@object $object [$key] () ($($rest)*));
+    };
+
+    (null) => {
+        $crate::value::Value::Null
+    };
+    (true) => {
+        $crate::value::Value::Bool(true)
+    };
+    (false) => {
+        $crate::value::Value::Bool(false)
+    };
+    ([ $($tt:tt)* ]) => {
+        $crate::value::Value::Array($crate::bser!(@array [] () ($($tt)*)))
+    };
+    ({ $($tt:tt)* }) => {
+        $crate::value::Value::Object({
+            #[allow(unused_mut)]
+            let mut object = $crate::value::Map::new();
+            $crate::bser!(@object object ($($tt)*));
+            object
+        })
+    };
+    ($other:expr) => {
+        $crate::value::Value::from($other)
+    };
+}
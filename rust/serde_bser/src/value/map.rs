@@ -0,0 +1,339 @@
+//! The map type used for the objects in `Value` and `ValueRef`.
+//!
+//! By default the entries are kept sorted by key.  With the
+//! `preserve_order` feature they are kept in the order they were inserted,
+//! which for decoded values is the order they had on the wire.  Either way,
+//! iterating over a map, and so encoding it, always visits the entries in
+//! the same order.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::ops;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use super::Value;
+
+#[cfg(not(feature = "preserve_order"))]
+use std::collections::btree_map as imp;
+#[cfg(not(feature = "preserve_order"))]
+type MapImpl<K, V> = std::collections::BTreeMap<K, V>;
+
+#[cfg(feature = "preserve_order")]
+use indexmap::map as imp;
+#[cfg(feature = "preserve_order")]
+type MapImpl<K, V> = indexmap::IndexMap<K, V>;
+
+/// A map whose iteration order depends only on its contents: sorted by
+/// key, or in insertion order with the `preserve_order` feature.
+#[derive(Clone)]
+pub struct Map<K = String, V = Value> {
+    map: MapImpl<K, V>,
+}
+
+impl<K, V> Map<K, V>
+where
+    K: Ord + Hash,
+{
+    pub fn new() -> Self {
+        Map {
+            map: MapImpl::new(),
+        }
+    }
+
+    /// Create an empty map with room for `capacity` entries.  The capacity
+    /// is only a hint without the `preserve_order` feature.
+    #[allow(unused_variables)]
+    pub fn with_capacity(capacity: usize) -> Self {
+        #[cfg(not(feature = "preserve_order"))]
+        let map = MapImpl::new();
+        #[cfg(feature = "preserve_order")]
+        let map = MapImpl::with_capacity(capacity);
+        Map { map }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord + Hash + Eq,
+    {
+        self.map.get(key)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord + Hash + Eq,
+    {
+        self.map.get_mut(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord + Hash + Eq,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Insert an entry, returning the previous value for the key if there
+    /// was one.  Replacing a value doesn't change the position of its
+    /// entry.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.map.insert(key, value)
+    }
+
+    /// Remove an entry, returning its value.  The order of the remaining
+    /// entries is unchanged.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord + Hash + Eq,
+    {
+        #[cfg(not(feature = "preserve_order"))]
+        return self.map.remove(key);
+        #[cfg(feature = "preserve_order")]
+        return self.map.shift_remove(key);
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter(self.map.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut(self.map.iter_mut())
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys(self.map.keys())
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(self.map.values())
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut(self.map.values_mut())
+    }
+}
+
+impl<K, V> Default for Map<K, V>
+where
+    K: Ord + Hash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maps are equal if they have the same entries, whatever their order.
+impl<K, V> PartialEq for Map<K, V>
+where
+    K: Ord + Hash,
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<K, V> fmt::Debug for Map<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+impl<K, V, Q> ops::Index<&Q> for Map<K, V>
+where
+    K: Ord + Hash + Borrow<Q>,
+    Q: ?Sized + Ord + Hash + Eq,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for Map<K, V>
+where
+    K: Ord + Hash,
+{
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        Map {
+            map: MapImpl::from_iter(iter),
+        }
+    }
+}
+
+impl<K, V> Extend<(K, V)> for Map<K, V>
+where
+    K: Ord + Hash,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.map.extend(iter)
+    }
+}
+
+/// The entries are sorted by key, even with the `preserve_order` feature,
+/// as a `HashMap` has no meaningful order of its own.
+impl<K, V> From<HashMap<K, V>> for Map<K, V>
+where
+    K: Ord + Hash,
+{
+    fn from(map: HashMap<K, V>) -> Self {
+        let mut entries: Vec<_> = map.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        entries.into_iter().collect()
+    }
+}
+
+macro_rules! delegate_iterator {
+    ($name:ident<$($lt:lifetime,)? $k:ident, $v:ident>, $imp:ident, $item:ty) => {
+        pub struct $name<$($lt,)? $k, $v>(imp::$imp<$($lt,)? $k, $v>);
+
+        impl<$($lt,)? $k, $v> Iterator for $name<$($lt,)? $k, $v> {
+            type Item = $item;
+
+            #[inline]
+            fn next(&mut self) -> Option<Self::Item> {
+                self.0.next()
+            }
+
+            #[inline]
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.0.size_hint()
+            }
+        }
+
+        impl<$($lt,)? $k, $v> DoubleEndedIterator for $name<$($lt,)? $k, $v> {
+            #[inline]
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.0.next_back()
+            }
+        }
+
+        impl<$($lt,)? $k, $v> ExactSizeIterator for $name<$($lt,)? $k, $v> {
+            #[inline]
+            fn len(&self) -> usize {
+                self.0.len()
+            }
+        }
+    };
+}
+
+delegate_iterator!(Iter<'a, K, V>, Iter, (&'a K, &'a V));
+delegate_iterator!(IterMut<'a, K, V>, IterMut, (&'a K, &'a mut V));
+delegate_iterator!(IntoIter<K, V>, IntoIter, (K, V));
+delegate_iterator!(Keys<'a, K, V>, Keys, &'a K);
+delegate_iterator!(Values<'a, K, V>, Values, &'a V);
+delegate_iterator!(ValuesMut<'a, K, V>, ValuesMut, &'a mut V);
+
+impl<K, V> IntoIterator for Map<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter(self.map.into_iter())
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a Map<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        Iter(self.map.iter())
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a mut Map<K, V> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        IterMut(self.map.iter_mut())
+    }
+}
+
+impl<K, V> Serialize for Map<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.map.len()))?;
+        for (k, v) in self.map.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for Map<K, V>
+where
+    K: Deserialize<'de> + Ord + Hash,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MapVisitor<K, V>(PhantomData<(K, V)>);
+
+        impl<'de, K, V> Visitor<'de> for MapVisitor<K, V>
+        where
+            K: Deserialize<'de> + Ord + Hash,
+            V: Deserialize<'de>,
+        {
+            type Value = Map<K, V>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map")
+            }
+
+            fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut map = Map::with_capacity(access.size_hint().unwrap_or(0));
+                while let Some((key, value)) = access.next_entry()? {
+                    map.insert(key, value);
+                }
+                Ok(map)
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor(PhantomData))
+    }
+}
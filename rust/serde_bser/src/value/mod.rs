@@ -1,3 +1,13 @@
+mod de;
+pub(crate) mod exact;
+mod index;
+mod macros;
+mod map;
+mod ser;
+#[cfg(test)]
+mod test;
+mod value_ref;
+
 use crate::bytestring::ByteString;
 use serde::de::{Deserialize, MapAccess, SeqAccess, Visitor};
 use serde::Serialize;
@@ -5,6 +15,13 @@ use std::collections::HashMap;
 use std::convert::TryInto;
 use std::path::PathBuf;
 
+pub use self::de::from_value;
+pub use self::exact::{ExactValue, IntWidth};
+pub use self::index::Index;
+pub use self::map::Map;
+pub use self::ser::{to_value, Serializer};
+pub use self::value_ref::ValueRef;
+
 /// The Value type is used in cases where the schema is not known statically.
 /// As used in Watchman's protocol, this allows encoding arbitrary metadata
 /// that can be passed through the system by eg: the `state-enter` command,
@@ -14,7 +31,8 @@ use std::path::PathBuf;
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Array(Vec<Value>),
-    Object(HashMap<String, Value>),
+    /// The entries are in a deterministic order; see `Map`.
+    Object(Map<String, Value>),
     ByteString(ByteString),
     Integer(i64),
     Real(f64),
@@ -23,15 +41,175 @@ pub enum Value {
     Utf8String(String),
 }
 
+impl Value {
+    /// Index into an array or object, returning `None` if the value isn't
+    /// of the right type or the element doesn't exist.
+    ///
+    /// ```
+    /// use maplit::hashmap;
+    /// use serde_bser::value::Value;
+    ///
+    /// let value = Value::from(hashmap! {
+    ///     "files".to_string() => Value::from(vec![Value::from("foo")]),
+    /// });
+    /// assert_eq!(value.get("files").and_then(|f| f.get(0)), Some(&Value::from("foo")));
+    /// assert_eq!(value.get("dirs"), None);
+    /// ```
+    pub fn get<I: Index>(&self, index: I) -> Option<&Value> {
+        index.index_into(self)
+    }
+
+    /// Mutable version of `get`.
+    pub fn get_mut<I: Index>(&mut self, index: I) -> Option<&mut Value> {
+        index.index_into_mut(self)
+    }
+
+    /// Look up a value by a JSON Pointer (RFC 6901) such as `/a/0/b`.
+    /// Within each path segment, `~1` stands for `/` and `~0` for `~`.
+    /// The empty pointer refers to the whole value.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        if !pointer.starts_with('/') {
+            return None;
+        }
+        pointer
+            .split('/')
+            .skip(1)
+            .map(unescape_pointer_token)
+            .try_fold(self, |target, token| match target {
+                Value::Object(map) => map.get(&token),
+                Value::Array(list) => parse_pointer_index(&token).and_then(|i| list.get(i)),
+                _ => None,
+            })
+    }
+
+    /// Mutable version of `pointer`.
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Value> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        if !pointer.starts_with('/') {
+            return None;
+        }
+        pointer
+            .split('/')
+            .skip(1)
+            .map(unescape_pointer_token)
+            .try_fold(self, |target, token| match target {
+                Value::Object(map) => map.get_mut(&token),
+                Value::Array(list) => {
+                    parse_pointer_index(&token).and_then(move |i| list.get_mut(i))
+                }
+                _ => None,
+            })
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Returns the number as an `f64`, converting integers if necessary.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Real(n) => Some(*n),
+            Value::Integer(n) => Some(*n as f64),
+            _ => None,
+        }
+    }
+
+    /// Returns the string contents of a `Utf8String`, or of a `ByteString`
+    /// that holds valid UTF-8.  The watchman server encodes strings as
+    /// bytestrings, so most strings in its responses are the latter.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Utf8String(s) => Some(s),
+            Value::ByteString(b) => std::str::from_utf8(b.as_bytes()).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes of either kind of string.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Utf8String(s) => Some(s.as_bytes()),
+            Value::ByteString(b) => Some(b.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_array_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match self {
+            Value::Array(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&Map<String, Value>> {
+        match self {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn as_object_mut(&mut self) -> Option<&mut Map<String, Value>> {
+        match self {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+}
+
+fn unescape_pointer_token(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+fn parse_pointer_index(token: &str) -> Option<usize> {
+    // RFC 6901 doesn't allow leading zeros or signs in array indices
+    if token.starts_with('+') || (token.starts_with('0') && token.len() > 1) {
+        return None;
+    }
+    token.parse().ok()
+}
+
 impl From<Vec<Value>> for Value {
     fn from(v: Vec<Value>) -> Self {
         Self::Array(v)
     }
 }
 
+impl From<Map<String, Value>> for Value {
+    fn from(v: Map<String, Value>) -> Self {
+        Self::Object(v)
+    }
+}
+
+/// The entries are sorted by key; see `Map`.
 impl From<HashMap<String, Value>> for Value {
     fn from(v: HashMap<String, Value>) -> Self {
-        Self::Object(v)
+        Self::Object(v.into())
     }
 }
 
@@ -68,11 +246,35 @@ impl From<i64> for Value {
     }
 }
 
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Self::Real(v)
+    }
+}
+
+impl From<ByteString> for Value {
+    fn from(v: ByteString) -> Self {
+        Self::ByteString(v)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(v: &[u8]) -> Self {
+        Self::ByteString(v.to_vec().into())
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for Value {
+    fn from(v: &[u8; N]) -> Self {
+        Self::ByteString(v.to_vec().into())
+    }
+}
+
 impl TryInto<Value> for usize {
     type Error = &'static str;
 
     fn try_into(self) -> Result<Value, Self::Error> {
-        if self > i64::max_value() as usize {
+        if self > i64::MAX as usize {
             Err("value is too large to represent as i64")
         } else {
             Ok(Value::Integer(self as i64))
@@ -105,12 +307,12 @@ impl<'de> Deserialize<'de> for Value {
                 Ok(Value::Integer(value))
             }
 
-            /*
             #[inline]
-            fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
-                // maybe_put_int! doesn't work for u64 because it converts to i64
-                // internally.
-                if v > (i64::max_value() as u64) {
+            fn visit_u64<E>(self, v: u64) -> Result<Value, E>
+            where
+                E: serde::de::Error,
+            {
+                if v > (i64::MAX as u64) {
                     Err(serde::de::Error::custom(format!(
                         "value {} is too large to represent as a BSER integer",
                         v
@@ -119,7 +321,6 @@ impl<'de> Deserialize<'de> for Value {
                     Ok(Value::Integer(v as i64))
                 }
             }
-            */
 
             #[inline]
             fn visit_f64<E>(self, value: f64) -> Result<Value, E> {
@@ -187,7 +388,7 @@ impl<'de> Deserialize<'de> for Value {
             {
                 match visitor.next_key()? {
                     Some(Value::ByteString(key)) => {
-                        let mut values = HashMap::new();
+                        let mut values = Map::new();
 
                         values.insert(
                             key.try_into().map_err(serde::de::Error::custom)?,
@@ -200,7 +401,7 @@ impl<'de> Deserialize<'de> for Value {
                         Ok(Value::Object(values))
                     }
                     Some(Value::Utf8String(key)) => {
-                        let mut values = HashMap::new();
+                        let mut values = Map::new();
 
                         values.insert(key, visitor.next_value()?);
                         while let Some((key, value)) = visitor.next_entry()? {
@@ -213,7 +414,7 @@ impl<'de> Deserialize<'de> for Value {
                         "value {:?} is illegal as a key in a BSER map",
                         value
                     ))),
-                    None => Ok(Value::Object(HashMap::new())),
+                    None => Ok(Value::Object(Map::new())),
                 }
             }
         }
@@ -0,0 +1,545 @@
+//! A serializer that builds a `Value` directly rather than encoding bytes.
+
+use serde::ser::{self, Impossible, Serialize};
+
+use super::{Map, Value};
+use crate::errors::*;
+use crate::raw;
+
+/// Convert a `T` into a `Value`.
+///
+/// The result is the same `Value` that decoding the BSER encoding of `T`
+/// would produce: enums use the same externally tagged representation and
+/// `Templated` sequences become plain arrays.  Strings are always
+/// `Utf8String`s.
+///
+/// ```
+/// use serde::Serialize;
+/// use serde_bser::value::{to_value, Value};
+///
+/// #[derive(Serialize)]
+/// struct Metadata {
+///     rev: String,
+///     distance: u32,
+/// }
+///
+/// let value = to_value(Metadata { rev: "abc123".into(), distance: 3 }).unwrap();
+/// assert_eq!(value["distance"], Value::Integer(3));
+/// ```
+pub fn to_value<T>(value: T) -> Result<Value>
+where
+    T: Serialize,
+{
+    value.serialize(Serializer)
+}
+
+/// Serializes values into a `Value` tree.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVariant<SerializeVec>;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeVariant<SerializeMap>;
+
+    #[inline]
+    fn serialize_bool(self, v: bool) -> Result<Value> {
+        Ok(Value::Bool(v))
+    }
+
+    #[inline]
+    fn serialize_i8(self, v: i8) -> Result<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_i16(self, v: i16) -> Result<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_i32(self, v: i32) -> Result<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_i64(self, v: i64) -> Result<Value> {
+        Ok(Value::Integer(v))
+    }
+
+    #[inline]
+    fn serialize_u8(self, v: u8) -> Result<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_u16(self, v: u16) -> Result<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_u32(self, v: u32) -> Result<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_u64(self, v: u64) -> Result<Value> {
+        if v > (i64::MAX as u64) {
+            Err(ErrorKind::SerU64TooBig(v).into())
+        } else {
+            self.serialize_i64(v as i64)
+        }
+    }
+
+    #[inline]
+    fn serialize_f32(self, v: f32) -> Result<Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    #[inline]
+    fn serialize_f64(self, v: f64) -> Result<Value> {
+        Ok(Value::Real(v))
+    }
+
+    #[inline]
+    fn serialize_char(self, v: char) -> Result<Value> {
+        Ok(Value::Utf8String(v.to_string()))
+    }
+
+    #[inline]
+    fn serialize_str(self, v: &str) -> Result<Value> {
+        Ok(Value::Utf8String(v.to_string()))
+    }
+
+    #[inline]
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value> {
+        Ok(Value::ByteString(v.to_vec().into()))
+    }
+
+    #[inline]
+    fn serialize_unit(self) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    #[inline]
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+        self.serialize_unit()
+    }
+
+    #[inline]
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value> {
+        self.serialize_str(variant)
+    }
+
+    #[inline]
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        if name == raw::TOKEN {
+            // Pre-encoded BSER, so decode it.
+            let mut bytes = vec![];
+            raw::write(&mut bytes, value)?;
+            return crate::de::from_raw_slice(&bytes);
+        }
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        let mut map = Map::with_capacity(1);
+        map.insert(variant.to_string(), to_value(value)?);
+        Ok(Value::Object(map))
+    }
+
+    #[inline]
+    fn serialize_none(self) -> Result<Value> {
+        self.serialize_unit()
+    }
+
+    #[inline]
+    fn serialize_some<T>(self, value: &T) -> Result<Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec> {
+        Ok(SerializeVec {
+            vec: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeVec> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeVec>> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeMap> {
+        Ok(SerializeMap {
+            map: Map::with_capacity(len.unwrap_or(0)),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeMap>> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct SerializeVec {
+    vec: Vec<Value>,
+}
+
+impl SerializeVec {
+    fn push<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.vec.push(to_value(value)?);
+        Ok(())
+    }
+}
+
+macro_rules! impl_serialize_vec {
+    ($trait:ty, $fn:ident) => {
+        impl $trait for SerializeVec {
+            type Ok = Value;
+            type Error = Error;
+
+            #[inline]
+            fn $fn<T>(&mut self, value: &T) -> Result<()>
+            where
+                T: ?Sized + Serialize,
+            {
+                self.push(value)
+            }
+
+            #[inline]
+            fn end(self) -> Result<Value> {
+                Ok(Value::Array(self.vec))
+            }
+        }
+    };
+}
+
+impl_serialize_vec!(ser::SerializeSeq, serialize_element);
+impl_serialize_vec!(ser::SerializeTuple, serialize_element);
+impl_serialize_vec!(ser::SerializeTupleStruct, serialize_field);
+
+#[doc(hidden)]
+pub struct SerializeMap {
+    map: Map<String, Value>,
+    next_key: Option<String>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.next_key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key = self
+            .next_key
+            .take()
+            .expect("serialize_value called before serialize_key");
+        self.map.insert(key, to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Object(self.map))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.map.insert(key.to_string(), to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+/// Wraps the contents of a tuple or struct variant as `{variant: contents}`.
+#[doc(hidden)]
+pub struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> SerializeVariant<S> {
+    fn wrap(variant: &'static str, value: Value) -> Value {
+        let mut map = Map::with_capacity(1);
+        map.insert(variant.to_string(), value);
+        Value::Object(map)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeVec> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.inner.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Self::wrap(self.variant, Value::Array(self.inner.vec)))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeMap> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Self::wrap(self.variant, Value::Object(self.inner.map)))
+    }
+}
+
+fn key_must_be_a_string() -> Error {
+    ErrorKind::SerCustom("keys of a Value::Object must be strings".into()).into()
+}
+
+/// Serializes map keys, which must be strings to fit in a `Value::Object`.
+struct KeySerializer;
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = Error;
+
+    type SerializeSeq = Impossible<String, Error>;
+    type SerializeTuple = Impossible<String, Error>;
+    type SerializeTupleStruct = Impossible<String, Error>;
+    type SerializeTupleVariant = Impossible<String, Error>;
+    type SerializeMap = Impossible<String, Error>;
+    type SerializeStruct = Impossible<String, Error>;
+    type SerializeStructVariant = Impossible<String, Error>;
+
+    fn serialize_str(self, v: &str) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<String> {
+        Ok(std::str::from_utf8(v)?.to_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<String>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_none(self) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<String>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit(self) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(key_must_be_a_string())
+    }
+}
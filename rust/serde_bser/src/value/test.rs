@@ -0,0 +1,570 @@
+use maplit::hashmap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{from_value, to_value, Map, Value};
+use crate::bser;
+use crate::de::DeserializerOptions;
+use crate::errors::ErrorKind;
+use crate::Templated;
+
+fn state_metadata() -> Value {
+    Value::from(hashmap! {
+        "name".to_string() => Value::from("hg.update"),
+        "metadata".to_string() => Value::from(hashmap! {
+            "rev".to_string() => Value::ByteString(b"abc123".to_vec().into()),
+            "distance".to_string() => Value::Integer(3),
+            "partial".to_string() => Value::Bool(false),
+            "a/b~c".to_string() => Value::Null,
+            "manifests".to_string() => Value::from(vec![
+                Value::from("first"),
+                Value::Real(1.5),
+            ]),
+        }),
+    })
+}
+
+#[test]
+fn test_accessors() {
+    let value = state_metadata();
+    let metadata = value.get("metadata").unwrap();
+    assert_eq!(metadata.get("rev").and_then(Value::as_str), Some("abc123"));
+    assert_eq!(
+        metadata.get("rev").and_then(Value::as_bytes),
+        Some(&b"abc123"[..])
+    );
+    assert_eq!(value["name"].as_str(), Some("hg.update"));
+    assert_eq!(metadata["distance"].as_i64(), Some(3));
+    assert_eq!(metadata["distance"].as_f64(), Some(3.0));
+    assert_eq!(metadata["partial"].as_bool(), Some(false));
+    assert_eq!(metadata["manifests"].as_array().map(Vec::len), Some(2));
+    assert_eq!(metadata["manifests"][1].as_f64(), Some(1.5));
+    assert!(metadata["a/b~c"].is_null());
+    assert_eq!(value.as_object().map(|o| o.len()), Some(2));
+
+    // Missing elements and mismatched types
+    assert!(value["nope"]["still nope"][3].is_null());
+    assert_eq!(value.get(0), None);
+    assert_eq!(metadata["distance"].as_str(), None);
+    assert_eq!(Value::ByteString(vec![0xff].into()).as_str(), None);
+}
+
+#[test]
+fn test_pointer() {
+    let mut value = state_metadata();
+    assert_eq!(value.pointer(""), Some(&value));
+    assert_eq!(
+        value.pointer("/metadata/manifests/0"),
+        Some(&Value::from("first"))
+    );
+    assert_eq!(value.pointer("/metadata/a~1b~0c"), Some(&Value::Null));
+    assert_eq!(value.pointer("/metadata/manifests/01"), None);
+    assert_eq!(value.pointer("/metadata/manifests/2"), None);
+    assert_eq!(value.pointer("metadata"), None);
+    assert_eq!(value.pointer("/name/0"), None);
+
+    *value.pointer_mut("/metadata/distance").unwrap() = Value::Integer(4);
+    assert_eq!(value["metadata"]["distance"], Value::Integer(4));
+}
+
+#[test]
+fn test_mutation() {
+    let mut value = state_metadata();
+    value["metadata"]["manifests"][0] = Value::from("replaced");
+    value["metadata"]["new"] = Value::Bool(true);
+    value
+        .get_mut("metadata")
+        .and_then(|m| m.get_mut("manifests"))
+        .and_then(Value::as_array_mut)
+        .unwrap()
+        .push(Value::Null);
+    value.as_object_mut().unwrap().remove("name");
+
+    assert_eq!(
+        value.pointer("/metadata/manifests/0"),
+        Some(&Value::from("replaced"))
+    );
+    assert_eq!(value.pointer("/metadata/new"), Some(&Value::Bool(true)));
+    assert_eq!(value["metadata"]["manifests"].as_array().unwrap().len(), 3);
+    assert_eq!(value.get("name"), None);
+
+    let mut null = Value::Null;
+    null["key"] = Value::Integer(1);
+    assert_eq!(
+        null,
+        Value::from(hashmap! {"key".to_string() => Value::Integer(1)})
+    );
+}
+
+#[test]
+#[should_panic(expected = "cannot access index 5 of BSER array of length 2")]
+fn test_index_out_of_bounds() {
+    let mut value = state_metadata();
+    value["metadata"]["manifests"][5] = Value::Null;
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+enum Kind {
+    Unit,
+    Newtype(i32),
+    Tuple(bool, String),
+    Struct { depth: u8 },
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct File {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Typed {
+    kinds: Vec<Kind>,
+    #[serde(with = "serde_bytes")]
+    bytes: Vec<u8>,
+    real: f64,
+    unit: (),
+    maybe: Option<i64>,
+    map: HashMap<String, char>,
+    files: Templated<Vec<File>>,
+}
+
+#[test]
+fn test_to_from_value() {
+    let typed = Typed {
+        kinds: vec![Kind::Unit, Kind::Newtype(-7), Kind::Tuple(true, "x".into())],
+        bytes: vec![0, 0xff],
+        real: 2.5,
+        unit: (),
+        maybe: None,
+        map: hashmap! {"k".to_string() => 'v'},
+        files: Templated(vec![
+            File {
+                name: "foo".into(),
+                size: Some(1 << 40),
+            },
+            File {
+                name: "bar".into(),
+                size: Some(0),
+            },
+        ]),
+    };
+
+    // Building a Value directly matches decoding the encoded bytes
+    let value = to_value(&typed).unwrap();
+    let pdu = crate::ser::to_vec(&typed).unwrap();
+    assert_eq!(value, crate::from_slice::<Value>(&pdu).unwrap());
+    assert_eq!(value.pointer("/kinds/2/Tuple/1"), Some(&Value::from("x")));
+    assert_eq!(from_value::<Typed>(value).unwrap(), typed);
+
+    let kind = Kind::Struct { depth: 255 };
+    let value = to_value(&kind).unwrap();
+    assert_eq!(value.pointer("/Struct/depth"), Some(&Value::Integer(255)));
+    assert_eq!(from_value::<Kind>(value).unwrap(), kind);
+}
+
+#[test]
+fn test_from_value_errors() {
+    assert!(from_value::<String>(Value::Integer(1)).is_err());
+    assert!(from_value::<(i32, i32)>(Value::from(vec![Value::Integer(1)])).is_err());
+    assert!(from_value::<Kind>(Value::from(HashMap::new())).is_err());
+    assert!(from_value::<Kind>(Value::from("Newtype")).is_err());
+    assert!(to_value(u64::MAX).is_err());
+    assert!(to_value(hashmap! {1 => 2}).is_err());
+}
+
+#[test]
+fn test_value_ref() {
+    use std::borrow::Cow;
+
+    use super::ValueRef;
+
+    let typed = Typed {
+        kinds: vec![Kind::Unit, Kind::Newtype(1)],
+        bytes: vec![1, 2, 3],
+        real: 0.5,
+        unit: (),
+        maybe: Some(3),
+        map: hashmap! {"k".to_string() => 'v'},
+        files: Templated(vec![File {
+            name: "foo".into(),
+            size: None,
+        }]),
+    };
+    let pdu = crate::ser::to_vec(&typed).unwrap();
+    let value: ValueRef = crate::from_slice(&pdu).unwrap();
+
+    let object = match &value {
+        ValueRef::Object(object) => object,
+        _ => panic!("expected an object"),
+    };
+    assert!(matches!(
+        object.get("bytes"),
+        Some(ValueRef::ByteString(Cow::Borrowed(b"\x01\x02\x03")))
+    ));
+    let files = match &object["files"] {
+        ValueRef::Array(files) => files,
+        _ => panic!("expected an array"),
+    };
+    match &files[0] {
+        ValueRef::Object(file) => {
+            assert!(matches!(file.keys().next(), Some(Cow::Borrowed(_))));
+            assert!(matches!(
+                file["name"],
+                ValueRef::Utf8String(Cow::Borrowed("foo"))
+            ));
+            assert_eq!(file["name"].as_str(), Some("foo"));
+            assert_eq!(file.get("size"), None);
+        }
+        _ => panic!("expected an object"),
+    }
+
+    let owned = crate::from_slice::<Value>(&pdu).unwrap();
+    assert_eq!(value.clone().into_owned(), owned);
+    let reencoded = crate::ser::to_vec(&value).unwrap();
+    assert_eq!(crate::from_slice::<Value>(&reencoded).unwrap(), owned);
+}
+
+/// The encoded value in `pdu`, without its header.
+fn pdu_body(pdu: &[u8]) -> &[u8] {
+    let info = crate::de::read_pdu_info(pdu).unwrap().unwrap();
+    &pdu[info.start as usize..]
+}
+
+#[test]
+fn test_exact_value() {
+    use super::{ExactValue, IntWidth};
+
+    // A template with bytestring keys, an int16 that would fit in an int8,
+    // an int32 length and a skipped slot:
+    // [{"abc": 123, "def": "bar", "ghi": null}, {"abc": 456, "ghi": 789}]
+    let template = b"\x00\x02\x00\x00\x00\x00\x05,\x00\x00\x00\x0b\x00\x03\x03\x02\x03\x03abc\x02\
+                     \x03\x03def\x02\x03\x03ghi\x05\x02\x00\x00\x00\x04{\x00\x02\x03\x03bar\n\
+                     \x04\xc8\x01\x0c\x04\x15\x03";
+    let value: ExactValue = crate::from_slice(template).unwrap();
+    match &value {
+        ExactValue::Template {
+            rows,
+            rows_len_width,
+            ..
+        } => {
+            assert_eq!(*rows_len_width, IntWidth::I32);
+            assert_eq!(
+                rows[0][0],
+                Some(ExactValue::Integer {
+                    value: 123,
+                    width: IntWidth::I16,
+                })
+            );
+            assert_eq!(rows[1][1], None);
+        }
+        _ => panic!("expected a template"),
+    }
+    let reencoded = crate::ser::to_vec(&value).unwrap();
+    assert_eq!(pdu_body(&reencoded), pdu_body(template));
+    assert_eq!(
+        value.to_value().unwrap(),
+        crate::from_slice::<Value>(template).unwrap()
+    );
+
+    // Key order is preserved, including inside other types, and when
+    // decoding from a reader.
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Wrapper {
+        name: String,
+        extra: ExactValue,
+    }
+    let extra = b"\x01\x03\x03\x0d\x03\x01z\x03\x01\x0d\x03\x01a\x0a\x02\x03\x01m\x06\x02\
+                  \x00\x00\x00\x00\x00\x00\x00";
+    let pdu = crate::ser::to_vec(Wrapper {
+        name: "x".into(),
+        extra: crate::from_slice(&[b"\x00\x02\x00\x00\x00\x00\x03\x1b", &extra[..]].concat())
+            .unwrap(),
+    })
+    .unwrap();
+    assert!(pdu.windows(extra.len()).any(|w| w == &extra[..]));
+    let wrapper: Wrapper = crate::from_reader(&pdu[..]).unwrap();
+    assert_eq!(crate::ser::to_vec(&wrapper).unwrap(), pdu);
+    let wrapper: Wrapper = crate::from_slice(&pdu).unwrap();
+    assert_eq!(crate::ser::to_vec(&wrapper).unwrap(), pdu);
+
+    // Templated rows can hold exact values too.
+    let files = crate::Templated(vec![wrapper]);
+    let pdu = crate::ser::to_vec(&files).unwrap();
+    let decoded: Vec<Wrapper> = crate::from_slice(&pdu).unwrap();
+    assert_eq!(decoded, files.0);
+
+    // Converting from a Value uses the serializer's encoding.
+    let metadata = state_metadata();
+    let exact = ExactValue::from(metadata.clone());
+    assert_eq!(exact.to_value().unwrap(), metadata);
+    assert_eq!(crate::value::to_value(&exact).unwrap(), metadata);
+    assert_eq!(
+        crate::value::from_value::<ExactValue>(metadata).unwrap(),
+        exact
+    );
+
+    // Widths that are too narrow for the value can't be encoded.
+    let narrow = ExactValue::Integer {
+        value: 1000,
+        width: IntWidth::I8,
+    };
+    assert!(crate::ser::to_vec(&narrow).is_err());
+
+    // A template with no keys can't claim more rows than there are bytes
+    // left to hold them.
+    let template =
+        b"\x00\x02\x00\x00\x00\x00\x03\x0d\x0b\x00\x03\x00\x06\xff\xff\xff\xff\xff\xff\xff\x7f";
+    assert!(crate::from_slice::<ExactValue>(template).is_err());
+
+    // The deserializer's limits apply, rather than fixed ones.
+    let mut body = [0x00, 0x03, 0x01].repeat(200);
+    body.push(0x0a);
+    let mut deep = vec![0x00, 0x01, 0x05];
+    deep.extend(&(body.len() as i32).to_le_bytes());
+    deep.extend(body);
+    assert!(crate::from_slice::<ExactValue>(&deep).is_err());
+    let options = DeserializerOptions::new().max_depth(200);
+    let value: ExactValue = crate::from_slice_with_options(&deep, &options).unwrap();
+    assert_eq!(
+        pdu_body(&crate::ser::to_vec(&value).unwrap()),
+        pdu_body(&deep)
+    );
+    // Nesting the value deeper counts against the same limit.
+    assert!(crate::from_slice_with_options::<Vec<ExactValue>>(
+        &crate::ser::to_vec(vec![value]).unwrap(),
+        &options
+    )
+    .is_err());
+
+    let template = crate::ser::to_vec(Templated(vec![
+        hashmap! {"name" => "foo", "type" => "f"},
+        hashmap! {"name" => "bar", "type" => "d"},
+    ]))
+    .unwrap();
+    let decode = |options: DeserializerOptions| {
+        crate::from_slice_with_options::<ExactValue>(&template, &options)
+            .map_err(|err| err.into_kind())
+    };
+    assert!(decode(DeserializerOptions::new().max_container_len(2)).is_ok());
+    assert!(matches!(
+        decode(DeserializerOptions::new().max_container_len(1)),
+        Err(ErrorKind::DeContainerTooLong { len: 2, max: 1 })
+    ));
+    assert!(matches!(
+        decode(DeserializerOptions::new().max_string_len(2)),
+        Err(ErrorKind::DeStringTooLong { len: 4, max: 2 })
+    ));
+}
+
+#[test]
+fn test_object_order() {
+    #[derive(Serialize)]
+    struct Unsorted {
+        zebra: i32,
+        apple: i32,
+        mango: i32,
+    }
+    let pdu = crate::ser::to_vec(Unsorted {
+        zebra: 1,
+        apple: 2,
+        mango: 3,
+    })
+    .unwrap();
+    let mut value: Value = crate::from_slice(&pdu).unwrap();
+    let keys: Vec<_> = value.as_object().unwrap().keys().cloned().collect();
+    if cfg!(feature = "preserve_order") {
+        assert_eq!(keys, vec!["zebra", "apple", "mango"]);
+        assert_eq!(crate::ser::to_vec(&value).unwrap(), pdu);
+    } else {
+        assert_eq!(keys, vec!["apple", "mango", "zebra"]);
+    }
+
+    // Encoding is deterministic.  Without `preserve_order`, maps with the
+    // same entries encode the same way whatever order they were built in.
+    let map = value.as_object_mut().unwrap();
+    assert_eq!(map.remove("apple"), Some(Value::Integer(2)));
+    map.insert("apple".into(), Value::Integer(2));
+    let rebuilt: Map = vec![
+        ("mango".to_string(), Value::Integer(3)),
+        ("apple".to_string(), Value::Integer(2)),
+        ("zebra".to_string(), Value::Integer(1)),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(value, Value::Object(rebuilt.clone()));
+    let encoded = crate::ser::to_vec(&value).unwrap();
+    assert_eq!(encoded, crate::ser::to_vec(value.clone()).unwrap());
+    if !cfg!(feature = "preserve_order") {
+        assert_eq!(encoded, crate::ser::to_vec(Value::Object(rebuilt)).unwrap());
+    }
+}
+
+#[test]
+fn test_bser_macro() {
+    let name = "hg.update";
+    let value = bser!({
+        "name": name,
+        "metadata": {
+            "rev": b"abc123",
+            "distance": 3,
+            "partial": false,
+            ("a/b~".to_string() + "c"): null,
+            "manifests": ["first", 1.5],
+        },
+    });
+    assert_eq!(value, state_metadata());
+
+    assert_eq!(bser!([]), Value::Array(vec![]));
+    assert_eq!(bser!({}), Value::Object(Map::new()));
+    // Elements can be any expression, including ones with commas inside
+    // brackets, and interpolated values.
+    let nested = bser!([
+        [1, -2],
+        std::cmp::max(3, 4),
+        value.clone(),
+        (b"\xff" as &[u8])
+    ]);
+    assert_eq!(
+        nested,
+        Value::from(vec![
+            Value::from(vec![Value::Integer(1), Value::Integer(-2)]),
+            Value::Integer(4),
+            value,
+            Value::ByteString(b"\xff".to_vec().into()),
+        ])
+    );
+}
+
+mod prop {
+    use crate::bser;
+    use proptest::prelude::*;
+    use std::convert::TryFrom;
+
+    use super::super::ExactValue;
+    use super::Value;
+    use crate::bytestring::ByteString;
+    use crate::header::*;
+
+    /// Integers at and either side of the boundary of each encoded width.
+    fn boundary_integer() -> impl Strategy<Value = i64> {
+        let boundaries = [
+            i8::MIN as i64,
+            i8::MAX as i64,
+            i16::MIN as i64,
+            i16::MAX as i64,
+            i32::MIN as i64,
+            i32::MAX as i64,
+        ];
+        prop_oneof![
+            prop::sample::select(boundaries.to_vec())
+                .prop_flat_map(|n| prop::sample::select(vec![n - 1, n, n + 1])),
+            Just(i64::MIN),
+            Just(i64::MAX),
+            Just(0),
+        ]
+    }
+
+    fn leaf() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            boundary_integer().prop_map(Value::Integer),
+            any::<i64>().prop_map(Value::Integer),
+            // NaN isn't equal to itself, so it can't round trip through ==.
+            any::<f64>()
+                .prop_filter("NaN", |f| !f.is_nan())
+                .prop_map(Value::Real),
+            any::<bool>().prop_map(Value::Bool),
+            Just(Value::Null),
+            any::<String>().prop_map(Value::Utf8String),
+            any::<Vec<u8>>().prop_map(|b| Value::ByteString(ByteString::from(b))),
+        ]
+    }
+
+    fn value() -> impl Strategy<Value = Value> {
+        leaf().prop_recursive(8, 256, 10, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..10).prop_map(Value::Array),
+                prop::collection::vec((any::<String>(), inner), 0..10)
+                    .prop_map(|entries| Value::Object(entries.into_iter().collect())),
+            ]
+        })
+    }
+
+    /// Wrap `value` in `depth` levels of arrays and objects.
+    fn nest(value: Value, depth: usize) -> Value {
+        (0..depth).fold(value, |value, level| {
+            if level % 2 == 0 {
+                Value::Array(vec![value])
+            } else {
+                bser!({ (level.to_string()): value })
+            }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(value in value()) {
+            let pdu = crate::ser::to_vec(&value).unwrap();
+            prop_assert_eq!(crate::from_slice::<Value>(&pdu).unwrap(), value);
+        }
+
+        #[test]
+        fn reencode(value in value()) {
+            let pdu = crate::ser::to_vec(&value).unwrap();
+            let decoded = crate::from_slice::<Value>(&pdu).unwrap();
+            prop_assert_eq!(crate::ser::to_vec(&decoded).unwrap(), pdu);
+        }
+
+        #[test]
+        fn exact_round_trip(value in value()) {
+            let pdu = crate::ser::to_vec(&value).unwrap();
+            let exact = crate::from_slice::<ExactValue>(&pdu).unwrap();
+            prop_assert_eq!(crate::ser::to_vec(&exact).unwrap(), pdu);
+        }
+
+        #[test]
+        fn round_trip_deep(value in leaf(), depth in 0usize..128) {
+            let value = nest(value, depth);
+            let pdu = crate::ser::to_vec(&value).unwrap();
+            prop_assert_eq!(crate::from_slice::<Value>(&pdu).unwrap(), value);
+        }
+
+        #[test]
+        fn too_deep(value in leaf(), depth in 129usize..256) {
+            let pdu = crate::ser::to_vec(nest(value, depth)).unwrap();
+            prop_assert!(crate::from_slice::<Value>(&pdu).is_err());
+        }
+
+        #[test]
+        fn integer_width(n in boundary_integer()) {
+            let pdu = crate::ser::to_vec(n).unwrap();
+            prop_assert_eq!(crate::from_slice::<i64>(&pdu).unwrap(), n);
+            // The narrowest encoding that holds `n` is chosen.
+            let tag = if i8::try_from(n).is_ok() {
+                BSER_INT8
+            } else if i16::try_from(n).is_ok() {
+                BSER_INT16
+            } else if i32::try_from(n).is_ok() {
+                BSER_INT32
+            } else {
+                BSER_INT64
+            };
+            prop_assert_eq!(super::pdu_body(&pdu)[0], tag);
+        }
+
+        #[test]
+        fn arbitrary_bytes(data in any::<Vec<u8>>()) {
+            // Garbage must be rejected without panicking.
+            let _ = crate::from_slice::<Value>(&data);
+        }
+
+        #[test]
+        fn escaped_string(bytes in any::<Vec<u8>>()) {
+            let escaped = ByteString::from(bytes.clone()).as_escaped_string();
+            if let Ok(s) = std::str::from_utf8(&bytes) {
+                prop_assert_eq!(escaped, s);
+            }
+        }
+    }
+}
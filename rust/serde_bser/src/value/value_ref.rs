@@ -0,0 +1,254 @@
+//! A `Value` that borrows strings from the input it was decoded from.
+
+use std::borrow::Cow;
+use std::str;
+
+use serde::de::{self, Deserialize, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap};
+
+use super::{Map, Value};
+
+/// Like `Value`, but strings and bytestrings borrow from the input where
+/// possible, which avoids copying them when deserializing with `from_slice`.
+/// Object keys borrow too, as long as they are valid UTF-8.
+///
+/// Use `into_owned` to detach the value from the input.
+///
+/// ```
+/// use std::borrow::Cow;
+/// use serde_bser::value::ValueRef;
+///
+/// let pdu = serde_bser::ser::to_vec(vec!["foo.rs", "bar.rs"]).unwrap();
+/// let value: ValueRef = serde_bser::from_slice(&pdu).unwrap();
+/// match &value {
+///     ValueRef::Array(files) => match &files[0] {
+///         ValueRef::Utf8String(Cow::Borrowed(name)) => assert_eq!(*name, "foo.rs"),
+///         _ => panic!("expected a borrowed string"),
+///     },
+///     _ => panic!("expected an array"),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueRef<'a> {
+    Array(Vec<ValueRef<'a>>),
+    Object(Map<Cow<'a, str>, ValueRef<'a>>),
+    ByteString(Cow<'a, [u8]>),
+    Integer(i64),
+    Real(f64),
+    Bool(bool),
+    Null,
+    Utf8String(Cow<'a, str>),
+}
+
+impl<'a> ValueRef<'a> {
+    /// Copy any borrowed data to produce an owned `Value`.
+    pub fn into_owned(self) -> Value {
+        match self {
+            ValueRef::Array(list) => {
+                Value::Array(list.into_iter().map(ValueRef::into_owned).collect())
+            }
+            ValueRef::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                    .collect(),
+            ),
+            ValueRef::ByteString(b) => Value::ByteString(b.into_owned().into()),
+            ValueRef::Integer(n) => Value::Integer(n),
+            ValueRef::Real(n) => Value::Real(n),
+            ValueRef::Bool(b) => Value::Bool(b),
+            ValueRef::Null => Value::Null,
+            ValueRef::Utf8String(s) => Value::Utf8String(s.into_owned()),
+        }
+    }
+
+    /// Returns the contents of a `Utf8String`, or of a `ByteString` that
+    /// holds valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ValueRef::Utf8String(s) => Some(s),
+            ValueRef::ByteString(b) => str::from_utf8(b).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes of either kind of string.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            ValueRef::Utf8String(s) => Some(s.as_bytes()),
+            ValueRef::ByteString(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<ValueRef<'a>> for Value {
+    fn from(value: ValueRef<'a>) -> Self {
+        value.into_owned()
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueRef<'de> {
+    #[inline]
+    fn deserialize<D>(deserializer: D) -> Result<ValueRef<'de>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct ValueRefVisitor;
+
+        impl<'de> Visitor<'de> for ValueRefVisitor {
+            type Value = ValueRef<'de>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("any valid BSER value")
+            }
+
+            #[inline]
+            fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E> {
+                Ok(ValueRef::Bool(value))
+            }
+
+            #[inline]
+            fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E> {
+                Ok(ValueRef::Integer(value))
+            }
+
+            #[inline]
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                if v > (i64::MAX as u64) {
+                    Err(de::Error::custom(format!(
+                        "value {} is too large to represent as a BSER integer",
+                        v
+                    )))
+                } else {
+                    Ok(ValueRef::Integer(v as i64))
+                }
+            }
+
+            #[inline]
+            fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E> {
+                Ok(ValueRef::Real(value))
+            }
+
+            #[inline]
+            fn visit_borrowed_bytes<E>(self, value: &'de [u8]) -> Result<Self::Value, E> {
+                Ok(ValueRef::ByteString(Cow::Borrowed(value)))
+            }
+
+            #[inline]
+            fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E> {
+                Ok(ValueRef::ByteString(Cow::Owned(value.to_vec())))
+            }
+
+            #[inline]
+            fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(ValueRef::ByteString(Cow::Owned(value)))
+            }
+
+            #[inline]
+            fn visit_borrowed_str<E>(self, value: &'de str) -> Result<Self::Value, E> {
+                Ok(ValueRef::Utf8String(Cow::Borrowed(value)))
+            }
+
+            #[inline]
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(ValueRef::Utf8String(Cow::Owned(value.to_string())))
+            }
+
+            #[inline]
+            fn visit_string<E>(self, value: String) -> Result<Self::Value, E> {
+                Ok(ValueRef::Utf8String(Cow::Owned(value)))
+            }
+
+            #[inline]
+            fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                Deserialize::deserialize(deserializer)
+            }
+
+            #[inline]
+            fn visit_none<E>(self) -> Result<Self::Value, E> {
+                Ok(ValueRef::Null)
+            }
+
+            #[inline]
+            fn visit_unit<E>(self) -> Result<Self::Value, E> {
+                Ok(ValueRef::Null)
+            }
+
+            #[inline]
+            fn visit_seq<V>(self, mut visitor: V) -> Result<Self::Value, V::Error>
+            where
+                V: SeqAccess<'de>,
+            {
+                let mut vec = Vec::new();
+
+                while let Some(elem) = visitor.next_element()? {
+                    vec.push(elem);
+                }
+
+                Ok(ValueRef::Array(vec))
+            }
+
+            fn visit_map<V>(self, mut visitor: V) -> Result<Self::Value, V::Error>
+            where
+                V: MapAccess<'de>,
+            {
+                let mut values = Map::new();
+
+                while let Some(key) = visitor.next_key()? {
+                    let key = match key {
+                        ValueRef::Utf8String(key) => key,
+                        ValueRef::ByteString(Cow::Borrowed(key)) => {
+                            Cow::Borrowed(str::from_utf8(key).map_err(de::Error::custom)?)
+                        }
+                        ValueRef::ByteString(Cow::Owned(key)) => {
+                            Cow::Owned(String::from_utf8(key).map_err(de::Error::custom)?)
+                        }
+                        value => {
+                            return Err(de::Error::custom(format!(
+                                "value {:?} is illegal as a key in a BSER map",
+                                value
+                            )))
+                        }
+                    };
+                    values.insert(key, visitor.next_value()?);
+                }
+
+                Ok(ValueRef::Object(values))
+            }
+        }
+
+        deserializer.deserialize_any(ValueRefVisitor)
+    }
+}
+
+impl<'a> Serialize for ValueRef<'a> {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        match *self {
+            ValueRef::Null => serializer.serialize_unit(),
+            ValueRef::Bool(b) => serializer.serialize_bool(b),
+            ValueRef::Integer(n) => serializer.serialize_i64(n),
+            ValueRef::Real(n) => serializer.serialize_f64(n),
+            ValueRef::Utf8String(ref s) => serializer.serialize_str(s),
+            ValueRef::ByteString(ref b) => serializer.serialize_bytes(b),
+            ValueRef::Array(ref v) => v.serialize(serializer),
+            ValueRef::Object(ref m) => {
+                let mut map = serializer.serialize_map(Some(m.len()))?;
+                for (k, v) in m {
+                    map.serialize_key(k)?;
+                    map.serialize_value(v)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Counts the allocations made while encoding and decoding PDUs.  This is
+//! its own test binary so that the counting allocator sees nothing else.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use serde::de::IgnoredAny;
+use serde::Serialize;
+use serde_bser::de::Decoder;
+use serde_bser::ser::Encoder;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The number of allocations made by `f`, on this thread.
+fn allocations<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    let after = ALLOCATIONS.with(Cell::get);
+    drop(result);
+    after - before
+}
+
+#[derive(Serialize)]
+struct Query<'a> {
+    expression: (&'a str, &'a str),
+    fields: &'a [&'a str],
+    relative_root: &'a str,
+}
+
+#[test]
+fn test_session_allocations() {
+    let request = (
+        "query",
+        "/home/user/project",
+        Query {
+            expression: ("suffix", "rs"),
+            fields: &["name", "exists", "size", "mtime", "type"],
+            relative_root: "src",
+        },
+    );
+    let pdu = serde_bser::ser::to_vec(&request).unwrap();
+
+    // Once its buffer has grown to fit, an encoder doesn't allocate.
+    let to_vec_allocations = allocations(|| serde_bser::ser::to_vec(&request).unwrap());
+    assert!(to_vec_allocations > 1);
+    let mut encoder = Encoder::new();
+    encoder.encode(&request).unwrap();
+    assert_eq!(
+        allocations(|| {
+            encoder.encode(&request).unwrap();
+        }),
+        0
+    );
+    // Handing a PDU over as `Bytes` costs it a new buffer, but one that is
+    // allocated at the size needed rather than grown to fit.
+    assert!(allocations(|| encoder.encode_bytes(&request).unwrap()) < to_vec_allocations);
+
+    // Nor does a decoder, beyond what the decoded value itself needs and
+    // the counter for the depth limit, which is set up for each PDU.
+    assert!(allocations(|| serde_bser::from_slice::<IgnoredAny>(&pdu).unwrap()) > 0);
+    let mut decoder = Decoder::new();
+    assert_eq!(
+        allocations(|| decoder.decode::<IgnoredAny>(&pdu).unwrap()),
+        1
+    );
+    let reader_allocations = allocations(|| {
+        serde_bser::from_reader::<_, IgnoredAny>(&pdu[..]).unwrap();
+    });
+    decoder.decode_from::<_, IgnoredAny>(&pdu[..]).unwrap();
+    let decoder_allocations = allocations(|| {
+        decoder.decode_from::<_, IgnoredAny>(&pdu[..]).unwrap();
+    });
+    assert!(decoder_allocations < reader_allocations);
+}
@@ -0,0 +1,77 @@
+//! Runs the `bser-dump` binary on good and malformed PDUs.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn bser_dump(input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bser-dump"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn test_dump() {
+    let mut input = serde_bser::ser::to_vec(("Tom", 42)).unwrap();
+    input.extend(serde_bser::ser::to_vec(vec![true]).unwrap());
+    let output = bser_dump(&input);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "\
+0000  PDU v2, capabilities 0x0, length 11 (BSER_INT8, 1 byte)
+0008  BSER_ARRAY, 2 items (BSER_INT8, 1 byte)
+0011    [0] BSER_UTF8STRING, 3 bytes (BSER_INT8, 1 byte): \"Tom\"
+0017    [1] BSER_INT8, 1 byte: 42
+0019  PDU v2, capabilities 0x0, length 4 (BSER_INT8, 1 byte)
+0027  BSER_ARRAY, 1 item (BSER_INT8, 1 byte)
+0030    [0] BSER_TRUE
+"
+    );
+}
+
+#[test]
+fn test_dump_malformed() {
+    // An unknown type tag
+    let mut input = serde_bser::ser::to_vec(("Tom", 42)).unwrap();
+    input[17] = 0x14;
+    let output = bser_dump(&input);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("error at offset 17: "));
+
+    // A template claiming i64::MAX keys
+    let input = b"\x00\x02\x00\x00\x00\x00\x03\x0d\x0b\x00\x06\xff\xff\xff\xff\xff\xff\xff\x7f";
+    let output = bser_dump(input);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).starts_with("error at offset 19: "));
+
+    // A template with no keys claiming i64::MAX rows
+    let input =
+        b"\x00\x02\x00\x00\x00\x00\x03\x0d\x0b\x00\x03\x00\x06\xff\xff\xff\xff\xff\xff\xff\x7f";
+    let output = bser_dump(input);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).starts_with("error at offset 21: "));
+
+    // Nesting deeper than `DeserializerOptions::max_depth` allows
+    let mut body = [0x00, 0x03, 0x01].repeat(200);
+    body.push(0x0a);
+    let mut input = vec![0x00, 0x01, 0x05];
+    input.extend(&(body.len() as i32).to_le_bytes());
+    input.extend(body);
+    let output = bser_dump(&input);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("recursion limit"));
+
+    // A PDU cut short
+    let input = serde_bser::ser::to_vec(("Tom", 42)).unwrap();
+    let output = bser_dump(&input[..input.len() - 1]);
+    assert_eq!(output.status.code(), Some(1));
+}
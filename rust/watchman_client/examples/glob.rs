@@ -1,4 +1,3 @@
-use serde::Deserialize;
 use std::path::PathBuf;
 use structopt::StructOpt;
 use watchman_client::prelude::*;
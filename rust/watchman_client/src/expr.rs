@@ -1,7 +1,7 @@
 //! Working with the watchman expression term syntax
 use crate::pdu::*;
-use maplit::hashmap;
 use serde::Serialize;
+use serde_bser::bser;
 use serde_bser::value::Value;
 use std::convert::TryInto;
 use std::path::PathBuf;
@@ -77,32 +77,32 @@ pub enum Expr {
     FileType(FileType),
 }
 
-impl Into<Value> for Expr {
-    fn into(self) -> Value {
-        match self {
-            Self::True => "true".into(),
-            Self::False => "false".into(),
-            Self::Not(expr) => Value::Array(vec!["not".into(), (*expr).into()]),
-            Self::All(expr) => {
+impl From<Expr> for Value {
+    fn from(expr: Expr) -> Self {
+        match expr {
+            Expr::True => "true".into(),
+            Expr::False => "false".into(),
+            Expr::Not(expr) => Value::Array(vec!["not".into(), (*expr).into()]),
+            Expr::All(expr) => {
                 let mut expr: Vec<Value> = expr.into_iter().map(Into::into).collect();
                 expr.insert(0, "allof".into());
                 Value::Array(expr)
             }
-            Self::Any(expr) => {
+            Expr::Any(expr) => {
                 let mut expr: Vec<Value> = expr.into_iter().map(Into::into).collect();
                 expr.insert(0, "anyof".into());
                 Value::Array(expr)
             }
-            Self::DirName(term) => {
+            Expr::DirName(term) => {
                 let mut expr: Vec<Value> = vec!["dirname".into(), term.path.try_into().unwrap()];
                 if let Some(depth) = term.depth {
                     expr.push(depth.into_term("depth"));
                 }
                 expr.into()
             }
-            Self::Empty => "empty".into(),
-            Self::Exists => "exists".into(),
-            Self::Match(term) => vec![
+            Expr::Empty => "empty".into(),
+            Expr::Exists => "exists".into(),
+            Expr::Match(term) => vec![
                 "match".into(),
                 term.glob.into(),
                 if term.wholename {
@@ -111,13 +111,13 @@ impl Into<Value> for Expr {
                     "basename"
                 }
                 .into(),
-                Value::Object(hashmap! {
-                    "includedotfiles".to_string() => term.include_dot_files.into(),
-                    "noescape".to_string() => term.no_escape.into()
+                bser!({
+                    "includedotfiles": term.include_dot_files,
+                    "noescape": term.no_escape,
                 }),
             ]
             .into(),
-            Self::Name(term) => vec![
+            Expr::Name(term) => vec![
                 "name".into(),
                 Value::Array(
                     term.paths
@@ -133,7 +133,7 @@ impl Into<Value> for Expr {
                 .into(),
             ]
             .into(),
-            Self::Pcre(term) => vec![
+            Expr::Pcre(term) => vec![
                 "pcre".into(),
                 term.pattern.into(),
                 if term.wholename {
@@ -144,7 +144,7 @@ impl Into<Value> for Expr {
                 .into(),
             ]
             .into(),
-            Self::Since(term) => match term {
+            Expr::Since(term) => match term {
                 SinceTerm::ObservedClock(c) => {
                     vec!["since".into(), c.into(), "oclock".into()].into()
                 }
@@ -158,13 +158,13 @@ impl Into<Value> for Expr {
                     vec!["since".into(), c.to_string().into(), "ctime".into()].into()
                 }
             },
-            Self::Size(term) => term.into_term("size"),
-            Self::Suffix(term) => vec![
+            Expr::Size(term) => term.into_term("size"),
+            Expr::Suffix(term) => vec![
                 "suffix".into(),
                 Value::Array(term.into_iter().map(|p| p.try_into().unwrap()).collect()),
             ]
             .into(),
-            Self::FileType(term) => vec!["type".into(), term.to_string().into()].into(),
+            Expr::FileType(term) => vec!["type".into(), term.to_string().into()].into(),
         }
     }
 }
@@ -285,21 +285,21 @@ mod tests {
 
     #[test]
     fn exprs() {
-        assert_eq!(val(Expr::True), "true".into());
-        assert_eq!(val(Expr::False), "false".into());
-        assert_eq!(val(Expr::Empty), "empty".into());
-        assert_eq!(val(Expr::Exists), "exists".into());
+        assert_eq!(val(Expr::True), bser!("true"));
+        assert_eq!(val(Expr::False), bser!("false"));
+        assert_eq!(val(Expr::Empty), bser!("empty"));
+        assert_eq!(val(Expr::Exists), bser!("exists"));
         assert_eq!(
             val(Expr::Not(Box::new(Expr::False))),
-            vec!["not".into(), "false".into()].into()
+            bser!(["not", "false"])
         );
         assert_eq!(
             val(Expr::All(vec![Expr::True, Expr::False])),
-            vec!["allof".into(), "true".into(), "false".into()].into()
+            bser!(["allof", "true", "false"])
         );
         assert_eq!(
             val(Expr::Any(vec![Expr::True, Expr::False])),
-            vec!["anyof".into(), "true".into(), "false".into()].into()
+            bser!(["anyof", "true", "false"])
         );
 
         assert_eq!(
@@ -307,19 +307,14 @@ mod tests {
                 path: "foo".into(),
                 depth: None,
             })),
-            vec!["dirname".into(), Value::ByteString("foo".into())].into()
+            bser!(["dirname", b"foo"])
         );
         assert_eq!(
             val(Expr::DirName(DirNameTerm {
                 path: "foo".into(),
                 depth: Some(RelOp::GreaterOrEqual(1)),
             })),
-            vec![
-                "dirname".into(),
-                Value::ByteString("foo".into()),
-                vec!["depth".into(), "ge".into(), 1.into()].into()
-            ]
-            .into()
+            bser!(["dirname", b"foo", ["depth", "ge", 1]])
         );
 
         assert_eq!(
@@ -327,17 +322,12 @@ mod tests {
                 glob: "*.txt".into(),
                 ..Default::default()
             })),
-            vec![
-                "match".into(),
-                "*.txt".into(),
-                "basename".into(),
-                hashmap! {
-                    "includedotfiles".to_string() => Value::Bool(false),
-                    "noescape".to_string() => Value::Bool(false),
-                }
-                .into()
-            ]
-            .into()
+            bser!([
+                "match",
+                "*.txt",
+                "basename",
+                {"includedotfiles": false, "noescape": false}
+            ])
         );
 
         assert_eq!(
@@ -347,17 +337,12 @@ mod tests {
                 include_dot_files: true,
                 ..Default::default()
             })),
-            vec![
-                "match".into(),
-                "*.txt".into(),
-                "wholename".into(),
-                hashmap! {
-                    "includedotfiles".to_string() => Value::Bool(true),
-                    "noescape".to_string() => Value::Bool(false),
-                }
-                .into()
-            ]
-            .into()
+            bser!([
+                "match",
+                "*.txt",
+                "wholename",
+                {"includedotfiles": true, "noescape": false}
+            ])
         );
 
         assert_eq!(
@@ -365,12 +350,7 @@ mod tests {
                 paths: vec!["foo".into()],
                 wholename: true,
             })),
-            vec![
-                "name".into(),
-                vec![Value::ByteString("foo".into())].into(),
-                "wholename".into()
-            ]
-            .into()
+            bser!(["name", [b"foo"], "wholename"])
         );
 
         assert_eq!(
@@ -378,30 +358,19 @@ mod tests {
                 pattern: "foo$".into(),
                 wholename: true,
             })),
-            vec!["pcre".into(), "foo$".into(), "wholename".into()].into()
+            bser!(["pcre", "foo$", "wholename"])
         );
 
-        assert_eq!(
-            val(Expr::FileType(FileType::Regular)),
-            vec!["type".into(), "f".into()].into()
-        );
+        assert_eq!(val(Expr::FileType(FileType::Regular)), bser!(["type", "f"]));
 
         assert_eq!(
             val(Expr::Suffix(vec!["php".into(), "js".into()])),
-            vec![
-                "suffix".into(),
-                vec![
-                    Value::ByteString("php".into()),
-                    Value::ByteString("js".into())
-                ]
-                .into()
-            ]
-            .into()
+            bser!(["suffix", [b"php", b"js"]])
         );
 
         assert_eq!(
             val(Expr::Since(SinceTerm::ObservedClock(ClockSpec::null()))),
-            vec!["since".into(), "c:0:0".into(), "oclock".into()].into()
+            bser!(["since", "c:0:0", "oclock"])
         );
     }
 }
@@ -4,14 +4,137 @@
 #![allow(deprecated)]
 
 use crate::prelude::*;
-use serde::Deserialize;
+use serde::de::{DeserializeSeed, Deserializer, Error, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize};
+use serde_bser::bytestring::BytesString;
+use std::marker::PhantomData;
 use std::path::PathBuf;
 
 /// This trait is used to furnish the caller with the watchman
 /// field name for an entry in the file results
 #[doc(hidden)]
 pub trait QueryFieldName {
-    fn field_name() -> &'static str;
+    const NAME: &'static str;
+
+    fn field_name() -> &'static str {
+        Self::NAME
+    }
+
+    /// Deserialize the field from its value alone, rather than from
+    /// an object holding the value under the field name.
+    fn deserialize_value<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        Self: Sized,
+        D: Deserializer<'de>;
+}
+
+/// Identifies which of `fields` a result key refers to, either by
+/// name or by index.  Used by the code that `query_result_type!`
+/// generates.
+#[doc(hidden)]
+pub struct FieldSeed(pub &'static [&'static str]);
+
+impl<'de> DeserializeSeed<'de> for FieldSeed {
+    type Value = Option<usize>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_identifier(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FieldSeed {
+    type Value = Option<usize>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a field name")
+    }
+
+    fn visit_u64<E>(self, index: u64) -> Result<Self::Value, E> {
+        Ok(Some(index as usize).filter(|index| *index < self.0.len()))
+    }
+
+    fn visit_str<E>(self, name: &str) -> Result<Self::Value, E> {
+        Ok(self.0.iter().position(|field| *field == name))
+    }
+
+    fn visit_bytes<E>(self, name: &[u8]) -> Result<Self::Value, E> {
+        Ok(self.0.iter().position(|field| field.as_bytes() == name))
+    }
+}
+
+/// Passed as the name of the structs that `query_result_type!` defines, so
+/// that their fields are identified by index in query results.
+#[doc(hidden)]
+pub use serde_bser::de::FIELD_INDICES_STRUCT;
+
+/// Deserializes a field from its value.  Used by the code that
+/// `query_result_type!` generates.
+#[doc(hidden)]
+pub struct ValueSeed<T>(PhantomData<T>);
+
+impl<T> Default for ValueSeed<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<'de, T> DeserializeSeed<'de> for ValueSeed<T>
+where
+    T: QueryFieldName,
+{
+    type Value = T;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize_value(deserializer)
+    }
+}
+
+/// Produces a field that is missing from a result, the way that serde's
+/// derived impls do: fields that hold an `Option` are `None`, and any
+/// other field is an error.  Used by the code that `query_result_type!`
+/// generates.
+#[doc(hidden)]
+pub fn missing_field<T, E>() -> Result<T, E>
+where
+    T: QueryFieldName,
+    E: Error,
+{
+    struct MissingFieldDeserializer<E>(&'static str, PhantomData<E>);
+
+    impl<'de, E> Deserializer<'de> for MissingFieldDeserializer<E>
+    where
+        E: Error,
+    {
+        type Error = E;
+
+        fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, E>
+        where
+            V: Visitor<'de>,
+        {
+            Err(E::missing_field(self.0))
+        }
+
+        fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, E>
+        where
+            V: Visitor<'de>,
+        {
+            visitor.visit_none()
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf unit unit_struct newtype_struct seq tuple
+            tuple_struct map struct enum identifier ignored_any
+        }
+    }
+
+    T::deserialize_value(MissingFieldDeserializer(T::NAME, PhantomData))
 }
 
 /// This trait is used to produce the complete list of file
@@ -26,17 +149,29 @@ pub trait QueryFieldList {
 /// with the `Client::query` method.
 macro_rules! define_field {(
     $(#[$meta:meta])*
-    $tyname:ident, $ty:ty, $field_name:literal) => {
+    $tyname:ident, $(#[$field_meta:meta])* $ty:ty, $field_name:literal) => {
         #[derive(Deserialize, Clone, Debug)]
         $(#[$meta])*
         pub struct $tyname {
             #[serde(rename = $field_name)]
+            $(#[$field_meta])*
             val: $ty,
         }
 
         impl QueryFieldName for $tyname {
-            fn field_name() -> &'static str {
-                $field_name
+            const NAME: &'static str = $field_name;
+
+            fn deserialize_value<'de, D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                #[derive(Deserialize)]
+                #[serde(transparent)]
+                struct Inner {
+                    $(#[$field_meta])*
+                    val: $ty,
+                }
+                Inner::deserialize(deserializer).map(|inner| Self { val: inner.val })
             }
         }
 
@@ -66,10 +201,21 @@ macro_rules! define_field {(
 define_field!(
     /// The field corresponding to the `name` of the file.
     NameField,
+    #[serde(with = "serde_bser::bytestring::path")]
     PathBuf,
     "name"
 );
 
+define_field!(
+    /// The field corresponding to the `name` of the file, as a
+    /// `BytesString` that shares the memory of the response it was
+    /// received in, rather than as a `PathBuf` of its own.  This makes
+    /// holding on to large results much cheaper.
+    NameBytesField,
+    BytesString,
+    "name"
+);
+
 define_field!(
     /// The field corresponding to the `exists` status of the file
     ExistsField,
@@ -182,6 +328,8 @@ define_field!(
 define_field!(
     /// The field corresponding to the `ino` field.
     /// The ino field is the inode number expressed as an integer.
+    /// The server sends inode numbers above `i64::MAX` as negative
+    /// integers, which decode correctly with `U64Policy::Reinterpret`.
     /// This field is not meaningful on Windows.
     InodeNumberField,
     u64,
@@ -191,6 +339,7 @@ define_field!(
 define_field!(
     /// The field corresponding to the `dev` field.
     /// The dev field is the device number expressed as an integer.
+    /// Like `ino`, large values are sent as negative integers.
     /// This field is not meaningful on Windows.
     DeviceNumberField,
     u64,
@@ -257,7 +406,6 @@ define_field!(
 ///
 /// ```
 /// use watchman_client::prelude::*;
-/// use serde::Deserialize;
 ///
 /// query_result_type! {
 ///     struct NameAndHash {
@@ -284,14 +432,72 @@ macro_rules! query_result_type {(
     }
     ) => (
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Debug, Clone)]
 $struct_vis struct $tyname {
     $(
-        #[serde(flatten)]
         $field_vis $field_name: $field_ty,
     )*
 }
 
+// Each field is decoded straight from its value.  The field list has
+// exactly one name per field, so passing `FIELD_INDICES_STRUCT` as the
+// name lets the deserializer identify fields by index, and the rows of a
+// template don't need to be matched by name.
+impl<'de> ::serde::Deserialize<'de> for $tyname {
+    #[allow(unused_assignments)]
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        const FIELDS: &[&str] = &[
+            $(<$field_ty as $crate::fields::QueryFieldName>::NAME,)*
+        ];
+
+        struct FieldsVisitor;
+
+        impl<'de> ::serde::de::Visitor<'de> for FieldsVisitor {
+            type Value = $tyname;
+
+            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                formatter.write_str(concat!("struct ", stringify!($tyname)))
+            }
+
+            #[allow(unused_assignments)]
+            fn visit_map<A>(self, mut map: A) -> ::std::result::Result<$tyname, A::Error>
+            where
+                A: ::serde::de::MapAccess<'de>,
+            {
+                $(
+                    let mut $field_name: ::std::option::Option<$field_ty> = None;
+                )*
+                while let Some(field) = map.next_key_seed($crate::fields::FieldSeed(FIELDS))? {
+                    let mut index = 0;
+                    $(
+                        if field == Some(index) {
+                            $field_name = Some(map.next_value_seed(
+                                $crate::fields::ValueSeed::<$field_ty>::default(),
+                            )?);
+                            continue;
+                        }
+                        index += 1;
+                    )*
+                    map.next_value::<::serde::de::IgnoredAny>()?;
+                }
+                Ok($tyname {
+                    $(
+                        $field_name: match $field_name {
+                            Some(value) => value,
+                            None => $crate::fields::missing_field::<$field_ty, A::Error>()?,
+                        },
+                    )*
+                })
+            }
+        }
+
+        deserializer.deserialize_struct($crate::fields::FIELD_INDICES_STRUCT, FIELDS, FieldsVisitor)
+    }
+}
+
 impl QueryFieldList for $tyname {
     fn field_list() -> Vec <&'static str> {
          vec![
@@ -308,12 +514,20 @@ impl QueryFieldList for $tyname {
 /// query results consist only of the name field.
 /// It is not possible to use the `query_result_type!` macro to define
 /// an appropriate type due to limitations in the Rust macro system.
-#[derive(Deserialize, Debug, Clone)]
-#[serde(from = "PathBuf")]
+#[derive(Debug, Clone)]
 pub struct NameOnly {
     pub name: NameField,
 }
 
+impl<'de> Deserialize<'de> for NameOnly {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        serde_bser::bytestring::path::deserialize(deserializer).map(Self::from)
+    }
+}
+
 impl QueryFieldList for NameOnly {
     fn field_list() -> Vec<&'static str> {
         vec!["name"]
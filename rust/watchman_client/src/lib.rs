@@ -30,8 +30,12 @@ pub mod expr;
 pub mod fields;
 mod named_pipe;
 pub mod pdu;
-use serde_bser::de::{Bunser, PduInfo, SliceRead};
+use bytes::Bytes;
+use serde_bser::codec::{BserCodec, DEFAULT_MAX_PDU_LEN};
+use serde_bser::de::{Decoder, DeserializerOptions};
+use serde_bser::ser::Encoder;
 use serde_bser::value::Value;
+use serde_bser::U64Policy;
 use std::collections::{HashMap, VecDeque};
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
@@ -42,8 +46,10 @@ use thiserror::Error;
 use tokio::net::UnixStream;
 use tokio::prelude::*;
 use tokio::process::Command;
+use tokio::stream::StreamExt;
 use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender};
 use tokio::sync::Mutex;
+use tokio_util::codec::FramedRead;
 
 /// The next id number to use when generating a subscription name
 static SUB_ID: AtomicUsize = AtomicUsize::new(1);
@@ -87,19 +93,19 @@ pub enum Error {
 
     #[error("{source} (data: {data:x?})")]
     Deserialize {
-        source: Box<dyn std::error::Error + Send>,
+        source: Box<dyn std::error::Error + Send + Sync>,
         data: Vec<u8>,
     },
 
     #[error("{source}")]
     Serialize {
-        source: Box<dyn std::error::Error + Send>,
+        source: Box<dyn std::error::Error + Send + Sync>,
     },
 
     #[error("while attempting to connect to {endpoint}: {source}")]
     Connect {
         endpoint: PathBuf,
-        source: Box<dyn std::error::Error + Send>,
+        source: Box<dyn std::error::Error + Send + Sync>,
     },
 
     #[error("{0}")]
@@ -118,10 +124,24 @@ impl Error {
 /// in situations such as integration testing environments, or in extremely
 /// latency sensitive environments where the cost of performing discovery
 /// is a measurable overhead.
-#[derive(Default)]
 pub struct Connector {
     watchman_cli_path: Option<PathBuf>,
     unix_domain: Option<PathBuf>,
+    decode_options: DeserializerOptions,
+}
+
+impl Default for Connector {
+    fn default() -> Self {
+        Self {
+            watchman_cli_path: None,
+            unix_domain: None,
+            // The server sends inode and device numbers above i64::MAX as
+            // negative integers.
+            decode_options: DeserializerOptions::new()
+                .max_pdu_len(DEFAULT_MAX_PDU_LEN)
+                .u64_policy(U64Policy::Reinterpret),
+        }
+    }
 }
 
 impl Connector {
@@ -154,6 +174,19 @@ impl Connector {
         self
     }
 
+    /// Specify the limits applied when decoding PDUs from the server.
+    /// The PDU size limit is checked before a PDU is buffered, so a
+    /// corrupt length can't cause a huge allocation.
+    ///
+    /// The default options limit PDUs to
+    /// `serde_bser::codec::DEFAULT_MAX_PDU_LEN` bytes, and use
+    /// `U64Policy::Reinterpret`, which is needed to decode large inode and
+    /// device numbers; options passed here should do the same.
+    pub fn decode_options(mut self, options: DeserializerOptions) -> Self {
+        self.decode_options = options;
+        self
+    }
+
     /// Resolve the unix domain socket path, taking either the override
     /// or performing discovery.
     async fn resolve_unix_domain_path(&self) -> Result<PathBuf, Error> {
@@ -167,7 +200,7 @@ impl Connector {
                 .unwrap_or_else(|| Path::new("watchman"));
 
             let output = Command::new(watchman_path)
-                .args(&["--output-encoding", "bser-v2", "get-sockname"])
+                .args(["--output-encoding", "bser-v2", "get-sockname"])
                 .output()
                 .await
                 .map_err(|source| Error::ConnectionDiscovery {
@@ -221,7 +254,10 @@ impl Connector {
         let (request_tx, request_rx) = tokio::sync::mpsc::channel(128);
 
         let mut reader_task = ReaderTask {
-            reader,
+            reader: FramedRead::new(
+                reader,
+                BserCodec::new().decode_options(self.decode_options.clone()),
+            ),
             request_tx: request_tx.clone(),
         };
         tokio::spawn(async move {
@@ -236,6 +272,7 @@ impl Connector {
             request_queue: VecDeque::new(),
             waiting_response: false,
             subscriptions: HashMap::new(),
+            decoder: Decoder::with_options(self.decode_options.clone()),
         };
         tokio::spawn(async move {
             if let Err(err) = task.run().await {
@@ -243,7 +280,11 @@ impl Connector {
             }
         });
 
-        let inner = Arc::new(Mutex::new(ClientInner { request_tx }));
+        let inner = Arc::new(Mutex::new(ClientInner {
+            request_tx,
+            encoder: Encoder::new(),
+            decoder: Decoder::with_options(self.decode_options),
+        }));
 
         Ok(Client { inner })
     }
@@ -349,13 +390,20 @@ impl ReadWriteStream for UnixStream {}
 
 struct SendRequest {
     /// The serialized request to send to the server
-    buf: Vec<u8>,
+    buf: Bytes,
     /// to pass the response back to the requstor
-    tx: tokio::sync::oneshot::Sender<Result<Vec<u8>, String>>,
+    tx: tokio::sync::oneshot::Sender<Result<ResponsePdu, String>>,
+}
+
+/// A response from the server, along with the error message from its
+/// envelope, so that the requestor doesn't need to look for it again.
+struct ResponsePdu {
+    pdu: Bytes,
+    error: Option<String>,
 }
 
 impl SendRequest {
-    fn respond(self, result: Result<Vec<u8>, String>) -> Result<(), Error> {
+    fn respond(self, result: Result<ResponsePdu, String>) -> Result<(), Error> {
         self.tx
             .send(result)
             .map_err(|_| Error::generic("requestor has dropped its receiver"))
@@ -364,8 +412,8 @@ impl SendRequest {
 
 enum TaskItem {
     QueueRequest(SendRequest),
-    ProcessReceivedPdu(Vec<u8>),
-    RegisterSubscription(String, UnboundedSender<Vec<u8>>),
+    ProcessReceivedPdu(Bytes),
+    RegisterSubscription(String, UnboundedSender<Bytes>),
 }
 
 /// A live connection to a watchman server.
@@ -376,68 +424,29 @@ pub struct Client {
 
 /// The reader task lives to read a PDU and send it to the ClientTask
 struct ReaderTask {
-    reader: tokio::io::ReadHalf<Box<dyn ReadWriteStream>>,
+    reader: FramedRead<tokio::io::ReadHalf<Box<dyn ReadWriteStream>>, BserCodec>,
     request_tx: Sender<TaskItem>,
 }
 
 impl ReaderTask {
     async fn run(&mut self) -> Result<(), Error> {
         loop {
-            let pdu = self.read_pdu_vec().await?;
+            let pdu = match self.reader.next().await {
+                Some(pdu) => pdu.map_err(|err| match err.kind() {
+                    serde_bser::ErrorKind::Io(_) => Error::Tokio(err.into()),
+                    _ => Error::Deserialize {
+                        source: Box::new(err),
+                        data: vec![],
+                    },
+                })?,
+                None => return Err(Error::Eof),
+            };
             self.request_tx
                 .send(TaskItem::ProcessReceivedPdu(pdu))
                 .await
                 .map_err(Error::generic)?;
         }
     }
-
-    /// Sniffs out the BSER PDU header to determine the length of data that
-    /// needs to be read in order to decode the full PDU
-    async fn read_bser_pdu_length(&mut self) -> Result<PduHeader, Error> {
-        // We know that the smallest full PDU returned by the server
-        // won't ever be smaller than this size
-        const BUF_SIZE: usize = 16;
-        let mut buf = [0u8; BUF_SIZE];
-
-        let pos = self.reader.read(&mut buf).await?;
-        if pos == 0 {
-            return Err(Error::Eof);
-        }
-
-        let buf = &buf[..pos];
-
-        let mut bunser = Bunser::new(SliceRead::new(buf));
-        let pdu = bunser.read_pdu().map_err(|source| Error::Deserialize {
-            source: Box::new(source),
-            data: buf.to_vec(),
-        })?;
-        let buf = buf.to_vec();
-        Ok(PduHeader { buf, pdu })
-    }
-
-    /// Read the bytes that comprise a BSER encoded PDU
-    async fn read_pdu_vec(&mut self) -> Result<Vec<u8>, Error> {
-        let header = self.read_bser_pdu_length().await?;
-        let total_size = (header.pdu.start + header.pdu.len) as usize;
-        let mut buf = header.buf;
-
-        let mut end = buf.len();
-
-        buf.resize(total_size, 0);
-
-        while end != total_size {
-            let n = self
-                .reader
-                .read(&mut buf.as_mut_slice()[end..total_size])
-                .await?;
-            if n == 0 {
-                return Err(Error::Eof);
-            }
-            end += n;
-        }
-
-        Ok(buf)
-    }
 }
 
 /// The client task coordinates sending requests with processing
@@ -447,7 +456,8 @@ struct ClientTask {
     request_rx: Receiver<TaskItem>,
     request_queue: VecDeque<SendRequest>,
     waiting_response: bool,
-    subscriptions: HashMap<String, UnboundedSender<Vec<u8>>>,
+    subscriptions: HashMap<String, UnboundedSender<Bytes>>,
+    decoder: Decoder,
 }
 
 impl Drop for ClientTask {
@@ -483,7 +493,7 @@ impl ClientTask {
         Ok(())
     }
 
-    fn register_subscription(&mut self, name: String, tx: UnboundedSender<Vec<u8>>) {
+    fn register_subscription(&mut self, name: String, tx: UnboundedSender<Bytes>) {
         self.subscriptions.insert(name, tx);
     }
 
@@ -525,21 +535,31 @@ impl ClientTask {
     }
 
     /// Dispatch a PDU that we just read to the appropriate client code.
-    async fn process_pdu(&mut self, pdu: Vec<u8>) -> Result<(), Error> {
+    async fn process_pdu(&mut self, pdu: Bytes) -> Result<(), Error> {
         use serde::Deserialize;
-        #[derive(Deserialize, Debug)]
-        pub struct Unilateral {
-            pub unilateral: bool,
-            pub subscription: String,
+        /// The fields that tell us where a PDU should go.  Everything else
+        /// is skipped over here, and decoded by its recipient.
+        #[derive(Deserialize, Debug, Default)]
+        struct Envelope {
+            #[serde(default)]
+            unilateral: bool,
+            #[serde(default)]
+            subscription: Option<String>,
+            #[serde(default)]
+            error: Option<String>,
         }
 
-        if let Ok(unilateral) = bunser::<Unilateral>(&pdu) {
-            if let Some(subscription) = self.subscriptions.get_mut(&unilateral.subscription) {
+        // If the envelope can't be decoded, the recipient will report the
+        // problem when it decodes the rest of the PDU.
+        let envelope: Envelope = bunser(&pdu, &mut self.decoder).unwrap_or_default();
+
+        if let (true, Some(name)) = (envelope.unilateral, envelope.subscription) {
+            if let Some(subscription) = self.subscriptions.get_mut(&name) {
                 if subscription.send(pdu).is_err() {
                     // The `Subscription` was dropped; we don't need to
                     // treat this as terminal for this client session,
                     // so just de-register the handler
-                    self.subscriptions.remove(&unilateral.subscription);
+                    self.subscriptions.remove(&name);
                 }
             }
         } else if self.waiting_response {
@@ -549,7 +569,10 @@ impl ClientTask {
                 .expect("waiting_response is only true when request_queue is not empty");
             self.waiting_response = false;
 
-            request.respond(Ok(pdu))?;
+            request.respond(Ok(ResponsePdu {
+                pdu,
+                error: envelope.error,
+            }))?;
         } else {
             // This should never happen as we're not doing any subscription stuff
             return Err(Error::generic("received a unilateral PDU from the server"));
@@ -560,24 +583,28 @@ impl ClientTask {
     }
 }
 
-struct PduHeader {
-    buf: Vec<u8>,
-    pdu: PduInfo,
-}
-
-fn bunser<T>(buf: &[u8]) -> Result<T, Error>
+/// Decode a PDU.  Any `BytesString`s in the result share the PDU's buffer.
+fn bunser<T>(buf: &Bytes, decoder: &mut Decoder) -> Result<T, Error>
 where
     T: serde::de::DeserializeOwned,
 {
-    let response: T = serde_bser::from_slice(&buf).map_err(|source| Error::Deserialize {
-        source: Box::new(source),
-        data: buf.to_vec(),
-    })?;
+    let response: T = decoder
+        .decode_bytes(buf)
+        .map_err(|source| Error::Deserialize {
+            source: Box::new(source),
+            data: buf.to_vec(),
+        })?;
     Ok(response)
 }
 
 struct ClientInner {
     request_tx: Sender<TaskItem>,
+    /// Requests are encoded and responses decoded one at a time.  Each
+    /// encoded request takes its buffer to the client task, and the next
+    /// starts in one allocated at the same size; the decoder reuses its
+    /// buffer.
+    encoder: Encoder,
+    decoder: Decoder,
 }
 
 impl ClientInner {
@@ -595,12 +622,12 @@ impl ClientInner {
         Response: serde::de::DeserializeOwned,
     {
         // Step 1: serialize into a bser byte buffer
-        let mut request_data = vec![];
-        serde_bser::ser::serialize(&mut request_data, &request).map_err(|source| {
-            Error::Serialize {
-                source: Box::new(source),
-            }
-        })?;
+        let request_data =
+            self.encoder
+                .encode_bytes(&request)
+                .map_err(|source| Error::Serialize {
+                    source: Box::new(source),
+                })?;
 
         // Step 2: ask the client task to send it for us
         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -613,26 +640,19 @@ impl ClientInner {
             .map_err(Error::generic)?;
 
         // Step 3: wait for the client task to give us the response
-        let pdu_data = rx.await.map_err(Error::generic)?.map_err(Error::generic)?;
-
-        // Step 4: sniff for an error response in the deserialized data
-        use serde::Deserialize;
-        #[derive(Deserialize, Debug)]
-        struct MaybeError {
-            #[serde(default)]
-            error: Option<String>,
-        }
+        let response = rx.await.map_err(Error::generic)?.map_err(Error::generic)?;
 
-        // Step 5: deserialize into the caller-desired format
-        let maybe_err: MaybeError = bunser(&pdu_data)?;
-        if let Some(message) = maybe_err.error {
+        // Step 4: check for an error response; the client task already
+        // found it while working out where the PDU should go
+        if let Some(message) = response.error {
             return Err(Error::WatchmanServerError {
                 message,
                 command: format!("{:#?}", request),
             });
         }
 
-        let response: Response = bunser(&pdu_data)?;
+        // Step 5: deserialize into the caller-desired format
+        let response: Response = bunser(&response.pdu, &mut self.decoder)?;
         Ok(response)
     }
 }
@@ -702,7 +722,8 @@ where
     name: String,
     inner: Arc<Mutex<ClientInner>>,
     root: ResolvedRoot,
-    responses: UnboundedReceiver<Vec<u8>>,
+    responses: UnboundedReceiver<Bytes>,
+    decoder: Decoder,
     _phantom: PhantomData<F>,
 }
 
@@ -726,7 +747,7 @@ where
             .await
             .ok_or_else(|| Error::generic("client was torn down"))?;
 
-        let response: QueryResult<F> = bunser(&pdu)?;
+        let response: QueryResult<F> = bunser(&pdu, &mut self.decoder)?;
 
         if response.subscription_canceled {
             self.responses.close();
@@ -858,6 +879,7 @@ impl Client {
     /// * [MTimeField](struct.MTimeField.html)
     /// * [ModeAndPermissionsField](struct.ModeAndPermissionsField.html)
     /// * [NameField](struct.NameField.html)
+    /// * [NameBytesField](struct.NameBytesField.html)
     /// * [NewField](struct.NewField.html)
     /// * [NumberOfLinksField](struct.NumberOfLinksField.html)
     /// * [ObservedClockField](struct.ObservedClockField.html)
@@ -934,20 +956,22 @@ impl Client {
 
         let (tx, responses) = tokio::sync::mpsc::unbounded_channel();
 
-        {
+        let decoder = {
             let mut inner = self.inner.lock().await;
             inner
                 .request_tx
                 .send(TaskItem::RegisterSubscription(name.clone(), tx))
                 .await
                 .map_err(Error::generic)?;
-        }
+            Decoder::with_options(inner.decoder.options().clone())
+        };
 
         let subscription = Subscription::<F> {
             name,
             inner: Arc::clone(&self.inner),
             root: root.clone(),
             responses,
+            decoder,
             _phantom: PhantomData,
         };
 
@@ -972,7 +996,7 @@ impl Client {
             .await?;
         Ok(response
             .files
-            .unwrap_or_else(Vec::new)
+            .unwrap_or_default()
             .into_iter()
             .map(|f| f.name.into_inner())
             .collect())
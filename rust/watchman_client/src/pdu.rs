@@ -23,7 +23,11 @@ pub struct ClockResponse {
 
 /// The `clock` command request.
 #[derive(Serialize, Debug)]
-pub struct ClockRequest(pub &'static str, pub PathBuf, pub ClockRequestParams);
+pub struct ClockRequest(
+    pub &'static str,
+    #[serde(with = "serde_bser::bytestring::path")] pub PathBuf,
+    pub ClockRequestParams,
+);
 
 #[derive(Serialize, Debug)]
 pub struct ClockRequestParams {
@@ -35,7 +39,10 @@ pub struct ClockRequestParams {
 /// You should use `Client::resolve_root` rather than directly
 /// constructing this type.
 #[derive(Serialize, Debug)]
-pub struct WatchProjectRequest(pub &'static str, pub PathBuf);
+pub struct WatchProjectRequest(
+    pub &'static str,
+    #[serde(with = "serde_bser::bytestring::path")] pub PathBuf,
+);
 
 /// The `watch-project` response
 #[derive(Deserialize, Debug)]
@@ -44,8 +51,10 @@ pub struct WatchProjectResponse {
     pub version: String,
     /// The path relative to the root of the project; if not none,
     /// this must be passed to QueryRequestCommon::relative_root
+    #[serde(default, with = "serde_bser::bytestring::path::option")]
     pub relative_path: Option<PathBuf>,
     /// The root of the watched project
+    #[serde(with = "serde_bser::bytestring::path")]
     pub watch: PathBuf,
     /// The watcher that the server is using to monitor this path
     pub watcher: String,
@@ -63,17 +72,22 @@ pub enum PathGeneratorElement {
 
 /// The `query` request
 #[derive(Serialize, Clone, Debug)]
-pub struct QueryRequest(pub &'static str, pub PathBuf, pub QueryRequestCommon);
+pub struct QueryRequest(
+    pub &'static str,
+    #[serde(with = "serde_bser::bytestring::path")] pub PathBuf,
+    pub QueryRequestCommon,
+);
 
 #[allow(clippy::trivially_copy_pass_by_ref)]
 fn is_false(v: &bool) -> bool {
     !*v
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, Default)]
 #[serde(into = "i64")]
 pub enum SyncTimeout {
     /// Use the default cookie synchronization timeout
+    #[default]
     Default,
     /// Disable the use of a sync cookie.
     /// This can save ~15ms of latency, but may result in
@@ -90,25 +104,13 @@ pub enum SyncTimeout {
     Duration(std::time::Duration),
 }
 
-impl Default for SyncTimeout {
-    fn default() -> Self {
-        Self::Default
-    }
-}
-
 impl SyncTimeout {
     fn is_default(&self) -> bool {
-        match self {
-            Self::Default => true,
-            _ => false,
-        }
+        matches!(self, Self::Default)
     }
 
     fn is_disabled(&self) -> bool {
-        match self {
-            Self::DisableCookie => true,
-            _ => false,
-        }
+        matches!(self, Self::DisableCookie)
     }
 }
 
@@ -123,9 +125,9 @@ impl From<std::time::Duration> for SyncTimeout {
     }
 }
 
-impl Into<i64> for SyncTimeout {
-    fn into(self) -> i64 {
-        match self {
+impl From<SyncTimeout> for i64 {
+    fn from(timeout: SyncTimeout) -> Self {
+        match timeout {
             // This is only really here because the `ClockRequestParams` PDU
             // treats a missing sync_timeout as `DisableCookie`, whereas
             // the `QueryRequestCommon` PDU treats it as `Default`.
@@ -135,9 +137,9 @@ impl Into<i64> for SyncTimeout {
             // default behavior, we use the current default sync timeout here.
             // We're honestly not likely to change this, so this should be fine.
             // The server uses 1 minute; the value here is expressed in milliseconds.
-            Self::Default => 60_000,
-            Self::DisableCookie => 0,
-            Self::Duration(d) => d.as_millis() as i64,
+            SyncTimeout::Default => 60_000,
+            SyncTimeout::DisableCookie => 0,
+            SyncTimeout::Duration(d) => d.as_millis() as i64,
         }
     }
 }
@@ -211,7 +213,10 @@ pub struct QueryRequestCommon {
     /// filesystem walk.
     /// This field is set automatically from the ResolvedRoot when you perform queries
     /// using Client::query.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        with = "serde_bser::bytestring::path::option"
+    )]
     pub relative_root: Option<PathBuf>,
 
     /// If set, specifies the expression to use to filter the candidate matches
@@ -370,7 +375,10 @@ pub struct SubscribeRequest {
     /// filesystem walk.
     /// This field is set automatically from the ResolvedRoot when you perform queries
     /// using Client::query.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        with = "serde_bser::bytestring::path::option"
+    )]
     pub relative_root: Option<PathBuf>,
 
     /// If set, specifies the expression to use to filter the candidate matches
@@ -410,7 +418,7 @@ pub struct SubscribeRequest {
 #[derive(Serialize, Clone, Debug)]
 pub struct SubscribeCommand(
     pub &'static str,
-    pub PathBuf,
+    #[serde(with = "serde_bser::bytestring::path")] pub PathBuf,
     pub String,
     pub SubscribeRequest,
 );
@@ -420,6 +428,7 @@ pub struct SubscribeCommand(
 #[derive(Deserialize, Debug)]
 pub struct SubscribeResponse {
     pub version: String,
+    #[allow(dead_code)]
     subscribe: String,
 
     /// The clock at initiation time.
@@ -440,7 +449,11 @@ pub struct SubscribeResponse {
 }
 
 #[derive(Serialize, Debug)]
-pub struct Unsubscribe(pub &'static str, pub PathBuf, pub String);
+pub struct Unsubscribe(
+    pub &'static str,
+    #[serde(with = "serde_bser::bytestring::path")] pub PathBuf,
+    pub String,
+);
 
 #[derive(Deserialize, Debug)]
 pub struct UnsubscribeResponse {
@@ -537,11 +550,11 @@ impl ClockSpec {
     }
 }
 
-impl Into<Value> for ClockSpec {
-    fn into(self) -> Value {
-        match self {
-            Self::StringClock(st) => Value::Utf8String(st),
-            Self::UnixTimestamp(ts) => Value::Integer(ts),
+impl From<ClockSpec> for Value {
+    fn from(clock: ClockSpec) -> Self {
+        match clock {
+            ClockSpec::StringClock(st) => Value::Utf8String(st),
+            ClockSpec::UnixTimestamp(ts) => Value::Integer(ts),
         }
     }
 }
@@ -634,9 +647,9 @@ pub enum FileType {
     SolarisDoor,
 }
 
-impl std::string::ToString for FileType {
-    fn to_string(&self) -> String {
-        (*self).into()
+impl std::fmt::Display for FileType {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.write_str(&String::from(*self))
     }
 }
 
@@ -656,17 +669,17 @@ impl From<String> for FileType {
     }
 }
 
-impl Into<String> for FileType {
-    fn into(self) -> String {
-        match self {
-            Self::BlockSpecial => "b",
-            Self::CharSpecial => "c",
-            Self::Directory => "d",
-            Self::Regular => "f",
-            Self::Fifo => "p",
-            Self::Symlink => "l",
-            Self::Socket => "s",
-            Self::SolarisDoor => "D",
+impl From<FileType> for String {
+    fn from(file_type: FileType) -> Self {
+        match file_type {
+            FileType::BlockSpecial => "b",
+            FileType::CharSpecial => "c",
+            FileType::Directory => "d",
+            FileType::Regular => "f",
+            FileType::Fifo => "p",
+            FileType::Symlink => "l",
+            FileType::Socket => "s",
+            FileType::SolarisDoor => "D",
         }
         .to_string()
     }
@@ -675,16 +688,14 @@ impl Into<String> for FileType {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::bunser;
-    use serde_bser::value::Value;
+    use serde_bser::value::{from_value, Value};
     use std::collections::HashMap;
 
     fn convert_bser_value<T>(input: Value) -> T
     where
         T: serde::de::DeserializeOwned,
     {
-        let binary = serde_bser::ser::serialize(Vec::new(), input).unwrap();
-        bunser(&binary).unwrap()
+        from_value(input).unwrap()
     }
 
     #[test]
@@ -716,4 +727,161 @@ mod tests {
         let value: ContentSha1Hex = convert_bser_value(Value::Null);
         assert_eq!(value, ContentSha1Hex::None);
     }
+
+    #[test]
+    fn test_query_result_template() {
+        use crate::prelude::*;
+        use serde_bser::de::DeserializerOptions;
+
+        query_result_type! {
+            struct NameSizeExists {
+                name: NameField,
+                size: SizeField,
+                exists: ExistsField,
+            }
+        }
+
+        #[derive(Serialize)]
+        struct Row {
+            mode: u32,
+            exists: bool,
+            name: &'static str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            size: Option<usize>,
+        }
+
+        let rows = vec![
+            Row {
+                mode: 0o644,
+                exists: true,
+                name: "foo",
+                size: Some(42),
+            },
+            Row {
+                mode: 0o755,
+                exists: false,
+                name: "bar",
+                size: Some(0),
+            },
+        ];
+        let missing = Row {
+            mode: 0o644,
+            exists: true,
+            name: "baz",
+            size: None,
+        };
+        let pdu = serde_bser::ser::to_vec(serde_bser::Templated(&rows)).unwrap();
+        for enabled in [false, true] {
+            let options = DeserializerOptions::new().template_field_indices(enabled);
+            let decoded: Vec<NameSizeExists> =
+                serde_bser::from_slice_with_options(&pdu[..], &options).unwrap();
+            assert_eq!(*decoded[0].name, PathBuf::from("foo"));
+            assert_eq!(*decoded[0].size, 42);
+            assert!(*decoded[0].exists);
+            assert_eq!(*decoded[1].name, PathBuf::from("bar"));
+            assert!(!*decoded[1].exists);
+
+            let pdu = serde_bser::ser::to_vec(&missing).unwrap();
+            assert!(
+                serde_bser::from_slice_with_options::<NameSizeExists>(&pdu[..], &options).is_err()
+            );
+        }
+
+        query_result_type! {
+            struct NameSymlink {
+                name: NameField,
+                symlink_target: SymlinkTargetField,
+            }
+        }
+
+        #[derive(Serialize)]
+        struct LinkRow {
+            name: &'static str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            symlink_target: Option<&'static str>,
+        }
+
+        // Optional fields that a row lacks are `None`
+        let links = vec![
+            LinkRow {
+                name: "link",
+                symlink_target: Some("foo"),
+            },
+            LinkRow {
+                name: "file",
+                symlink_target: None,
+            },
+        ];
+        let links_pdu = serde_bser::ser::to_vec(serde_bser::Templated(&links)).unwrap();
+        for enabled in [false, true] {
+            let options = DeserializerOptions::new().template_field_indices(enabled);
+            let decoded: Vec<NameSymlink> =
+                serde_bser::from_slice_with_options(&links_pdu[..], &options).unwrap();
+            assert_eq!(*decoded[0].symlink_target, Some("foo".to_string()));
+            assert_eq!(*decoded[1].name, PathBuf::from("file"));
+            assert_eq!(*decoded[1].symlink_target, None);
+        }
+
+        query_result_type! {
+            struct SharedNameSize {
+                name: NameBytesField,
+                size: SizeField,
+            }
+        }
+
+        // Names decoded from a `Bytes` buffer share it
+        let pdu = bytes::Bytes::from(pdu);
+        let decoded: Vec<SharedNameSize> = serde_bser::from_bytes(&pdu).unwrap();
+        assert_eq!(
+            decoded[1].name.as_path().unwrap(),
+            std::path::Path::new("bar")
+        );
+        assert_eq!(*decoded[1].size, 0);
+        assert!(pdu.as_ptr_range().contains(&decoded[1].name.as_ptr()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths() {
+        use crate::fields::{NameField, NameOnly};
+        use std::os::unix::ffi::OsStringExt;
+
+        let root = PathBuf::from(std::ffi::OsString::from_vec(b"/repo/caf\xe9".to_vec()));
+        let name = PathBuf::from(std::ffi::OsString::from_vec(b"dir/\xff".to_vec()));
+
+        // Requests encode the root as a bytestring rather than failing
+        let request = QueryRequest(
+            "query",
+            root.clone(),
+            QueryRequestCommon {
+                relative_root: Some(name.clone()),
+                ..Default::default()
+            },
+        );
+        let pdu = serde_bser::ser::to_vec(&request).unwrap();
+        let value: Value = serde_bser::from_slice(&pdu).unwrap();
+        assert_eq!(
+            value[1],
+            Value::ByteString(b"/repo/caf\xe9".to_vec().into())
+        );
+        assert_eq!(
+            value[2]["relative_root"],
+            Value::ByteString(b"dir/\xff".to_vec().into())
+        );
+        let pdu = serde_bser::ser::to_vec(WatchProjectRequest("watch-project", root)).unwrap();
+        let value: Value = serde_bser::from_slice(&pdu).unwrap();
+        assert_eq!(
+            value[1],
+            Value::ByteString(b"/repo/caf\xe9".to_vec().into())
+        );
+
+        // File names in results decode from bytestrings
+        let file: Value = Value::ByteString(b"dir/\xff".to_vec().into());
+        let name_only: NameOnly = convert_bser_value(file.clone());
+        assert_eq!(*name_only.name, name);
+        let mut object = HashMap::new();
+        object.insert("name".to_string(), file);
+        let field: NameField = convert_bser_value(object.into());
+        assert_eq!(field.into_inner(), name);
+    }
 }